@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+use rustix::process::Signal;
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use crate::error::CirnoError;
+use crate::gpu::GpuAllocations;
+use crate::pool::WorkerPool;
+use crate::task::Task;
+
+/// Where the attempts of a task run and how the scheduler learns that they
+/// ended. The scheduler still decides when to launch and preempt, a backend
+/// only carries that out.
+pub trait Backend {
+    /// Get a task ready for this backend as it is submitted.
+    fn prepare(&mut self, _task: &mut Task, _run_dir: &Path) {}
+
+    /// Start a new attempt of `task`, its logs already open.
+    fn spawn(&mut self, task: &mut Task) -> Result<(), CirnoError>;
+
+    /// Whether there is an attempt of `task` to wait for.
+    fn is_running(&self, task: &Task) -> bool;
+
+    /// How the attempt ended, if it did. Once it ran longer than `timeout`
+    /// seconds it gets `signal`, and is stopped if still there `grace`
+    /// later. Must not block.
+    fn try_wait(
+        &mut self,
+        task: &mut Task,
+        timeout: usize,
+        signal: Signal,
+        grace: Duration,
+    ) -> io::Result<Option<ExitStatus>>;
+
+    /// One step of stopping the attempt on shutdown, without waiting. The
+    /// end is reported by `try_wait`.
+    fn stop_step(&mut self, task: &mut Task) -> io::Result<()>;
+
+    /// End the attempt right away.
+    fn kill(&mut self, task: &mut Task) -> io::Result<()>;
+
+    /// Stop the attempt in the background so the caller can move on at
+    /// once, releasing the task's gpus when done.
+    fn stop(&mut self, task: &mut Task, pool: &mut WorkerPool, gpus: &GpuAllocations);
+}
+
+/// The backends `--backend` chooses from.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    /// run the tasks as children of cirno, the default
+    Local,
+    /// create a Kubernetes Job per task through kubectl, experimental
+    Kubernetes,
+}
+
+/// Runs the tasks as children of cirno on this machine.
+pub struct LocalBackend;
+
+impl Backend for LocalBackend {
+    fn prepare(&mut self, task: &mut Task, run_dir: &Path) {
+        task.containerize(run_dir);
+    }
+
+    fn spawn(&mut self, task: &mut Task) -> Result<(), CirnoError> {
+        task.spawn()
+    }
+
+    fn is_running(&self, task: &Task) -> bool {
+        task.child.is_some()
+    }
+
+    fn try_wait(
+        &mut self,
+        task: &mut Task,
+        timeout: usize,
+        signal: Signal,
+        grace: Duration,
+    ) -> io::Result<Option<ExitStatus>> {
+        task.try_wait(timeout, signal, grace)
+    }
+
+    fn stop_step(&mut self, task: &mut Task) -> io::Result<()> {
+        task.stop_step()
+    }
+
+    fn kill(&mut self, task: &mut Task) -> io::Result<()> {
+        task.kill()
+    }
+
+    fn stop(&mut self, task: &mut Task, pool: &mut WorkerPool, gpus: &GpuAllocations) {
+        task.stop_in_background(pool, gpus);
+    }
+}
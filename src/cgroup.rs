@@ -0,0 +1,212 @@
+use rustix::process::{kill_process, Pid, Signal};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Resource limits written into a task's cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CgroupLimits {
+    /// bytes, `memory.max`
+    pub memory_max: Option<u64>,
+    /// bytes, `memory.high`
+    pub memory_high: Option<u64>,
+    /// cores, `cpu.max`
+    pub cpu_max: Option<f64>,
+}
+
+const CPU_PERIOD: u64 = 100000;
+
+/// A cgroup v2 directory owned by one task attempt.
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Enable the memory and cpu controllers for the children of `parent`,
+    /// creating it if needed.
+    pub fn prepare_parent(parent: &Path) -> io::Result<()> {
+        fs::create_dir_all(parent)?;
+        fs::write(parent.join("cgroup.subtree_control"), "+memory +cpu")
+    }
+
+    pub fn create(parent: &Path, name: &str) -> io::Result<Cgroup> {
+        let path = parent.join(name);
+        fs::create_dir_all(&path)?;
+        Ok(Cgroup { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn apply(&self, limits: &CgroupLimits) -> io::Result<()> {
+        if let Some(max) = limits.memory_max {
+            self.write("memory.max", &max.to_string())?;
+        }
+        if let Some(high) = limits.memory_high {
+            self.write("memory.high", &high.to_string())?;
+        }
+        if let Some(cores) = limits.cpu_max {
+            let quota = (cores * CPU_PERIOD as f64) as u64;
+            self.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD))?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(file), value)
+    }
+
+    pub fn read(&self, file: &str) -> io::Result<String> {
+        fs::read_to_string(self.path.join(file))
+    }
+
+    /// Open `cgroup.procs` for the child to write itself into before exec.
+    pub fn open_procs(&self) -> io::Result<fs::File> {
+        fs::OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    /// Every process in the cgroup.
+    pub fn procs(&self) -> Vec<Pid> {
+        self.read("cgroup.procs")
+            .map(|procs| {
+                procs
+                    .lines()
+                    .filter_map(|line| line.trim().parse::<i32>().ok())
+                    .filter_map(Pid::from_raw)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Send `sig` to every process in the cgroup. Membership comes from the
+    /// kernel, so processes that were reparented or daemonized are included.
+    pub fn signal(&self, sig: Signal) -> io::Result<()> {
+        for pid in self.procs() {
+            match kill_process(pid, sig) {
+                Ok(()) | Err(rustix::io::Errno::SRCH) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// SIGKILL everything in the cgroup at once through `cgroup.kill`, which
+    /// also catches processes forked while the kill is in progress.
+    ///
+    /// Kernels before 5.14 have no `cgroup.kill`, there the processes are
+    /// killed one by one until none are left.
+    pub fn kill(&self) -> io::Result<()> {
+        if self.write("cgroup.kill", "1").is_ok() {
+            return Ok(());
+        }
+        for _ in 0..10 {
+            if self.procs().is_empty() {
+                break;
+            }
+            self.signal(Signal::Kill)?;
+        }
+        Ok(())
+    }
+
+    /// Memory charged to the cgroup in KiB.
+    pub fn memory_current(&self) -> Option<u64> {
+        let bytes: u64 = self.read("memory.current").ok()?.trim().parse().ok()?;
+        Some(bytes / 1024)
+    }
+
+    /// Processes killed by the OOM killer because of the cgroup's limits,
+    /// from `memory.events`.
+    pub fn oom_kills(&self) -> Option<u64> {
+        self.read("memory.events").ok()?.lines().find_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            if key == "oom_kill" {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Remove the cgroup, which only works once it has no processes.
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_dir(&self.path)
+    }
+}
+
+/// Turn a task name into something usable as a cgroup directory name.
+pub fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name.trim_start_matches('.').to_string()
+}
+
+/// Parse a size like `512M`, `4G` or a plain number of bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {}", s))?;
+    let unit = unit.to_ascii_uppercase();
+    let unit = unit.strip_suffix('B').unwrap_or(&unit);
+    let scale: u64 = match unit.strip_suffix('I').unwrap_or(unit) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit: {}", s)),
+    };
+    let bytes = number * scale as f64;
+    // the cast would saturate instead
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size too large: {}", s));
+    }
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_scales_by_unit() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("1k"), Ok(1024));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size(" 4G "), Ok(4 << 30));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_size("2TB"), Ok(2 << 40));
+        assert_eq!(parse_size("10B"), Ok(10));
+    }
+
+    #[test]
+    fn parse_size_rejects_bad_units() {
+        for s in [
+            "", "G", "4X", "4 G", "4GBB", "4GIBI", "4MiG", "-1G", "1.2.3M",
+        ] {
+            assert!(parse_size(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_size_rejects_what_overflows() {
+        assert_eq!(parse_size("16777215T"), Ok(16777215 << 40));
+        assert!(parse_size("16777216T").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("99999999999999999999999G").is_err());
+    }
+}
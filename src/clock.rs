@@ -0,0 +1,231 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch.
+pub fn unix_time(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs_f64()
+}
+
+pub fn unix_now() -> f64 {
+    unix_time(SystemTime::now())
+}
+
+// Howard Hinnant's days-from-civil algorithms, proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Format a unix timestamp as UTC `YYYY-MM-DD<sep>HH:MM:SS`, with `time_sep`
+/// between the time fields.
+pub fn format_utc(ts: f64, sep: char, time_sep: char) -> String {
+    let secs = ts.floor() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}{}{:02}{}{:02}{}{:02}",
+        year,
+        month,
+        day,
+        sep,
+        rem / 3600,
+        time_sep,
+        rem % 3600 / 60,
+        time_sep,
+        rem % 60
+    )
+}
+
+/// Format a unix timestamp as in a mail header, e.g.
+/// `Sat, 01 Jun 2024 12:00:00 +0000`.
+pub fn format_rfc2822(ts: f64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = ts.floor() as i64;
+    let days = secs.div_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let time = &format_utc(ts, ' ', ':')[11..];
+    format!(
+        "{}, {:02} {} {:04} {} +0000",
+        // the epoch was a Thursday
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time
+    )
+}
+
+/// Format `secs` seconds as `HH:MM:SS`, with the days in front as `D-`
+/// once there are any.
+pub fn format_elapsed(secs: u64) -> String {
+    let (days, rest) = (secs / 86400, secs % 86400);
+    let time = format!("{:02}:{:02}:{:02}", rest / 3600, rest / 60 % 60, rest % 60);
+    if days > 0 {
+        format!("{}-{}", days, time)
+    } else {
+        time
+    }
+}
+
+/// Parse a duration such as `90`, `30s`, `15m`, `12h`, `7d` or `2w` into seconds.
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number: f64 = number.parse().ok()?;
+    let scale = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        "w" => 604800.0,
+        _ => return None,
+    };
+    Some(number * scale)
+}
+
+/// Parse a check interval like `10`, `0.5` or `1m`, which has to be positive.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    parse_duration(s)
+        .filter(|secs| *secs > 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or(format!("invalid interval: {}", s))
+}
+
+/// Parse a grace period like `30`, `1.5` or `2m`.
+pub fn parse_grace(s: &str) -> Result<Duration, String> {
+    parse_duration(s)
+        .filter(|secs| *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or(format!("invalid grace period: {}", s))
+}
+
+/// Parse a UTC date `YYYY-MM-DD`, optionally followed by `THH:MM[:SS]` or
+/// ` HH:MM[:SS]`, into a unix timestamp.
+pub fn parse_datetime(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let mut date = date.split('-').map(|p| p.parse::<i64>());
+    let year = date.next()?.ok()?;
+    let month = date.next()?.ok()?;
+    let day = date.next()?.ok()?;
+    if date.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut secs = days_from_civil(year, month, day) * 86400;
+    if let Some(time) = time {
+        let mut fields = time.split(':').map(|p| p.parse::<i64>());
+        let hour = fields.next()?.ok()?;
+        let minute = fields.next()?.ok()?;
+        let second = match fields.next() {
+            Some(second) => second.ok()?,
+            None => 0,
+        };
+        let valid =
+            (0..24).contains(&hour) && (0..60).contains(&minute) && (0..60).contains(&second);
+        if fields.next().is_some() || !valid {
+            return None;
+        }
+        secs += hour * 3600 + minute * 60 + second;
+    }
+    Some(secs as f64)
+}
+
+/// Parse a point in time given either as an absolute UTC date or as a
+/// duration back from `now`.
+pub fn parse_since(s: &str, now: f64) -> Option<f64> {
+    parse_datetime(s).or_else(|| parse_duration(s).map(|d| now - d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_scales_by_unit() {
+        assert_eq!(parse_duration("90"), Some(90.0));
+        assert_eq!(parse_duration(" 30s "), Some(30.0));
+        assert_eq!(parse_duration("1.5m"), Some(90.0));
+        assert_eq!(parse_duration("12h"), Some(43200.0));
+        assert_eq!(parse_duration("7d"), Some(604800.0));
+        assert_eq!(parse_duration("2w"), Some(1209600.0));
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for s in ["", "s", "10x", "10 m", "-5", "1.2.3", "1e3", "5ms"] {
+            assert_eq!(parse_duration(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_datetime_reads_a_date_and_an_optional_time() {
+        assert_eq!(parse_datetime("1970-01-01"), Some(0.0));
+        assert_eq!(parse_datetime("2024-06-01"), Some(1717200000.0));
+        assert_eq!(parse_datetime("2024-06-01T12:30"), Some(1717245000.0));
+        assert_eq!(parse_datetime("2024-06-01 12:30:15"), Some(1717245015.0));
+        // leap day
+        assert_eq!(parse_datetime("2024-02-29"), Some(1709164800.0));
+        assert_eq!(parse_datetime("1969-12-31T23:59:59"), Some(-1.0));
+    }
+
+    #[test]
+    fn parse_datetime_rejects_out_of_range_fields() {
+        for s in [
+            "2024-13-01",
+            "2024-00-01",
+            "2024-06-32",
+            "2024-06",
+            "2024-06-01-02",
+            "2024-06-01T24:00",
+            "2024-06-01T12:60",
+            "2024-06-01T12:00:60",
+            "2024-06-01T12:00:00:00",
+            "2024-06-01T12",
+            "yesterday",
+        ] {
+            assert_eq!(parse_datetime(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn format_utc_inverts_parse_datetime() {
+        assert_eq!(format_utc(0.0, 'T', ':'), "1970-01-01T00:00:00");
+        assert_eq!(format_utc(1717245015.9, ' ', ':'), "2024-06-01 12:30:15");
+        assert_eq!(format_utc(1709164800.0, '_', '-'), "2024-02-29_00-00-00");
+        assert_eq!(format_utc(-1.0, 'T', ':'), "1969-12-31T23:59:59");
+        for s in [
+            "2000-02-29T00:00:00",
+            "2100-03-01T23:59:59",
+            "2038-01-19T03:14:08",
+        ] {
+            assert_eq!(format_utc(parse_datetime(s).unwrap(), 'T', ':'), s);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// The config files read on startup, later ones overriding earlier ones:
+/// `$XDG_CONFIG_HOME/cirno/config.toml` (`~/.config` by default), then
+/// `./cirno.toml`.
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("cirno/config.toml"));
+    }
+    paths.push(PathBuf::from("cirno.toml"));
+    paths
+}
+
+/// Read the config file at `path`, empty if there is none.
+pub fn load(path: &Path) -> Result<Table, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+    };
+    contents
+        .parse()
+        .map_err(|e| format!("invalid config file {}: {}", path.display(), e))
+}
+
+/// The `[profile.NAME]` section of `table`, if it has one.
+pub fn profile<'a>(table: &'a Table, name: &str) -> Result<Option<&'a Table>, String> {
+    let profiles = match table.get("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err("profile must be a table of [profile.NAME] sections".to_string()),
+        None => return Ok(None),
+    };
+    match profiles.get(name) {
+        Some(Value::Table(profile)) => Ok(Some(profile)),
+        Some(_) => Err(format!("profile.{} must be a table", name)),
+        None => Ok(None),
+    }
+}
+
+/// The command line flags the settings of `table` stand for, leaving out the
+/// profiles: `max_workers = 8` is `--max-workers=8`, `subreaper = true` is
+/// `--subreaper` and an array repeats the flag for every element.
+pub fn table_args(table: &Table) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        if key == "profile" {
+            continue;
+        }
+        let flag = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            // `--flag=value` keeps values like -5 from looking like flags
+            match value {
+                Value::Boolean(true) => args.push(flag.clone()),
+                Value::Boolean(false) => {}
+                Value::String(s) => args.push(format!("{}={}", flag, s)),
+                Value::Integer(n) => args.push(format!("{}={}", flag, n)),
+                Value::Float(n) => args.push(format!("{}={}", flag, n)),
+                _ => return Err(format!("unsupported value for {}", key)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Table {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn table_args_spells_out_every_setting_as_a_flag() {
+        let config = table(
+            "max_workers = 8\n\
+             load_max = 1.5\n\
+             nice = -5\n\
+             policy = \"strict\"\n\
+             subreaper = true\n\
+             stream = false\n\
+             env = [\"A=1\", \"B=2\"]\n\
+             [profile.gpu]\n\
+             max_workers = 2\n",
+        );
+        let args = table_args(&config).unwrap();
+        let mut expected = vec![
+            "--env=A=1",
+            "--env=B=2",
+            "--load-max=1.5",
+            "--max-workers=8",
+            "--nice=-5",
+            "--policy=strict",
+            "--subreaper",
+        ];
+        expected.sort();
+        let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+        args.sort();
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn table_args_rejects_nested_values() {
+        assert!(table_args(&table("limits = { mem = \"4G\" }")).is_err());
+        assert!(table_args(&table("hosts = [[\"a\"]]")).is_err());
+        assert!(table_args(&table("start = 1979-05-27")).is_err());
+    }
+
+    #[test]
+    fn profile_finds_its_section() {
+        let config = table("max_workers = 8\n[profile.gpu]\nmax_workers = 2\n[profile.night]\n");
+        let gpu = profile(&config, "gpu").unwrap().unwrap();
+        assert_eq!(table_args(gpu).unwrap(), vec!["--max-workers=2"]);
+        assert!(profile(&config, "night").unwrap().unwrap().is_empty());
+        assert!(profile(&config, "day").unwrap().is_none());
+        assert!(profile(&table("max_workers = 8"), "gpu").unwrap().is_none());
+    }
+
+    #[test]
+    fn profile_rejects_what_isnt_a_table() {
+        assert!(profile(&table("profile = \"gpu\""), "gpu").is_err());
+        assert!(profile(&table("[profile]\ngpu = 2"), "gpu").is_err());
+        // only the profile asked for has to be well formed
+        assert!(profile(&table("[profile]\ngpu = 2\n[profile.cpu]"), "cpu")
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,244 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cgroup::sanitize_name;
+
+/// The container runtimes a task can run in, set with `@runtime=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    pub fn parse(s: &str) -> Result<Runtime, String> {
+        match s {
+            "docker" => Ok(Runtime::Docker),
+            "podman" => Ok(Runtime::Podman),
+            _ => Err(format!("unknown container runtime: {}", s)),
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+}
+
+/// The container a task runs in. The client of the runtime is the child of
+/// cirno and passes signals on to the container, but the processes in it
+/// belong to the runtime, so killing, pausing and resuming them goes
+/// through the runtime as well.
+#[derive(Debug, Clone)]
+pub(crate) struct Container {
+    runtime: Runtime,
+    name: String,
+}
+
+impl Container {
+    /// A container for the task called `task`, with a name no other task
+    /// of any cirno on the machine has.
+    pub(crate) fn new(runtime: Runtime, task: &str) -> Container {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        Container {
+            runtime,
+            name: format!(
+                "cirno-{}-{}-{}",
+                sanitize_name(task),
+                std::process::id(),
+                count
+            ),
+        }
+    }
+
+    /// `command` run in `image` by the runtime instead. The working
+    /// directory and the run directory are mounted at the same paths, and
+    /// the environment set for the task, as well as the variables cirno sets
+    /// at launch, is passed on.
+    pub(crate) fn wrap(
+        &self,
+        command: &Command,
+        image: &str,
+        run_dir: &Path,
+        gpus: bool,
+    ) -> Command {
+        let cwd = command
+            .get_current_dir()
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let cwd = std::path::absolute(&cwd).unwrap_or(cwd);
+        let run_dir = std::path::absolute(run_dir).unwrap_or(run_dir.to_path_buf());
+        let mut wrapped = Command::new(self.runtime.program());
+        wrapped.args(["run", "--rm", "--init", "--name", &self.name]);
+        for dir in [&cwd, &run_dir] {
+            wrapped
+                .arg("--volume")
+                .arg(format!("{}:{}", dir.display(), dir.display()));
+        }
+        wrapped.arg("--workdir").arg(&cwd);
+        if gpus {
+            wrapped.args(["--gpus", "all"]);
+        }
+        for (key, value) in command.get_envs() {
+            if let Some(value) = value {
+                wrapped.env(key, value);
+            }
+            wrapped.arg("--env").arg(key);
+        }
+        for key in ["CUDA_VISIBLE_DEVICES", "TRACEPARENT"] {
+            wrapped.args(["--env", key]);
+        }
+        wrapped
+            .arg(image)
+            .arg(command.get_program())
+            .args(command.get_args());
+        if let Some(dir) = command.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        wrapped
+    }
+
+    /// Kill and remove the container in the background, the runtime only
+    /// answers once it is gone.
+    pub(crate) fn remove(&self) {
+        let mut command = self.runtime_command(&["rm", "--force"]);
+        std::thread::spawn(move || {
+            let _ = command.status();
+        });
+    }
+
+    /// Remove what a previous attempt left, so the name is free again.
+    pub(crate) fn clear(&self) {
+        let _ = self.runtime_command(&["rm", "--force"]).status();
+    }
+
+    pub(crate) fn pause(&self) -> std::io::Result<()> {
+        self.run(&["pause"])
+    }
+
+    pub(crate) fn unpause(&self) -> std::io::Result<()> {
+        self.run(&["unpause"])
+    }
+
+    fn run(&self, args: &[&str]) -> std::io::Result<()> {
+        let status = self.runtime_command(args).status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "{} {} {} failed with {}",
+                self.runtime.program(),
+                args.join(" "),
+                self.name,
+                status
+            )));
+        }
+        Ok(())
+    }
+
+    fn runtime_command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(self.runtime.program());
+        command
+            .args(args)
+            .arg(&self.name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn container() -> Container {
+        Container {
+            runtime: Runtime::Podman,
+            name: "cirno-train-1-0".to_string(),
+        }
+    }
+
+    #[test]
+    fn wrap_runs_the_command_in_the_image() {
+        let mut command = Command::new("python");
+        command
+            .args(["train.py", "--note", "it's"])
+            .env("SEED", "1")
+            .current_dir("/data/work");
+        let wrapped = container().wrap(&command, "pytorch:2", Path::new("/data/run"), false);
+        assert_eq!(wrapped.get_program(), "podman");
+        assert_eq!(
+            args(&wrapped),
+            [
+                "run",
+                "--rm",
+                "--init",
+                "--name",
+                "cirno-train-1-0",
+                "--volume",
+                "/data/work:/data/work",
+                "--volume",
+                "/data/run:/data/run",
+                "--workdir",
+                "/data/work",
+                "--env",
+                "SEED",
+                "--env",
+                "CUDA_VISIBLE_DEVICES",
+                "--env",
+                "TRACEPARENT",
+                "pytorch:2",
+                "python",
+                "train.py",
+                "--note",
+                "it's",
+            ]
+        );
+        // the values reach the container through the client's environment
+        let envs: Vec<_> = wrapped.get_envs().collect();
+        assert_eq!(envs, [(OsStr::new("SEED"), Some(OsStr::new("1")))]);
+        assert_eq!(wrapped.get_current_dir(), Some(Path::new("/data/work")));
+    }
+
+    #[test]
+    fn wrap_hands_the_gpus_over_when_asked_to() {
+        let mut command = Command::new("nvidia-smi");
+        command.current_dir("/data/work");
+        let wrapped = container().wrap(&command, "cuda", Path::new("/data/run"), true);
+        let args = args(&wrapped);
+        let workdir = args.iter().position(|arg| arg == "--workdir").unwrap();
+        assert_eq!(args[workdir + 2..workdir + 4], ["--gpus", "all"]);
+        assert_eq!(args[args.len() - 2..], ["cuda", "nvidia-smi"]);
+    }
+
+    #[test]
+    fn new_names_the_container_after_the_task() {
+        let first = Container::new(Runtime::Docker, "a b");
+        let second = Container::new(Runtime::Docker, "a b");
+        assert!(first.name.starts_with(&format!(
+            "cirno-{}-{}-",
+            sanitize_name("a b"),
+            std::process::id()
+        )));
+        assert_ne!(first.name, second.name);
+    }
+
+    #[test]
+    fn runtime_parse_takes_docker_and_podman() {
+        assert_eq!(Runtime::parse("docker"), Ok(Runtime::Docker));
+        assert_eq!(Runtime::parse("podman"), Ok(Runtime::Podman));
+        assert!(Runtime::parse("lxc").is_err());
+    }
+}
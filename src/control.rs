@@ -0,0 +1,136 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+/// How long a client gets to send its command once connected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The listening end of the control socket. Every connection carries one
+/// command line and gets the reply, then is closed. Commands are read by
+/// threads of their own, so a slow client doesn't hold up the scheduler.
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+    requests: Receiver<Request>,
+    /// readable while requests are waiting
+    woken: UnixStream,
+}
+
+/// A command read from a client, waiting for its reply.
+#[derive(Debug)]
+pub struct Request {
+    pub line: String,
+    stream: UnixStream,
+}
+
+impl ControlSocket {
+    /// Listen on `path`, replacing a socket left behind by a cirno that is
+    /// gone. Fails if another cirno is still listening there.
+    pub fn bind(path: &Path) -> io::Result<ControlSocket> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another cirno", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, requests) = mpsc::channel();
+        let (wake, woken) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        woken.set_nonblocking(true)?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let sender = sender.clone();
+                let wake = match wake.try_clone() {
+                    Ok(wake) => wake,
+                    Err(_) => continue,
+                };
+                thread::spawn(move || match read_request(stream) {
+                    Ok(Some(request)) => {
+                        // the socket is gone if this fails, and the client with it
+                        if sender.send(request).is_ok() {
+                            let _ = (&wake).write(&[0]);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("failed to read control request: {}", e),
+                });
+            }
+        });
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+            requests,
+            woken,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The commands read since the last call, without blocking.
+    pub fn requests(&self) -> Vec<Request> {
+        let mut buf = [0u8; 64];
+        while matches!((&self.woken).read(&mut buf), Ok(n) if n > 0) {}
+        self.requests.try_iter().collect()
+    }
+}
+
+impl AsRawFd for ControlSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.woken.as_raw_fd()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn read_request(stream: UnixStream) -> io::Result<Option<Request>> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // the reply is written by the scheduler, which can't wait on a client
+    // that doesn't read
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let line = line.trim().to_string();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Request { line, stream }))
+}
+
+impl Request {
+    /// Send `reply` to the client and hang up.
+    pub fn reply(mut self, reply: &str) {
+        let mut reply = reply.to_string();
+        if !reply.ends_with('\n') {
+            reply.push('\n');
+        }
+        // the client may be gone already, nothing to do about it
+        let _ = self.stream.write_all(reply.as_bytes());
+    }
+}
+
+/// Send one command line to the cirno listening on `path` and return its reply.
+pub fn send(path: &Path, line: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{}\n", line).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
@@ -0,0 +1,164 @@
+use sha1_smol::Sha1;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::http::HttpRequest;
+
+/// The dashboard page, polling the queue state and tailing logs.
+pub const PAGE: &str = include_str!("dashboard.html");
+
+/// How much of a log is sent when its tail is opened.
+const TAIL_BYTES: u64 = 64 << 10;
+/// How often a tailed log is looked at for new output.
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether `request` comes from the dashboard page rather than from another
+/// site open in the same browser. The host has to be `localhost` or an IP
+/// address, so a name rebound to it doesn't pass, and the origin browsers
+/// send along has to be that host. With `header`, the request also needs the
+/// header the page sends, which other sites can't send without a preflight
+/// the dashboard never answers.
+pub fn from_page(request: &HttpRequest, header: bool) -> bool {
+    let host = match request.header("host") {
+        Some(host) => host,
+        None => return false,
+    };
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    if name != "localhost" && name.parse::<IpAddr>().is_err() {
+        return false;
+    }
+    if header && request.header("x-cirno-dashboard").is_none() {
+        return false;
+    }
+    request
+        .header("origin")
+        .is_none_or(|origin| origin == format!("http://{}", host))
+}
+
+/// Answer the WebSocket handshake of `request` and send, from a thread of
+/// its own, the end of the log at `path` and whatever is written to it after,
+/// until the client goes away. A log that shrinks was rotated and is read
+/// again from its start.
+pub fn tail_log(request: HttpRequest, path: PathBuf) {
+    let key = match request.header("sec-websocket-key") {
+        Some(key) => key.to_string(),
+        None => {
+            request.respond(400, "text/plain", b"expected a websocket\n");
+            return;
+        }
+    };
+    let mut stream = request.into_stream();
+    // the thread below can wait on the client as long as it takes
+    let _ = stream.set_write_timeout(None);
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    let accept = base64(&sha1.digest().bytes());
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    );
+    if stream.write_all(head.as_bytes()).is_err() {
+        return;
+    }
+    thread::spawn(move || {
+        if let Err(e) = follow(&mut stream, &path) {
+            let _ = send(
+                &mut stream,
+                format!("\n[{}: {}]\n", path.display(), e).as_bytes(),
+            );
+        }
+        // close
+        let _ = stream.write_all(&[0x88, 0]);
+    });
+}
+
+fn follow(stream: &mut TcpStream, path: &PathBuf) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut offset = len.saturating_sub(TAIL_BYTES);
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        let len = match std::fs::metadata(path) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if len < offset {
+            offset = 0;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            send(stream, &buf[..n])?;
+            offset += n as u64;
+        }
+        // a ping gets an error once the client is gone, even without output
+        stream.write_all(&[0x89, 0])?;
+        thread::sleep(TAIL_INTERVAL);
+    }
+}
+
+/// Send `data` as one binary frame.
+fn send(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x82];
+    match data.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(data);
+    stream.write_all(&frame)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().fold(0u32, |n, &b| n << 8 | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_to_whole_quads() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+pub fn free_space(path: &Path) -> std::io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Milliseconds each block device has spent doing I/O, from `/proc/diskstats`.
+///
+/// Loop, ram and zram devices are left out since they are not real disks.
+pub fn read_io_ticks() -> HashMap<String, u64> {
+    let mut ticks = HashMap::new();
+    let contents = match std::fs::read_to_string("/proc/diskstats") {
+        Ok(contents) => contents,
+        Err(_) => return ticks,
+    };
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 13 {
+            continue;
+        }
+        let name = fields[2];
+        if ["loop", "ram", "zram"].iter().any(|p| name.starts_with(p)) {
+            continue;
+        }
+        if let Ok(io_ticks) = fields[12].parse() {
+            ticks.insert(name.to_string(), io_ticks);
+        }
+    }
+    ticks
+}
+
+/// Busy percentage of the busiest device between two `read_io_ticks`
+/// samples taken `elapsed_ms` apart, limited to `devices` unless empty.
+pub fn max_utilization(
+    before: &HashMap<String, u64>,
+    after: &HashMap<String, u64>,
+    elapsed_ms: f64,
+    devices: &[String],
+) -> f64 {
+    if elapsed_ms <= 0.0 {
+        return 0.0;
+    }
+    after
+        .iter()
+        .filter(|(name, _)| devices.is_empty() || devices.contains(name))
+        .filter_map(|(name, ticks)| {
+            let busy = ticks.saturating_sub(*before.get(name)?);
+            Some((busy as f64 / elapsed_ms * 100.0).min(100.0))
+        })
+        .fold(0.0, f64::max)
+}
@@ -0,0 +1,26 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// What went wrong in the scheduler, with what it was doing at the time.
+#[derive(Debug, Error)]
+pub enum CirnoError {
+    /// a log file of a task could not be created or opened
+    #[error("failed to open log {}: {source}", path.display())]
+    Log { path: PathBuf, source: io::Error },
+    /// the program of a task could not be started
+    #[error("failed to spawn: {0}")]
+    Spawn(#[source] io::Error),
+    /// the previous attempt of a task would not stop before the next one
+    #[error("failed to stop the previous attempt: {0}")]
+    Stop(#[source] io::Error),
+    /// `/proc` could not be read
+    #[error("failed to list processes: {0}")]
+    Processes(#[source] io::Error),
+    /// the scheduler a `SchedulerHandle` was made for was dropped
+    #[error("the scheduler is gone")]
+    Gone,
+    /// a task list line that doesn't parse
+    #[error("{0}")]
+    TaskLine(String),
+}
@@ -0,0 +1,35 @@
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::clock::unix_now;
+
+/// Scheduler events appended to a file as JSON Lines, one object per event
+/// with its `time` as a unix timestamp and its `event` name.
+#[derive(Debug)]
+pub struct EventLog {
+    file: File,
+}
+
+impl EventLog {
+    /// Append to `path`, keeping the events of earlier runs.
+    pub fn open(path: &Path) -> io::Result<EventLog> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(EventLog { file })
+    }
+
+    /// Write `event` with the fields of `fields`, which should be an object.
+    pub fn write(&mut self, event: &str, fields: Value) -> io::Result<()> {
+        let mut object = Map::new();
+        object.insert("time".to_string(), Value::from(unix_now()));
+        object.insert("event".to_string(), Value::from(event));
+        if let Value::Object(fields) = fields {
+            object.extend(fields);
+        }
+        let mut line = Value::Object(object).to_string();
+        line.push('\n');
+        // one write per event, so a reader never sees half of one
+        self.file.write_all(line.as_bytes())
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// State of one NVIDIA device as reported by `nvidia-smi`.
+#[derive(Debug, Clone, Default)]
+pub struct GpuInfo {
+    pub index: u32,
+    pub uuid: String,
+    /// MiB
+    pub memory_total: u64,
+    pub memory_used: u64,
+    /// percent
+    pub utilization: u32,
+}
+
+impl GpuInfo {
+    pub fn memory_free(&self) -> u64 {
+        self.memory_total.saturating_sub(self.memory_used)
+    }
+}
+
+/// Query every device through `nvidia-smi`.
+pub fn query_gpus() -> std::io::Result<Vec<GpuInfo>> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=index,uuid,memory.total,memory.used,utilization.gpu",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "nvidia-smi failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_gpus(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the csv printed by `query_gpus`, skipping lines it can't read.
+pub fn parse_gpus(csv: &str) -> Vec<GpuInfo> {
+    csv.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            if fields.len() < 5 {
+                return None;
+            }
+            Some(GpuInfo {
+                index: fields[0].parse().ok()?,
+                uuid: fields[1].to_string(),
+                memory_total: fields[2].parse().ok()?,
+                memory_used: fields[3].parse().ok()?,
+                // "[N/A]" on some boards
+                utilization: fields[4].parse().unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/// Pick `count` devices not in `taken` with at least `min_free` MiB free and
+/// at most `max_utilization` percent busy, preferring the emptiest ones.
+pub fn pick_gpus(
+    gpus: &[GpuInfo],
+    taken: &[u32],
+    count: usize,
+    min_free: u64,
+    max_utilization: u32,
+) -> Option<Vec<u32>> {
+    let mut available: Vec<&GpuInfo> = gpus
+        .iter()
+        .filter(|gpu| !taken.contains(&gpu.index))
+        .filter(|gpu| gpu.memory_free() >= min_free && gpu.utilization <= max_utilization)
+        .collect();
+    if available.len() < count {
+        return None;
+    }
+    available.sort_by_key(|gpu| std::cmp::Reverse(gpu.memory_free()));
+    Some(available[..count].iter().map(|gpu| gpu.index).collect())
+}
+
+/// Which task holds which device. Cloning shares the table, so a background
+/// stop can release the devices once the task's tree is really gone.
+#[derive(Debug, Clone, Default)]
+pub struct GpuAllocations {
+    table: Arc<Mutex<HashMap<u32, String>>>,
+}
+
+impl GpuAllocations {
+    pub fn allocate(&self, devices: &[u32], owner: &str) {
+        let mut table = self.table.lock().unwrap();
+        for device in devices {
+            table.insert(*device, owner.to_string());
+        }
+    }
+
+    pub fn release(&self, devices: &[u32]) {
+        let mut table = self.table.lock().unwrap();
+        for device in devices {
+            table.remove(device);
+        }
+    }
+
+    pub fn taken(&self) -> Vec<u32> {
+        self.table.lock().unwrap().keys().copied().collect()
+    }
+
+    pub fn owner(&self, device: u32) -> Option<String> {
+        self.table.lock().unwrap().get(&device).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_gpus_reads_every_device() {
+        let csv = "0, GPU-aaaa, 24576, 1024, 35\n1, GPU-bbbb, 24576, 0, [N/A]\n";
+        let gpus = parse_gpus(csv);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].index, 0);
+        assert_eq!(gpus[0].uuid, "GPU-aaaa");
+        assert_eq!(gpus[0].memory_total, 24576);
+        assert_eq!(gpus[0].memory_free(), 23552);
+        assert_eq!(gpus[0].utilization, 35);
+        assert_eq!(gpus[1].index, 1);
+        assert_eq!(gpus[1].utilization, 0);
+    }
+
+    #[test]
+    fn parse_gpus_skips_lines_it_cant_read() {
+        let csv = "\nNo devices were found\n0, GPU-aaaa, 24576\nx, GPU-bbbb, 24576, 0, 0\n2, GPU-cccc, 8192, 0, 0\n";
+        let gpus = parse_gpus(csv);
+        assert_eq!(gpus.len(), 1);
+        assert_eq!(gpus[0].index, 2);
+    }
+
+    fn gpu(index: u32, memory_used: u64, utilization: u32) -> GpuInfo {
+        GpuInfo {
+            index,
+            uuid: format!("GPU-{}", index),
+            memory_total: 16384,
+            memory_used,
+            utilization,
+        }
+    }
+
+    #[test]
+    fn pick_gpus_prefers_the_emptiest_devices() {
+        let gpus = [gpu(0, 8192, 0), gpu(1, 0, 0), gpu(2, 4096, 0), gpu(3, 0, 0)];
+        assert_eq!(pick_gpus(&gpus, &[], 2, 0, 100), Some(vec![1, 3]));
+        assert_eq!(pick_gpus(&gpus, &[], 3, 0, 100), Some(vec![1, 3, 2]));
+        assert_eq!(pick_gpus(&gpus, &[], 0, 0, 100), Some(vec![]));
+    }
+
+    #[test]
+    fn pick_gpus_leaves_taken_full_and_busy_devices() {
+        let gpus = [gpu(0, 0, 0), gpu(1, 0, 90), gpu(2, 12288, 0), gpu(3, 0, 10)];
+        assert_eq!(pick_gpus(&gpus, &[0], 1, 8192, 50), Some(vec![3]));
+        assert_eq!(pick_gpus(&gpus, &[0], 2, 8192, 50), None);
+        assert_eq!(pick_gpus(&gpus, &[0, 3], 1, 8192, 50), None);
+        assert_eq!(pick_gpus(&[], &[], 1, 0, 100), None);
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+use crate::error::CirnoError;
+use crate::task::Task;
+
+/// What a `SchedulerHandle` asks of the scheduler.
+pub(crate) enum Command {
+    Submit(Box<Task>),
+    Cancel(String),
+    Drain,
+}
+
+/// Submits, cancels and drains from other threads while `Scheduler::do_it`
+/// runs. Cloned handles all reach the same scheduler, and the scheduler
+/// takes their commands as soon as they are sent, even while it waits.
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    sender: Sender<Command>,
+    wake: Arc<UnixStream>,
+}
+
+impl SchedulerHandle {
+    /// Queue `task`, as `Scheduler::submit` does.
+    pub fn submit(&self, task: Task) -> Result<(), CirnoError> {
+        self.send(Command::Submit(Box::new(task)))
+    }
+
+    /// Drop the waiting tasks called `name` and stop the running ones.
+    pub fn cancel(&self, name: &str) -> Result<(), CirnoError> {
+        self.send(Command::Cancel(name.to_string()))
+    }
+
+    /// Launch nothing more and return from `do_it` once the running tasks
+    /// are done.
+    pub fn drain(&self) -> Result<(), CirnoError> {
+        self.send(Command::Drain)
+    }
+
+    fn send(&self, command: Command) -> Result<(), CirnoError> {
+        self.sender.send(command).map_err(|_| CirnoError::Gone)?;
+        // a full socket already wakes the scheduler up
+        let _ = (&*self.wake).write(&[0]);
+        Ok(())
+    }
+}
+
+/// Where the commands of the handles arrive, with a socket that is readable
+/// while any are waiting.
+pub(crate) struct Inbox {
+    sender: Sender<Command>,
+    receiver: Receiver<Command>,
+    wake: Arc<UnixStream>,
+    woken: UnixStream,
+}
+
+impl Inbox {
+    pub(crate) fn new() -> std::io::Result<Inbox> {
+        let (sender, receiver) = mpsc::channel();
+        let (wake, woken) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        woken.set_nonblocking(true)?;
+        Ok(Inbox {
+            sender,
+            receiver,
+            wake: Arc::new(wake),
+            woken,
+        })
+    }
+
+    pub(crate) fn handle(&self) -> SchedulerHandle {
+        SchedulerHandle {
+            sender: self.sender.clone(),
+            wake: self.wake.clone(),
+        }
+    }
+
+    /// The commands sent since the last call.
+    pub(crate) fn commands(&self) -> Vec<Command> {
+        let mut buf = [0u8; 64];
+        while matches!((&self.woken).read(&mut buf), Ok(n) if n > 0) {}
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl AsRawFd for Inbox {
+    fn as_raw_fd(&self) -> RawFd {
+        self.woken.as_raw_fd()
+    }
+}
@@ -0,0 +1,601 @@
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::path::Path;
+
+/// One finished (or abandoned) run of a task.
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    pub name: String,
+    pub command: String,
+    /// unix timestamps in seconds
+    pub start_time: f64,
+    pub end_time: f64,
+    /// `finished`, `preempted`, `interrupted`, `cancelled`, `oom` or `error`
+    pub outcome: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// cpu time in seconds
+    pub user_time: f64,
+    pub sys_time: f64,
+    /// peak resident set size in KiB
+    pub max_rss: u64,
+    /// name of the session the run belonged to
+    pub session: Option<String>,
+}
+
+impl Execution {
+    pub fn duration(&self) -> f64 {
+        self.end_time - self.start_time
+    }
+}
+
+/// Which executions a history query looks at.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// only runs that errored or exited unsuccessfully
+    pub failed: bool,
+    /// only runs started at or after this unix timestamp
+    pub since: Option<f64>,
+    /// SQL `LIKE` pattern matched against the task name
+    pub name_like: Option<String>,
+    /// only runs of the sessions with this name
+    pub session: Option<String>,
+}
+
+impl Filter {
+    fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if self.failed {
+            conditions.push(format!("({})", FAILED));
+        }
+        if let Some(since) = self.since {
+            values.push(Value::Real(since));
+            conditions.push(format!("e.start_time >= ?{}", values.len()));
+        }
+        if let Some(name_like) = &self.name_like {
+            values.push(Value::Text(name_like.clone()));
+            conditions.push(format!("e.name LIKE ?{}", values.len()));
+        }
+        if let Some(session) = &self.session {
+            values.push(Value::Text(session.clone()));
+            conditions.push(format!("s.name = ?{}", values.len()));
+        }
+        if conditions.is_empty() {
+            (String::new(), values)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), values)
+        }
+    }
+}
+
+const FAILED: &str =
+    "e.outcome IN ('error', 'oom') OR (e.outcome = 'finished' AND e.exit_code IS NOT 0)";
+
+/// Aggregated history of one task name.
+#[derive(Debug, Clone, Default)]
+pub struct TaskStats {
+    pub name: String,
+    /// finished or errored runs, preemptions are not counted
+    pub runs: u64,
+    pub failures: u64,
+    /// mean wall time of the successful runs in seconds
+    pub mean_duration: Option<f64>,
+}
+
+impl TaskStats {
+    pub fn failure_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.runs as f64
+        }
+    }
+}
+
+/// One invocation of the scheduler.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub id: i64,
+    pub name: Option<String>,
+    pub start_time: f64,
+    pub end_time: Option<f64>,
+    pub metadata: Vec<(String, String)>,
+}
+
+/// Task execution history backed by an SQLite database.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path) -> rusqlite::Result<History> {
+        History::with_connection(Connection::open(path)?)
+    }
+
+    /// The history kept in `conn`, creating its tables if they are missing.
+    fn with_connection(conn: Connection) -> rusqlite::Result<History> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS executions (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                command TEXT NOT NULL,
+                start_time REAL NOT NULL,
+                end_time REAL NOT NULL,
+                outcome TEXT NOT NULL,
+                exit_code INTEGER,
+                signal INTEGER,
+                user_time REAL NOT NULL,
+                sys_time REAL NOT NULL,
+                max_rss INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS executions_name ON executions (name);
+            CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                name TEXT,
+                start_time REAL NOT NULL,
+                end_time REAL
+            );
+            CREATE TABLE IF NOT EXISTS session_metadata (
+                session_id INTEGER NOT NULL REFERENCES sessions (id),
+                key TEXT NOT NULL,
+                value TEXT NOT NULL
+            );",
+        )?;
+        let history = History { conn };
+        history.ensure_column(
+            "executions",
+            "session_id",
+            "INTEGER REFERENCES sessions (id)",
+        )?;
+        Ok(history)
+    }
+
+    /// Add a column missing from a database created by an older version.
+    fn ensure_column(&self, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+        if !exists {
+            self.conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, decl
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Start a session, returning the id its runs are recorded with.
+    pub fn begin_session(
+        &self,
+        name: Option<&str>,
+        start_time: f64,
+        metadata: &[(String, String)],
+    ) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO sessions (name, start_time) VALUES (?1, ?2)",
+            params![name, start_time],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        for (key, value) in metadata {
+            self.conn.execute(
+                "INSERT INTO session_metadata (session_id, key, value) VALUES (?1, ?2, ?3)",
+                params![id, key, value],
+            )?;
+        }
+        Ok(id)
+    }
+
+    pub fn end_session(&self, session: i64, end_time: f64) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET end_time = ?1 WHERE id = ?2",
+            params![end_time, session],
+        )?;
+        Ok(())
+    }
+
+    /// Sessions ordered by start, optionally only those with a given name.
+    pub fn sessions(&self, name: Option<&str>) -> rusqlite::Result<Vec<Session>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, start_time, end_time FROM sessions
+             WHERE ?1 IS NULL OR name = ?1 ORDER BY start_time",
+        )?;
+        let mut sessions = stmt
+            .query_map(params![name], |row| {
+                Ok(Session {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    start_time: row.get(2)?,
+                    end_time: row.get(3)?,
+                    metadata: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Session>>>()?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, value FROM session_metadata WHERE session_id = ?1")?;
+        for session in sessions.iter_mut() {
+            session.metadata = stmt
+                .query_map(params![session.id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        }
+        Ok(sessions)
+    }
+
+    /// Add `execution` to the runs of `session`, if it belongs to one.
+    pub fn record(&self, execution: &Execution, session: Option<i64>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO executions (name, command, start_time, end_time, outcome,
+                exit_code, signal, user_time, sys_time, max_rss, session_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                execution.name,
+                execution.command,
+                execution.start_time,
+                execution.end_time,
+                execution.outcome,
+                execution.exit_code,
+                execution.signal,
+                execution.user_time,
+                execution.sys_time,
+                execution.max_rss as i64,
+                session,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Mean wall time of the successful runs of a task, in seconds.
+    pub fn mean_duration(&self, name: &str) -> rusqlite::Result<Option<f64>> {
+        self.conn
+            .query_row(
+                "SELECT AVG(end_time - start_time) FROM executions
+                 WHERE name = ?1 AND outcome = 'finished' AND exit_code = 0",
+                params![name],
+                |row| row.get::<_, Option<f64>>(0),
+            )
+            .optional()
+            .map(|v| v.flatten())
+    }
+
+    /// Highest peak RSS (KiB) over the last few finished runs of a task.
+    pub fn peak_rss_estimate(&self, name: &str) -> rusqlite::Result<Option<u64>> {
+        self.conn
+            .query_row(
+                "SELECT MAX(max_rss) FROM (
+                    SELECT max_rss FROM executions
+                    WHERE name = ?1 AND outcome = 'finished' AND max_rss > 0
+                    ORDER BY start_time DESC LIMIT 5
+                )",
+                params![name],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .map(|v| v.map(|v| v as u64))
+    }
+
+    pub fn query(&self, filter: &Filter) -> rusqlite::Result<Vec<Execution>> {
+        let (clause, values) = filter.where_clause();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT e.name, e.command, e.start_time, e.end_time, e.outcome, e.exit_code,
+                e.signal, e.user_time, e.sys_time, e.max_rss, s.name
+             FROM executions e LEFT JOIN sessions s ON e.session_id = s.id
+             {} ORDER BY e.start_time",
+            clause
+        ))?;
+        let rows = stmt.query_map(params_from_iter(values), execution_from_row)?;
+        rows.collect()
+    }
+
+    pub fn stats(&self, filter: &Filter) -> rusqlite::Result<Vec<TaskStats>> {
+        let (clause, values) = filter.where_clause();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT e.name,
+                SUM(e.outcome NOT IN ('preempted', 'interrupted', 'cancelled')),
+                SUM({failed}),
+                AVG(CASE WHEN e.outcome = 'finished' AND e.exit_code = 0
+                    THEN e.end_time - e.start_time END)
+             FROM executions e LEFT JOIN sessions s ON e.session_id = s.id
+             {clause} GROUP BY e.name ORDER BY e.name",
+            failed = FAILED,
+            clause = clause
+        ))?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok(TaskStats {
+                name: row.get(0)?,
+                runs: row.get::<_, i64>(1)? as u64,
+                failures: row.get::<_, i64>(2)? as u64,
+                mean_duration: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+fn execution_from_row(row: &Row) -> rusqlite::Result<Execution> {
+    Ok(Execution {
+        name: row.get(0)?,
+        command: row.get(1)?,
+        start_time: row.get(2)?,
+        end_time: row.get(3)?,
+        outcome: row.get(4)?,
+        exit_code: row.get(5)?,
+        signal: row.get(6)?,
+        user_time: row.get(7)?,
+        sys_time: row.get(8)?,
+        max_rss: row.get::<_, i64>(9)? as u64,
+        session: row.get(10)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        History::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn run(
+        name: &str,
+        start_time: f64,
+        duration: f64,
+        outcome: &str,
+        exit_code: Option<i32>,
+    ) -> Execution {
+        Execution {
+            name: name.to_string(),
+            command: format!("./{}", name),
+            start_time,
+            end_time: start_time + duration,
+            outcome: outcome.to_string(),
+            exit_code,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mean_duration_only_counts_successful_runs() {
+        let history = history();
+        assert_eq!(history.mean_duration("a").unwrap(), None);
+        history
+            .record(&run("a", 100.0, 10.0, "finished", Some(0)), None)
+            .unwrap();
+        history
+            .record(&run("a", 200.0, 20.0, "finished", Some(0)), None)
+            .unwrap();
+        history
+            .record(&run("a", 300.0, 90.0, "finished", Some(1)), None)
+            .unwrap();
+        history
+            .record(&run("a", 400.0, 90.0, "preempted", None), None)
+            .unwrap();
+        history
+            .record(&run("b", 500.0, 90.0, "finished", Some(0)), None)
+            .unwrap();
+        assert_eq!(history.mean_duration("a").unwrap(), Some(15.0));
+    }
+
+    fn starts(executions: &[Execution]) -> Vec<f64> {
+        executions
+            .iter()
+            .map(|execution| execution.start_time)
+            .collect()
+    }
+
+    fn record_runs(history: &History) {
+        for execution in [
+            run("train-a", 100.0, 10.0, "finished", Some(0)),
+            run("train-b", 200.0, 10.0, "finished", Some(1)),
+            run("eval", 300.0, 10.0, "error", None),
+            run("train-a", 400.0, 10.0, "preempted", None),
+            run("train-a", 500.0, 30.0, "finished", Some(0)),
+        ] {
+            history.record(&execution, None).unwrap();
+        }
+    }
+
+    #[test]
+    fn query_returns_what_was_recorded() {
+        let history = history();
+        let mut execution = run("a", 100.0, 10.0, "finished", Some(0));
+        execution.signal = Some(9);
+        execution.user_time = 1.5;
+        execution.sys_time = 0.5;
+        execution.max_rss = 2048;
+        history.record(&execution, None).unwrap();
+
+        let executions = history.query(&Filter::default()).unwrap();
+        assert_eq!(executions.len(), 1);
+        let got = &executions[0];
+        assert_eq!(
+            (
+                got.name.as_str(),
+                got.command.as_str(),
+                got.outcome.as_str()
+            ),
+            ("a", "./a", "finished")
+        );
+        assert_eq!(
+            (got.start_time, got.end_time, got.exit_code, got.signal),
+            (100.0, 110.0, Some(0), Some(9))
+        );
+        assert_eq!((got.user_time, got.sys_time, got.max_rss), (1.5, 0.5, 2048));
+    }
+
+    #[test]
+    fn query_applies_every_filter() {
+        let history = history();
+        record_runs(&history);
+        let query = |filter: Filter| starts(&history.query(&filter).unwrap());
+        assert_eq!(
+            query(Filter::default()),
+            vec![100.0, 200.0, 300.0, 400.0, 500.0]
+        );
+        let failed = Filter {
+            failed: true,
+            ..Default::default()
+        };
+        assert_eq!(query(failed.clone()), vec![200.0, 300.0]);
+        let since = Filter {
+            since: Some(300.0),
+            ..Default::default()
+        };
+        assert_eq!(query(since), vec![300.0, 400.0, 500.0]);
+        let train = Filter {
+            name_like: Some("train-%".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(train.clone()), vec![100.0, 200.0, 400.0, 500.0]);
+        let failed_train = Filter {
+            name_like: train.name_like,
+            ..failed
+        };
+        assert_eq!(query(failed_train), vec![200.0]);
+    }
+
+    #[test]
+    fn stats_count_runs_and_failures_per_name() {
+        let history = history();
+        record_runs(&history);
+        let stats = history.stats(&Filter::default()).unwrap();
+        let rows: Vec<_> = stats
+            .iter()
+            .map(|stats| {
+                (
+                    stats.name.as_str(),
+                    stats.runs,
+                    stats.failures,
+                    stats.mean_duration,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("eval", 1, 1, None),
+                ("train-a", 2, 0, Some(20.0)),
+                ("train-b", 1, 1, None)
+            ]
+        );
+        assert_eq!(stats[1].failure_rate(), 0.0);
+        assert_eq!(stats[2].failure_rate(), 1.0);
+
+        let since = Filter {
+            since: Some(450.0),
+            ..Default::default()
+        };
+        let stats = history.stats(&since).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].name.as_str(), stats[0].runs), ("train-a", 1));
+    }
+
+    #[test]
+    fn sessions_keep_their_metadata_and_runs() {
+        let history = history();
+        history
+            .record(&run("before", 50.0, 1.0, "finished", Some(0)), None)
+            .unwrap();
+        let metadata = vec![
+            ("commit".to_string(), "abc".to_string()),
+            ("gpu".to_string(), "a100".to_string()),
+        ];
+        let id = history
+            .begin_session(Some("sweep"), 100.0, &metadata)
+            .unwrap();
+        history
+            .record(&run("a", 100.0, 10.0, "finished", Some(0)), Some(id))
+            .unwrap();
+        history.end_session(id, 120.0).unwrap();
+        history.begin_session(None, 200.0, &[]).unwrap();
+        history
+            .record(&run("b", 200.0, 10.0, "finished", Some(0)), None)
+            .unwrap();
+
+        let sessions = history.sessions(None).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, id);
+        assert_eq!(sessions[0].name.as_deref(), Some("sweep"));
+        assert_eq!(
+            (sessions[0].start_time, sessions[0].end_time),
+            (100.0, Some(120.0))
+        );
+        assert_eq!(sessions[0].metadata, metadata);
+        assert_eq!(
+            (sessions[1].name.as_deref(), sessions[1].end_time),
+            (None, None)
+        );
+        assert_eq!(history.sessions(Some("sweep")).unwrap().len(), 1);
+
+        let executions = history.query(&Filter::default()).unwrap();
+        let names: Vec<_> = executions
+            .iter()
+            .map(|execution| execution.session.as_deref())
+            .collect();
+        assert_eq!(names, vec![None, Some("sweep"), None]);
+        let sweep = Filter {
+            session: Some("sweep".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(starts(&history.query(&sweep).unwrap()), vec![100.0]);
+    }
+
+    #[test]
+    fn opening_an_old_database_adds_the_session_column() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE executions (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                command TEXT NOT NULL,
+                start_time REAL NOT NULL,
+                end_time REAL NOT NULL,
+                outcome TEXT NOT NULL,
+                exit_code INTEGER,
+                signal INTEGER,
+                user_time REAL NOT NULL,
+                sys_time REAL NOT NULL,
+                max_rss INTEGER NOT NULL
+            );
+            INSERT INTO executions (name, command, start_time, end_time, outcome, exit_code,
+                user_time, sys_time, max_rss)
+            VALUES ('old', './old', 1.0, 2.0, 'finished', 0, 0.0, 0.0, 0);",
+        )
+        .unwrap();
+        let history = History::with_connection(conn).unwrap();
+        let id = history.begin_session(Some("new"), 100.0, &[]).unwrap();
+        history
+            .record(&run("a", 100.0, 1.0, "finished", Some(0)), Some(id))
+            .unwrap();
+
+        let executions = history.query(&Filter::default()).unwrap();
+        let runs: Vec<_> = executions
+            .iter()
+            .map(|execution| (execution.name.as_str(), execution.session.as_deref()))
+            .collect();
+        assert_eq!(runs, vec![("old", None), ("a", Some("new"))]);
+    }
+
+    #[test]
+    fn peak_rss_estimate_looks_at_the_last_five_finished_runs() {
+        let history = history();
+        assert_eq!(history.peak_rss_estimate("a").unwrap(), None);
+        for (i, max_rss) in [9000, 100, 200, 300, 400, 500].into_iter().enumerate() {
+            let mut execution = run("a", i as f64 * 100.0, 10.0, "finished", Some(0));
+            execution.max_rss = max_rss;
+            history.record(&execution, None).unwrap();
+        }
+        // neither counts, one was cut short and the other has no usage
+        let mut preempted = run("a", 1000.0, 10.0, "preempted", None);
+        preempted.max_rss = 8000;
+        history.record(&preempted, None).unwrap();
+        history
+            .record(&run("a", 1100.0, 10.0, "finished", Some(1)), None)
+            .unwrap();
+        assert_eq!(history.peak_rss_estimate("a").unwrap(), Some(500));
+        assert_eq!(history.peak_rss_estimate("b").unwrap(), None);
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+/// How long a client gets to send its request once connected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Requests with a larger body are turned away.
+const MAX_BODY: usize = 1 << 20;
+
+/// A small HTTP/1.0 server polled by the scheduler. Every connection carries
+/// one request and gets the response, then is closed. Requests are read by
+/// threads of their own, so a slow client doesn't hold up the scheduler.
+#[derive(Debug)]
+pub struct HttpServer {
+    addr: SocketAddr,
+    requests: Receiver<HttpRequest>,
+    /// readable while requests are waiting
+    woken: UnixStream,
+}
+
+/// A request read from a client, waiting for its response.
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    /// the path without the query string
+    pub path: String,
+    pub query: String,
+    /// header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    stream: TcpStream,
+}
+
+impl HttpServer {
+    /// Listen on `addr`, e.g. `127.0.0.1:9100`.
+    pub fn bind(addr: &str) -> io::Result<HttpServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (sender, requests) = mpsc::channel();
+        let (wake, woken) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        woken.set_nonblocking(true)?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let sender = sender.clone();
+                let wake = match wake.try_clone() {
+                    Ok(wake) => wake,
+                    Err(_) => continue,
+                };
+                thread::spawn(move || match read_request(stream) {
+                    Ok(Some(request)) => {
+                        // the server is gone if this fails, and the client with it
+                        if sender.send(request).is_ok() {
+                            let _ = (&wake).write(&[0]);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("failed to read http request: {}", e),
+                });
+            }
+        });
+        Ok(HttpServer {
+            addr,
+            requests,
+            woken,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    /// The requests read since the last call, without blocking.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        let mut buf = [0u8; 64];
+        while matches!((&self.woken).read(&mut buf), Ok(n) if n > 0) {}
+        self.requests.try_iter().collect()
+    }
+}
+
+impl AsRawFd for HttpServer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.woken.as_raw_fd()
+    }
+}
+
+fn read_request(stream: TcpStream) -> io::Result<Option<HttpRequest>> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // the response is written by the scheduler, which can't wait on a client
+    // that doesn't read
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(None),
+    };
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length.min(MAX_BODY)];
+    reader.read_exact(&mut body)?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target, String::new()),
+    };
+    let request = HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+        stream,
+    };
+    if length > MAX_BODY {
+        request.respond(413, "text/plain", b"request body too large\n");
+        return Ok(None);
+    }
+    Ok(Some(request))
+}
+
+impl HttpRequest {
+    /// The value of the header `name`, given in lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of `key` in the query string, percent-decoded.
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query.split('&').find_map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k) == key).then(|| percent_decode(v))
+        })
+    }
+
+    /// The connection, for protocols that take over from HTTP.
+    pub fn into_stream(self) -> TcpStream {
+        self.stream
+    }
+
+    /// Send `body` with `status` to the client and hang up.
+    pub fn respond(mut self, status: u16, content_type: &str, body: &[u8]) {
+        let head = format!(
+            "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            reason(status),
+            content_type,
+            body.len()
+        );
+        // the client may be gone already, nothing to do about it
+        let _ = self
+            .stream
+            .write_all(head.as_bytes())
+            .and_then(|_| self.stream.write_all(body));
+    }
+
+    /// Send what `file` holds now with `status` to the client from a thread,
+    /// however large it is and however slowly the client reads, and hang up.
+    pub fn respond_file(mut self, status: u16, content_type: &str, file: File) {
+        let len = match file.metadata() {
+            Ok(meta) => meta.len(),
+            Err(_) => return self.respond(500, "text/plain", b"failed to read the file\n"),
+        };
+        let head = format!(
+            "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            reason(status),
+            content_type,
+            len
+        );
+        thread::spawn(move || {
+            let _ = self.stream.set_write_timeout(None);
+            // the client may be gone already, nothing to do about it
+            let _ = self
+                .stream
+                .write_all(head.as_bytes())
+                .and_then(|_| io::copy(&mut file.take(len), &mut self.stream));
+        });
+    }
+}
+
+/// Whether `a` and `b` are equal, taking as long whichever byte differs so
+/// a token can't be guessed one byte at a time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `s` with `%XX` escapes and `+` decoded.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // from_str_radix would take a sign as well
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match (
+            bytes[i],
+            hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+        ) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `bytes` random bytes in hex, from `/dev/urandom`.
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0; bytes];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut buf))
+        .expect("Failed to read /dev/urandom");
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_decodes_escapes_and_plus() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("%2Fdone%2f"), "/done/");
+        assert_eq!(percent_decode("%e6%97%a5"), "日");
+        assert_eq!(percent_decode("%2B"), "+");
+    }
+
+    #[test]
+    fn percent_decode_keeps_broken_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%2"), "%2");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%+1"), "% 1");
+        assert_eq!(percent_decode("%-1"), "%-1");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_slices() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,436 @@
+use rustix::process::Signal;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+use crate::backend::Backend;
+use crate::error::CirnoError;
+use crate::gpu::GpuAllocations;
+use crate::pool::WorkerPool;
+use crate::task::Task;
+
+/// How often the status of a job is asked for, the API server shouldn't
+/// hear from cirno every tick.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs every attempt as a Kubernetes Job through `kubectl`, with whatever
+/// context and credentials it is set up with. The container runs the task's
+/// command in its `@image=`, or the default image, with the task's
+/// environment, and its output is copied to the task's log once it ends.
+///
+/// Experimental: the working directory, the run directory and the
+/// pre-exec options stay behind on this machine, and so do the resource
+/// readings the scheduler goes by.
+pub struct KubernetesBackend {
+    kubectl: Kubectl,
+    image: Option<String>,
+    /// the job of each running task, by task name
+    jobs: HashMap<String, Job>,
+}
+
+struct Job {
+    name: String,
+    polled: Option<Instant>,
+    deleted: bool,
+}
+
+impl KubernetesBackend {
+    /// Create the jobs in `namespace`, or the one of the current context,
+    /// running `image` for tasks without one of their own.
+    pub fn new(namespace: Option<String>, image: Option<String>) -> KubernetesBackend {
+        KubernetesBackend {
+            kubectl: Kubectl { namespace },
+            image,
+            jobs: HashMap::new(),
+        }
+    }
+
+    fn manifest(&self, task: &Task, name: &str, image: &str) -> Value {
+        let command: Vec<String> = std::iter::once(task.handler.get_program())
+            .chain(task.handler.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let env: Vec<Value> = task
+            .handler
+            .get_envs()
+            .filter(|(key, _)| *key != "CUDA_VISIBLE_DEVICES")
+            .filter_map(|(key, value)| {
+                Some(json!({
+                    "name": key.to_string_lossy(),
+                    "value": value?.to_string_lossy(),
+                }))
+            })
+            .collect();
+        let mut requests = json!({ "cpu": task.cpus.max(1).to_string() });
+        if let Some(reserve) = task.mem_reserve {
+            requests["memory"] = json!(format!("{}Ki", reserve));
+        }
+        json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": {
+                "name": name,
+                "labels": {
+                    "app.kubernetes.io/managed-by": "cirno",
+                    "cirno/task": label(&task.name),
+                },
+            },
+            "spec": {
+                // retries are up to cirno
+                "backoffLimit": 0,
+                "template": {
+                    "spec": {
+                        "restartPolicy": "Never",
+                        "containers": [{
+                            "name": "task",
+                            "image": image,
+                            "command": command,
+                            "env": env,
+                            "resources": { "requests": requests },
+                        }],
+                    },
+                },
+            },
+        })
+    }
+
+    /// Copy the output of the job into the task's log and delete it, which
+    /// takes its pod along.
+    fn end(&mut self, task: &Task, grace: Option<Duration>) -> io::Result<()> {
+        let job = match self.jobs.get_mut(&task.name) {
+            Some(job) if !job.deleted => job,
+            _ => return Ok(()),
+        };
+        job.deleted = true;
+        if let Some(log) = task.logs.first() {
+            if let Err(e) = self.kubectl.copy_logs(&job.name, log) {
+                warn!("task: {} failed to copy the job's logs: {}", task.name, e);
+            }
+        }
+        self.kubectl.delete(&job.name, grace)
+    }
+
+    /// The exit code from the pod of a failed job, 1 if it has none.
+    fn exit_code(&self, job: &str) -> i32 {
+        let selector = format!("job-name={}", job);
+        let pods = match self.kubectl.json(&["get", "pods", "--selector", &selector]) {
+            Ok(pods) => pods,
+            Err(_) => return 1,
+        };
+        let statuses = pods["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|pod| {
+                pod["status"]["containerStatuses"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+            });
+        statuses
+            .filter_map(|status| status["state"]["terminated"]["exitCode"].as_i64())
+            .find(|code| *code != 0)
+            .unwrap_or(1) as i32
+    }
+}
+
+impl Backend for KubernetesBackend {
+    fn spawn(&mut self, task: &mut Task) -> Result<(), CirnoError> {
+        let image = task.image.as_ref().or(self.image.as_ref()).ok_or_else(|| {
+            CirnoError::Spawn(io::Error::other("no image, set @image= or --k8s-image"))
+        })?;
+        let name = job_name(&task.name);
+        let manifest = self.manifest(task, &name, image);
+        task.attempts += 1;
+        self.kubectl
+            .run(
+                &["create", "--filename", "-"],
+                Some(manifest.to_string().as_bytes()),
+            )
+            .map_err(CirnoError::Spawn)?;
+        info!("task: {} created job {}", task.name, name);
+        task.start_time = SystemTime::now();
+        task.timed_out_at = None;
+        task.usage = None;
+        task.rss = 0;
+        task.peak_rss = 0;
+        self.jobs.insert(
+            task.name.clone(),
+            Job {
+                name,
+                polled: None,
+                deleted: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn is_running(&self, task: &Task) -> bool {
+        self.jobs.contains_key(&task.name)
+    }
+
+    /// The signal is up to the kubelet, a timed out job is deleted with
+    /// `grace` as the grace period of its pod.
+    fn try_wait(
+        &mut self,
+        task: &mut Task,
+        timeout: usize,
+        _signal: Signal,
+        grace: Duration,
+    ) -> io::Result<Option<ExitStatus>> {
+        let elapsed = task.start_time.elapsed().unwrap_or(Duration::from_secs(0));
+        if elapsed.as_secs() > timeout as u64 && timeout > 0 && task.timed_out_at.is_none() {
+            info!("task: {} timeout", task.name);
+            task.timed_out_at = Some(Instant::now());
+            self.end(task, Some(grace))?;
+        }
+        let job = match self.jobs.get_mut(&task.name) {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        if job.polled.is_some_and(|at| at.elapsed() < POLL_INTERVAL) {
+            return Ok(None);
+        }
+        job.polled = Some(Instant::now());
+        let name = job.name.clone();
+        let deleted = job.deleted;
+        let status = self.kubectl.json(&["get", "job", &name])?;
+        let killed = ExitStatus::from_raw(libc::SIGKILL);
+        let code = if status.is_null() {
+            // gone, deleted by cirno or someone else
+            self.jobs.remove(&task.name);
+            return Ok(Some(killed));
+        } else if status["status"]["succeeded"].as_u64().unwrap_or(0) > 0 {
+            0
+        } else if status["status"]["failed"].as_u64().unwrap_or(0) > 0 {
+            self.exit_code(&name)
+        } else {
+            return Ok(None);
+        };
+        if !deleted {
+            self.end(task, None)?;
+        }
+        self.jobs.remove(&task.name);
+        Ok(Some(ExitStatus::from_raw((code & 0xff) << 8)))
+    }
+
+    fn stop_step(&mut self, task: &mut Task) -> io::Result<()> {
+        self.end(task, Some(task.stop_policy.grace()))
+    }
+
+    fn kill(&mut self, task: &mut Task) -> io::Result<()> {
+        match self.jobs.get(&task.name) {
+            // a stop already under way is forced
+            Some(job) if job.deleted => {
+                self.kubectl.delete(&job.name, Some(Duration::from_secs(0)))
+            }
+            _ => self.end(task, Some(Duration::from_secs(0))),
+        }
+    }
+
+    fn stop(&mut self, task: &mut Task, pool: &mut WorkerPool, gpus: &GpuAllocations) {
+        gpus.release(&std::mem::take(&mut task.assigned_gpus));
+        let job = match self.jobs.remove(&task.name) {
+            Some(job) if !job.deleted => job,
+            _ => return,
+        };
+        let kubectl = self.kubectl.clone();
+        let log = task.logs.first().cloned();
+        let grace = task.stop_policy.grace();
+        let name = task.name.clone();
+        pool.execute(move || {
+            if let Some(log) = log {
+                let _ = kubectl.copy_logs(&job.name, &log);
+            }
+            match kubectl.delete(&job.name, Some(grace)) {
+                Ok(()) => info!("task: {} stopped", name),
+                Err(e) => warn!("task: {} failed to stop: {}", name, e),
+            }
+        });
+    }
+}
+
+/// `kubectl` in the namespace given, if any.
+#[derive(Clone)]
+struct Kubectl {
+    namespace: Option<String>,
+}
+
+impl Kubectl {
+    /// Run `kubectl` with `args`, feeding it `input`, and return its output.
+    fn run(&self, args: &[&str], input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut command = Command::new("kubectl");
+        if let Some(namespace) = &self.namespace {
+            command.arg("--namespace").arg(namespace);
+        }
+        command
+            .args(args)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "kubectl {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+
+    /// The object `kubectl get` describes, null if it doesn't exist.
+    fn json(&self, args: &[&str]) -> io::Result<Value> {
+        let args = [args, &["--ignore-not-found", "--output", "json"]].concat();
+        let output = self.run(&args, None)?;
+        if output.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Value::Null);
+        }
+        serde_json::from_slice(&output).map_err(io::Error::other)
+    }
+
+    fn copy_logs(&self, job: &str, log: &Path) -> io::Result<()> {
+        let output = self.run(&["logs", &format!("job/{}", job)], None)?;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(log)?
+            .write_all(&output)
+    }
+
+    /// Delete `job` without waiting for its pod to go, `grace` being the
+    /// time its container gets between SIGTERM and SIGKILL.
+    fn delete(&self, job: &str, grace: Option<Duration>) -> io::Result<()> {
+        let mut args = vec![
+            "delete".to_string(),
+            "job".to_string(),
+            job.to_string(),
+            "--ignore-not-found".to_string(),
+            "--wait=false".to_string(),
+        ];
+        match grace.map(|grace| grace.as_secs()) {
+            Some(0) => args.extend(["--grace-period=0".to_string(), "--force".to_string()]),
+            Some(secs) => args.push(format!("--grace-period={}", secs)),
+            None => {}
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run(&args, None).map(drop)
+    }
+}
+
+/// A job name no other attempt of any cirno has, which Kubernetes wants in
+/// lower case and at most 63 characters.
+fn job_name(task: &str) -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let mut task = label(task);
+    task.truncate(32);
+    format!(
+        "cirno-{}-{}-{}",
+        task.trim_end_matches('-'),
+        std::process::id(),
+        count
+    )
+}
+
+/// `name` with nothing but lower case letters, digits and dashes.
+fn label(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-');
+    let name = &name[..name.len().min(63)];
+    name.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::TaskBuilder;
+
+    #[test]
+    fn manifest_runs_the_command_with_the_environment_of_the_task() {
+        let mut task = TaskBuilder::new("Train it", "python")
+            .args(["train.py", "it's"])
+            .env("SEED", "1")
+            .env("CUDA_VISIBLE_DEVICES", "0")
+            .env("TRACEPARENT", "00-1-2-01")
+            .build();
+        task.cpus = 4;
+        task.mem_reserve = Some(2048);
+        let backend = KubernetesBackend::new(None, None);
+        let manifest = backend.manifest(&task, "cirno-train-it-1-0", "pytorch:2");
+        assert_eq!(manifest["metadata"]["name"], "cirno-train-it-1-0");
+        assert_eq!(manifest["metadata"]["labels"]["cirno/task"], "train-it");
+        assert_eq!(manifest["spec"]["backoffLimit"], 0);
+        let container = &manifest["spec"]["template"]["spec"]["containers"][0];
+        assert_eq!(container["image"], "pytorch:2");
+        assert_eq!(container["command"], json!(["python", "train.py", "it's"]));
+        // the gpus of this machine mean nothing in the cluster
+        let mut env: Vec<&str> = container["env"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| var["name"].as_str().unwrap())
+            .collect();
+        env.sort();
+        assert_eq!(env, ["SEED", "TRACEPARENT"]);
+        assert_eq!(
+            container["resources"]["requests"],
+            json!({ "cpu": "4", "memory": "2048Ki" })
+        );
+    }
+
+    #[test]
+    fn manifest_requests_a_core_without_a_memory_reserve() {
+        let task = TaskBuilder::new("a", "true").build();
+        let manifest = KubernetesBackend::new(None, None).manifest(&task, "a", "busybox");
+        let requests =
+            &manifest["spec"]["template"]["spec"]["containers"][0]["resources"]["requests"];
+        assert_eq!(requests, &json!({ "cpu": "1" }));
+    }
+
+    #[test]
+    fn label_keeps_letters_digits_and_dashes() {
+        assert_eq!(label("Train_Model v2"), "train-model-v2");
+        assert_eq!(label("--a.b--"), "a-b");
+        assert_eq!(label(&"x".repeat(100)).len(), 63);
+    }
+
+    #[test]
+    fn job_name_is_unique_lowercase_and_short() {
+        let first = job_name(&"Some_Task_".repeat(20));
+        let second = job_name(&"Some_Task_".repeat(20));
+        assert_ne!(first, second);
+        for name in [first, second] {
+            assert!(name.len() <= 63, "{}", name);
+            assert!(name.starts_with("cirno-some-task-"), "{}", name);
+            assert!(
+                !name.chars().any(|c| c.is_ascii_uppercase() || c == '_'),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -1 +1,92 @@
+// cirno needs Unix, on Windows all that builds so far is what `process`
+// has there
+#[cfg(unix)]
+pub mod backend;
+#[cfg(unix)]
+pub mod cgroup;
+#[cfg(unix)]
+pub mod clock;
+#[cfg(unix)]
+pub mod config;
+#[cfg(unix)]
+pub mod container;
+#[cfg(unix)]
+pub mod control;
+#[cfg(all(unix, feature = "dashboard"))]
+pub mod dashboard;
+#[cfg(unix)]
+pub mod disk;
+pub mod error;
+#[cfg(unix)]
+pub mod events;
+#[cfg(unix)]
+pub mod gpu;
+#[cfg(unix)]
+pub mod handle;
+#[cfg(unix)]
+pub mod history;
+#[cfg(unix)]
+pub mod http;
+#[cfg(unix)]
+pub mod kubernetes;
+#[cfg(unix)]
+pub mod logfile;
+#[cfg(unix)]
+pub mod mail;
+#[cfg(unix)]
+pub mod namespace;
+#[cfg(unix)]
+pub mod notify;
+#[cfg(unix)]
+pub mod observer;
+#[cfg(unix)]
+pub mod otlp;
+#[cfg(unix)]
+pub mod pidfile;
+#[cfg(unix)]
+pub mod policy;
+#[cfg(unix)]
+pub mod pool;
+#[cfg(unix)]
+pub mod preexec;
+#[cfg(unix)]
+pub mod pressure;
+#[cfg(unix)]
+pub mod probe;
+#[cfg(unix)]
 pub mod process;
+#[cfg(windows)]
+#[path = "process/windows.rs"]
+pub mod process;
+#[cfg(unix)]
+pub mod progress;
+#[cfg(all(unix, feature = "tokio"))]
+pub mod reactor;
+#[cfg(unix)]
+pub mod sandbox;
+#[cfg(unix)]
+pub mod scheduler;
+#[cfg(unix)]
+pub mod signals;
+#[cfg(unix)]
+pub mod ssh;
+#[cfg(unix)]
+pub mod stream;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(unix)]
+pub mod task;
+#[cfg(all(unix, feature = "telegram"))]
+pub mod telegram;
+#[cfg(unix)]
+pub mod thermal;
+#[cfg(unix)]
+pub mod timeline;
+#[cfg(unix)]
+pub mod user;
+#[cfg(unix)]
+pub mod vmstat;
+#[cfg(unix)]
+pub mod watch;
+#[cfg(unix)]
+pub mod webhook;
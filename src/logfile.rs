@@ -0,0 +1,231 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// `path` with `.n` appended, e.g. `a.txtlog.2`.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Move the contents of the log at `path` to `path.1`, shifting the older
+/// copies up to `path.<keep>`, and empty it. The log is copied and truncated
+/// rather than renamed since the task keeps writing to it, so it has to be
+/// opened with `O_APPEND` for the writes to start over at its new end. With
+/// `keep` 0 the contents are dropped.
+pub fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    for n in (1..keep).rev() {
+        match fs::rename(numbered(path, n), numbered(path, n + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    if keep > 0 {
+        fs::copy(path, numbered(path, 1))?;
+    }
+    OpenOptions::new().write(true).open(path)?.set_len(0)
+}
+
+/// How finished logs are compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Parse `gzip` or `zstd`.
+    pub fn parse(s: &str) -> Result<Compression, String> {
+        match s {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression: {}, expected gzip or zstd", s)),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+/// The rotated copies of the log at `path` that are there, `path.1` first.
+pub fn rotated(path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| numbered(path, n))
+        .take_while(|copy| copy.exists())
+        .collect()
+}
+
+/// Replace the log at `path` with a compressed copy, e.g. `a.txtlog.gz`.
+/// Empty logs are left as they are.
+pub fn compress(path: &Path, compression: Compression) -> io::Result<()> {
+    if fs::metadata(path)?.len() == 0 {
+        return Ok(());
+    }
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", compression.extension()));
+    let target = PathBuf::from(name);
+    let mut input = File::open(path)?;
+    let output = File::create(&target)?;
+    fs::set_permissions(&target, input.metadata()?.permissions())?;
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    fs::remove_file(path)
+}
+
+/// The newest log of the task `name` in `dir` with `extension`, e.g.
+/// `txtlog`: the one of its latest attempt, compressed or not.
+pub fn find(dir: &Path, name: &str, extension: &str) -> Option<PathBuf> {
+    let first = format!("{}.{}", name, extension);
+    let attempt_prefix = format!("{}.attempt", name);
+    let mut newest: Option<(usize, PathBuf)> = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        let plain = [".gz", ".zst"]
+            .iter()
+            .find_map(|ext| file_name.strip_suffix(ext))
+            .unwrap_or(file_name);
+        let attempt = if plain == first {
+            1
+        } else {
+            match plain
+                .strip_prefix(&attempt_prefix)
+                .and_then(|rest| rest.strip_suffix(&format!(".{}", extension)))
+                .and_then(|n| n.parse().ok())
+            {
+                Some(attempt) => attempt,
+                None => continue,
+            }
+        };
+        // the plain log is the one being written if both are there
+        let better = match &newest {
+            Some((best, path)) => {
+                attempt > *best
+                    || (attempt == *best && path.extension() != Some(extension.as_ref()))
+            }
+            None => true,
+        };
+        if better {
+            newest = Some((attempt, entry.path()));
+        }
+    }
+    newest.map(|(_, path)| path)
+}
+
+/// Read the log at `path`, decompressing it if it ends in `.gz` or `.zst`.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(GzDecoder::new(file)),
+        Some("zst") => Box::new(zstd::Decoder::new(file)?),
+        _ => Box::new(file),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// An empty directory of its own for `test`.
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cirno-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rotate_shifts_the_copies_and_drops_the_oldest() {
+        let dir = dir("rotate");
+        let log = dir.join("a.txtlog");
+        let mut writer = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&log)
+            .unwrap();
+        for line in ["one\n", "two\n", "three\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+            rotate(&log, 2).unwrap();
+        }
+        writer.write_all(b"four\n").unwrap();
+
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!(read(&log), "four\n");
+        assert_eq!(rotated(&log), vec![numbered(&log, 1), numbered(&log, 2)]);
+        assert_eq!(read(&numbered(&log, 1)), "three\n");
+        assert_eq!(read(&numbered(&log, 2)), "two\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_keeping_nothing_only_empties_the_log() {
+        let dir = dir("rotate-none");
+        let log = dir.join("a.txtlog");
+        fs::write(&log, "one\n").unwrap();
+        rotate(&log, 0).unwrap();
+        assert_eq!(fs::metadata(&log).unwrap().len(), 0);
+        assert!(rotated(&log).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find_picks_the_latest_attempt() {
+        let dir = dir("find");
+        let files = [
+            "a.txtlog.gz",
+            "a.attempt2.txtlog.zst",
+            "a.attempt3.txtlog",
+            "a.attempt10.err",
+            "a.attemptx.txtlog",
+            "ab.txtlog",
+            "ab.attempt9.txtlog",
+        ];
+        for file in files {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(
+            find(&dir, "a", "txtlog"),
+            Some(dir.join("a.attempt3.txtlog"))
+        );
+        assert_eq!(find(&dir, "a", "err"), Some(dir.join("a.attempt10.err")));
+        assert_eq!(
+            find(&dir, "ab", "txtlog"),
+            Some(dir.join("ab.attempt9.txtlog"))
+        );
+        assert_eq!(find(&dir, "b", "txtlog"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find_prefers_the_log_still_being_written() {
+        let dir = dir("find-plain");
+        for file in ["a.txtlog", "a.txtlog.gz", "b.txtlog.zst"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(find(&dir, "a", "txtlog"), Some(dir.join("a.txtlog")));
+        assert_eq!(find(&dir, "b", "txtlog"), Some(dir.join("b.txtlog.zst")));
+        assert_eq!(find(&dir.join("missing"), "a", "txtlog"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
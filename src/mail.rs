@@ -0,0 +1,128 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::clock::{format_rfc2822, unix_now};
+
+/// How long the SMTP server gets to answer each command.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the summary of a run is mailed to, through an SMTP server or else
+/// the local `sendmail`.
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    pub to: Vec<String>,
+    pub from: String,
+    /// `host:port` of a server relaying plain SMTP, such as the local MTA
+    pub server: Option<String>,
+}
+
+impl Mailer {
+    /// Send a plain text mail with `subject` and `body` to every recipient.
+    pub fn send(&self, subject: &str, body: &str) -> io::Result<()> {
+        let message = self.message(subject, body);
+        match &self.server {
+            Some(server) => self.send_smtp(server, &message),
+            None => self.send_sendmail(&message),
+        }
+    }
+
+    fn message(&self, subject: &str, body: &str) -> String {
+        let mut message = format!(
+            "Date: {}\r\nFrom: {}\r\nTo: {}\r\nSubject: {}\r\nX-Mailer: cirno-rs\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            format_rfc2822(unix_now()),
+            self.from,
+            self.to.join(", "),
+            subject
+        );
+        for line in body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+
+    fn send_sendmail(&self, message: &str) -> io::Result<()> {
+        let mut sendmail = Command::new("sendmail")
+            .arg("-i")
+            .arg("-f")
+            .arg(&self.from)
+            .arg("--")
+            .args(&self.to)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = sendmail.stdin.take() {
+            stdin.write_all(message.replace("\r\n", "\n").as_bytes())?;
+        }
+        let status = sendmail.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("sendmail exited with {}", status)));
+        }
+        Ok(())
+    }
+
+    fn send_smtp(&self, server: &str, message: &str) -> io::Result<()> {
+        let stream = TcpStream::connect(server)?;
+        stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        // who we are, as far as the server needs to know
+        let host = self.from.rsplit('@').next().unwrap_or("localhost");
+        expect(&mut reader, 220)?;
+        command(&mut writer, &mut reader, &format!("HELO {}", host), 250)?;
+        command(
+            &mut writer,
+            &mut reader,
+            &format!("MAIL FROM:<{}>", self.from),
+            250,
+        )?;
+        for to in &self.to {
+            command(&mut writer, &mut reader, &format!("RCPT TO:<{}>", to), 250)?;
+        }
+        command(&mut writer, &mut reader, "DATA", 354)?;
+        for line in message.split_inclusive("\r\n") {
+            // a lone dot would end the DATA early
+            if line.starts_with('.') {
+                writer.write_all(b".")?;
+            }
+            writer.write_all(line.as_bytes())?;
+        }
+        command(&mut writer, &mut reader, ".", 250)?;
+        command(&mut writer, &mut reader, "QUIT", 221)
+    }
+}
+
+fn command(
+    writer: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    line: &str,
+    code: u16,
+) -> io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes())?;
+    expect(reader, code)
+}
+
+/// Read a reply, which may span several `250-` lines, and check its code.
+fn expect(reader: &mut BufReader<TcpStream>, code: u16) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "SMTP server hung up",
+            ));
+        }
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return match line.get(..3).and_then(|got| got.parse::<u16>().ok()) {
+            Some(got) if got == code => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "SMTP server answered: {}",
+                line.trim()
+            ))),
+        };
+    }
+}
@@ -1,412 +1,960 @@
-use clap::Parser;
-use rustix::process::{Pid, Signal};
-use rustix::process::kill_process;
-use std::fmt::Display;
+// everything but `process::ProcessGroupHandle` needs Unix so far
+#[cfg(not(unix))]
+compile_error!("cirno-rs runs on Unix only, on Windows just the library builds");
+
+use cirno_rs::backend::BackendKind;
+use cirno_rs::cgroup::parse_size;
+use cirno_rs::clock::{
+    format_elapsed, format_utc, parse_grace, parse_interval, parse_since, unix_now,
+};
+use cirno_rs::config;
+use cirno_rs::control::{self, ControlSocket};
+use cirno_rs::events::EventLog;
+use cirno_rs::history::{Filter, History};
+use cirno_rs::http::{random_hex, HttpServer};
+use cirno_rs::kubernetes::KubernetesBackend;
+use cirno_rs::logfile::{self, Compression};
+use cirno_rs::mail::Mailer;
+use cirno_rs::namespace::Namespaces;
+use cirno_rs::otlp::Exporter;
+use cirno_rs::pidfile::{read_pid, PidFile};
+use cirno_rs::policy::Policy;
+use cirno_rs::preexec::{parse_oom_score_adj, IoPriority, PreExec, Rlimit};
+use cirno_rs::process::{daemonize, parse_signal};
+use cirno_rs::progress;
+use cirno_rs::scheduler::{
+    LogPolicy, NotifyMode, PreemptMode, PreemptPolicy, Scheduler, StderrMode,
+};
+use cirno_rs::signals;
+use cirno_rs::systemd;
+use cirno_rs::task::{gen_tasks, StopPolicy};
+#[cfg(feature = "telegram")]
+use cirno_rs::telegram;
+use cirno_rs::user::{parse_mode, parse_owner, FileAccess};
+use cirno_rs::watch::DirWatch;
+use cirno_rs::webhook::Webhook;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use rustix::process::{kill_process, Pid, Signal};
+use std::collections::VecDeque;
 use std::fs;
-use std::path::Path;
-use std::process::{Child, Command, Stdio};
-use std::time::{SystemTime, Duration};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use sysinfo::{System, SystemExt};
-use cirno_rs::process::kill_process_tree;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+
+/// How scheduler events are logged.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum LogFormat {
+    /// one line of text each
+    Text,
+    /// one JSON object per line
+    Json,
+}
 
+fn init_runtime(dir: &Path, access: &FileAccess) {
+    fs::create_dir_all(dir).expect("Failed to create runtime directory");
+    access
+        .apply(dir)
+        .expect("Failed to set runtime directory permissions");
+}
 
-#[derive(Debug)]
-struct Task {
-    name: String,
-    prog: String,
-    args: Vec<String>,
-    handler: Command,
-    child: Option<Child>,
-    start_time: SystemTime,
+/// Point `run_dir/latest` at the run directory `name`, replacing the link of
+/// an earlier run.
+fn link_latest(run_dir: &Path, name: &str) -> std::io::Result<()> {
+    let tmp = run_dir.join(".latest");
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(name, &tmp)?;
+    fs::rename(&tmp, run_dir.join("latest"))
 }
 
-impl PartialEq for Task {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.prog == other.prog && self.args == other.args
+/// `name` in `run_dir`, or in `run_dir/latest` if only that has it, for the
+/// subcommands to find a cirno started with --timestamped.
+fn find_run_file(run_dir: &Path, name: &str) -> PathBuf {
+    let path = run_dir.join(name);
+    let latest = run_dir.join("latest").join(name);
+    if !path.exists() && latest.exists() {
+        latest
+    } else {
+        path
     }
 }
 
-impl Display for Task {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut args = String::new();
-        for arg in &self.args {
-            args.push_str(arg);
-            args.push_str(" ");
-        }
-        write!(f, "{} {} {}", self.name, self.prog, args)
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(args_override_self = true)]
+struct CLIArgs {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[arg(required_unless_present = "watch")]
+    input_list: Option<String>,
+    /// Keep running and submit the lines of every file written or moved into
+    /// this directory, hidden files excepted
+    #[arg(long)]
+    watch: Option<PathBuf>,
+    /// Cores to hand out; each task takes its `@cpus=N`, one by default
+    #[arg(short, long, default_value_t = default_max_workers())]
+    max_workers: usize,
+    /// Tasks kept running whatever the resources
+    #[arg(short, long, default_value_t = 1)]
+    force_task: usize,
+    /// Seconds between two checks of the children
+    #[arg(short, long, default_value_t = 10, alias = "sleep-duartion")]
+    sleep_duration: usize,
+    /// How often (e.g. 30, 2m) resources are checked to launch or preempt
+    /// tasks, every --sleep-duration seconds by default. Children are checked
+    /// every tick and a task exiting always triggers a check
+    #[arg(long, value_parser = parse_interval)]
+    probe_interval: Option<Duration>,
+    /// GiB of memory kept free, a tenth of the total memory by default
+    #[arg(short, long, default_value_t = default_reserved_mem())]
+    reserved_mem: usize,
+    /// GiB of memory a task is expected to take
+    #[arg(short, long, default_value_t = 3)]
+    per_task_mem: usize,
+    /// Seconds a task may run before it gets --timeout-signal
+    #[arg(short, long, default_value_t = 7200)]
+    timeout: usize,
+    /// Signal sent to a task that ran longer than --timeout
+    #[arg(long, value_parser = parse_signal, default_value = "ALRM")]
+    timeout_signal: Signal,
+    /// Seconds (or 30s, 2m) a timed out task may keep running before it is
+    /// stopped like a preempted one
+    #[arg(long, value_parser = parse_grace, default_value = "60")]
+    timeout_grace: Duration,
+    #[arg(long)]
+    load_max: Option<f64>,
+    #[arg(long)]
+    load_min: Option<f64>,
+    /// Smooth the 1-minute load with this EWMA weight (0..1) instead of using the 5-minute load
+    #[arg(long)]
+    load_smoothing: Option<f64>,
+    /// Max cpu pressure (PSI some avg10, percent); replaces the load average checks
+    #[arg(long)]
+    psi_cpu_max: Option<f64>,
+    /// Max memory pressure (PSI some avg10, percent)
+    #[arg(long)]
+    psi_memory_max: Option<f64>,
+    /// Max io pressure (PSI some avg10, percent)
+    #[arg(long)]
+    psi_io_max: Option<f64>,
+    /// Max swap in use (GB) before cirno considers memory short
+    #[arg(long)]
+    max_swap_used: Option<usize>,
+    /// Max swap-in rate (MB/s) before cirno considers memory short
+    #[arg(long)]
+    max_swapin_rate: Option<f64>,
+    /// Max disk utilization (percent busy, from /proc/diskstats) of the busiest disk
+    #[arg(long)]
+    io_max: Option<f64>,
+    /// Only consider these block devices for --io-max, e.g. sda, may be repeated
+    #[arg(long)]
+    io_device: Vec<String>,
+    /// Max temperature (°C) of the hottest thermal zone; also backs off while throttling
+    #[arg(long)]
+    temp_max: Option<f64>,
+    /// Niceness of tasks without their own `@nice=N`
+    #[arg(long, allow_hyphen_values = true)]
+    nice: Option<i32>,
+    /// I/O priority of tasks without their own `@ionice=`, e.g. idle or best-effort:7
+    #[arg(long)]
+    ionice: Option<String>,
+    /// `oom_score_adj` of tasks without their own `@oom_score_adj=`, up to 1000
+    /// to have the OOM killer pick them before anything else
+    #[arg(long, allow_hyphen_values = true)]
+    oom_score_adj: Option<String>,
+    /// Resource limit of tasks without their own `@rlimit_<name>=`, like as=8G,
+    /// cpu=2h, nofile=1024 or core=0, may be repeated
+    #[arg(long, value_parser = parse_rlimit)]
+    rlimit: Vec<Rlimit>,
+    /// Namespaces of their own for tasks without their own `@unshare=`, a
+    /// comma separated list of mount, pid and net
+    #[arg(long, value_parser = Namespaces::parse)]
+    unshare: Option<Namespaces>,
+    /// Adopt the orphaned children of tasks, so daemonized helpers are still
+    /// accounted to their task and killed with it
+    #[arg(long)]
+    subreaper: bool,
+    /// SIGKILL processes still around 10s after their task ended, instead of
+    /// only reporting them
+    #[arg(long)]
+    kill_leftovers: bool,
+    /// Don't SIGKILL the tasks when cirno itself dies
+    #[arg(long)]
+    keep_tasks_on_exit: bool,
+    /// Detach from the terminal and keep running in the background, writing
+    /// the output to cirno.log in the run directory
+    #[arg(long)]
+    daemon: bool,
+    /// Directory for the logs, state and control socket of cirno
+    #[arg(long, default_value = "run")]
+    run_dir: PathBuf,
+    /// Give every run a subdirectory of --run-dir named after its start time,
+    /// e.g. run/2024-06-01T12-00-00/, linked as run/latest. The history and
+    /// done markers stay in --run-dir, shared by every run
+    #[arg(long)]
+    timestamped: bool,
+    /// Signal asking a task to stop, e.g. INT for programs that save a
+    /// checkpoint on Ctrl-C [default: TERM]
+    #[arg(long, value_parser = parse_signal)]
+    stop_signal: Option<Signal>,
+    /// Seconds (or 30s, 2m) a task gets to exit after each stop signal [default: 1]
+    #[arg(long, value_parser = parse_grace)]
+    stop_grace: Option<Duration>,
+    /// How many times the stop signal is sent before SIGKILL [default: 3]
+    #[arg(long)]
+    stop_attempts: Option<usize>,
+    /// Requeue a task killed by the OOM killer up to this many times, reserving
+    /// 1.5x its peak memory for the next attempt
+    #[arg(long, default_value_t = 0)]
+    oom_retries: usize,
+    /// Run every task in its own cgroup v2 group below this directory, e.g.
+    /// /sys/fs/cgroup/cirno.slice, enabling `@mem_max=`, `@mem_high=` and `@cpu_max=`
+    #[arg(long)]
+    cgroup_parent: Option<PathBuf>,
+    /// Max utilization (percent) of a gpu that is handed to a task asking for `@gpus=N`
+    #[arg(long, default_value_t = 100)]
+    gpu_util_max: u32,
+    /// Min free space (GB) on the run directory's filesystem to launch new tasks
+    #[arg(long)]
+    min_disk_free: Option<usize>,
+    /// Extra paths whose filesystems must keep --min-disk-free, may be repeated
+    #[arg(long)]
+    disk_path: Vec<PathBuf>,
+    /// Seconds an opinion must last before cirno changes its mind
+    #[arg(long, default_value_t = 0)]
+    min_dwell: usize,
+    /// How to preempt a task when resources are short; suspended tasks keep their memory
+    #[arg(long, value_enum, default_value_t = PreemptMode::Kill)]
+    preempt_mode: PreemptMode,
+    /// Where the stderr of tasks goes
+    #[arg(long, value_enum, default_value_t = StderrMode::Separate)]
+    stderr: StderrMode,
+    /// What the logs of a task's earlier attempt become when it is launched again
+    #[arg(long, value_enum, default_value_t = LogPolicy::Truncate)]
+    log_policy: LogPolicy,
+    /// Also print the output of tasks as it comes, every line behind the time
+    /// and the task's `[name]`
+    #[arg(long)]
+    stream: bool,
+    /// Rotate the log of a task once it grows over this size (e.g. 100M),
+    /// checked every tick
+    #[arg(long, value_parser = parse_size)]
+    log_max_size: Option<u64>,
+    /// Rotated copies of a log kept as `<log>.1`, `<log>.2` and so on; with 0
+    /// the log is only emptied
+    #[arg(long, default_value_t = 3)]
+    log_keep: usize,
+    /// Compress the logs of a task once it finished, with gzip or zstd
+    #[arg(long, value_parser = Compression::parse)]
+    compress_logs: Option<Compression>,
+    /// Log more, -v for debug and -vv for trace level
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log less, -q for warnings and errors only and -qq for errors only
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+    /// How cirno logs what it does
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Which running task to preempt; by default the biggest one under memory pressure, else the newest
+    #[arg(long, value_enum)]
+    preempt_policy: Option<PreemptPolicy>,
+    /// When to launch and preempt tasks, and which
+    #[arg(long, value_enum, default_value_t = Policy::Resources)]
+    policy: Policy,
+    /// Where the tasks run; kubernetes creates a Job per task with kubectl and is experimental
+    #[arg(long, value_enum, default_value_t = BackendKind::Local)]
+    backend: BackendKind,
+    /// Namespace of the jobs with --backend kubernetes, by default that of the current context
+    #[arg(long)]
+    k8s_namespace: Option<String>,
+    /// Image of the jobs of tasks without their own `@image=`, with --backend kubernetes
+    #[arg(long)]
+    k8s_image: Option<String>,
+    /// Run tasks without their own `@host=` over ssh on the least busy of these hosts; may be
+    /// repeated or comma separated
+    #[arg(long, value_delimiter = ',')]
+    hosts: Vec<String>,
+    /// Adapt the worker cap to what the machine sustains, using --max-workers as the ceiling
+    #[arg(long)]
+    auto_tune: bool,
+    /// Admit tasks using their peak memory from previous runs instead of --per-task-mem
+    #[arg(long)]
+    adaptive_mem: bool,
+    /// Skip tasks that already have a `<name>.done` marker in --run-dir, and write one on success
+    #[arg(long)]
+    skip_done: bool,
+    /// Octal mode for created task log files, e.g. 640
+    #[arg(long)]
+    log_mode: Option<String>,
+    /// Octal mode for the run directory, e.g. 750
+    #[arg(long)]
+    run_dir_mode: Option<String>,
+    /// Owner of the run directory and log files, as user[:group] or uid[:gid]
+    #[arg(long)]
+    log_owner: Option<String>,
+    /// Do not record executions into `run/history.db`
+    #[arg(long)]
+    no_history: bool,
+    /// Log why no task was launched after every resource check that launched none
+    #[arg(long)]
+    explain: bool,
+    /// Serve Prometheus metrics at `/metrics` on this address, e.g.
+    /// 127.0.0.1:9100
+    #[arg(long)]
+    metrics_addr: Option<String>,
+    /// Notify at the end of the run, and with --notify-failures when tasks
+    /// fail; may be repeated
+    #[arg(long, value_enum)]
+    notify: Vec<NotifyMode>,
+    /// Also notify once this many tasks have failed
+    #[arg(long)]
+    notify_failures: Option<usize>,
+    /// POST JSON to this URL when the run starts and ends, and when a task
+    /// fails or times out, e.g. a Slack, Discord or Matrix webhook
+    #[arg(long, value_parser = Webhook::parse)]
+    webhook: Option<Webhook>,
+    /// Mail a summary of the run to this address when it ends; may be repeated
+    #[arg(long)]
+    mail_to: Vec<String>,
+    /// Sender of the mail, cirno@<hostname> by default
+    #[arg(long)]
+    mail_from: Option<String>,
+    /// SMTP server relaying the mail as host:port, e.g. localhost:25; without
+    /// one the mail goes through `sendmail`
+    #[arg(long)]
+    smtp_server: Option<String>,
+    /// Don't draw the progress bar on stderr, which is only drawn on a terminal
+    #[arg(long)]
+    no_progress: bool,
+    /// Serve the REST API on this address, e.g. 127.0.0.1:8081
+    #[arg(long)]
+    api_addr: Option<String>,
+    /// Token the clients of the REST API send as `Authorization: Bearer`,
+    /// generated into `api.token` in the run directory by default
+    #[arg(long, value_parser = parse_token)]
+    api_token: Option<String>,
+    /// Serve the web dashboard on this address, e.g. 127.0.0.1:8080
+    #[cfg(feature = "dashboard")]
+    #[arg(long)]
+    dashboard_addr: Option<String>,
+    /// Token of a Telegram bot answering /status, /pause, /resume, /drain
+    /// and /cancel <task> in the --telegram-chat chats
+    #[cfg(feature = "telegram")]
+    #[arg(long, requires = "telegram_chat")]
+    telegram_token: Option<String>,
+    /// Id of a chat the Telegram bot takes commands from
+    #[cfg(feature = "telegram")]
+    #[arg(long, allow_hyphen_values = true)]
+    telegram_chat: Vec<i64>,
+    /// Send a span per task to this OTLP/HTTP collector, e.g.
+    /// http://localhost:4318
+    #[arg(long, value_parser = Exporter::parse)]
+    otlp_endpoint: Option<Exporter>,
+    /// Do not write scheduler events to `events.jsonl` in the run directory
+    #[arg(long)]
+    no_events: bool,
+    /// Name recorded for this run, e.g. "resnet-sweep-v3"
+    #[arg(long)]
+    session_name: Option<String>,
+    /// Also apply the `[profile.NAME]` section of the config files, over
+    /// their top level settings
+    #[arg(long)]
+    profile: Option<String>,
+    /// Extra KEY=VALUE metadata recorded for this run, may be repeated
+    #[arg(long = "meta", value_parser = parse_key_value)]
+    metadata: Vec<(String, String)>,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got: {}", s)),
     }
 }
 
-impl Drop for Task {
-    fn drop(&mut self) {
-        let child = self.child.take();
-        // kill it
-        if let Some(mut child) = child {
-            kill_process_tree(Pid::from_child(&child), Signal::Kill).expect("Failed to drop task");
-            child.wait().expect("Failed to drop task");
-        }
+fn parse_token(s: &str) -> Result<String, String> {
+    match s.trim() {
+        "" => Err("the token can't be empty".to_string()),
+        _ => Ok(s.to_string()),
     }
-    
 }
 
-impl Task {
-    fn new(name: &str, cmd: &str) -> Task {
-        let mut count = 0;
-        let mut prog = String::new();
-        let mut args = Vec::new();
-        for token in cmd.split_whitespace() {
-            if count == 0 {
-                prog.push_str(token);
-            } else {
-                args.push(token.to_string());
-            }
+fn parse_rlimit(s: &str) -> Result<Rlimit, String> {
+    let (name, value) = parse_key_value(s)?;
+    Rlimit::parse(&name, &value)
+}
 
-            count += 1;
-        }
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show past executions recorded in the history database
+    History(HistoryArgs),
+    /// Let the tasks of a running cirno finish but start no new ones
+    Drain {
+        /// pid of the cirno to drain, the one in cirno.pid of --run-dir by default
+        pid: Option<i32>,
+        /// Run directory of the cirno, also tried as <RUN_DIR>/latest
+        #[arg(long, default_value = "run")]
+        run_dir: PathBuf,
+    },
+    /// Send a command to a running cirno, e.g. `status` or `drain`
+    Ctl(CtlArgs),
+    /// Print the end of the log of a task, the one of its latest attempt
+    Logs(LogsArgs),
+    /// List the running and suspended tasks of a running cirno
+    Ps {
+        /// Control socket of the cirno, cirno.sock of --run-dir by default
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Run directory of the cirno, also tried as <RUN_DIR>/latest
+        #[arg(long, default_value = "run")]
+        run_dir: PathBuf,
+    },
+}
 
-        let mut res = Task {
-            name: name.to_string(),
-            prog: prog.clone(),
-            args: args.clone(),
-            handler: Command::new(prog),
-            child: None,
-            start_time: SystemTime::now(),
+/// Print the tasks a running cirno has started, with the pid, how long they
+/// have been running and the memory of their process tree.
+fn show_ps(socket: &Path) {
+    let reply = control::send(socket, "ps")
+        .unwrap_or_else(|e| panic!("Failed to reach cirno at {}: {}", socket.display(), e));
+    let ps: serde_json::Value =
+        serde_json::from_str(&reply).expect("Failed to parse the reply of cirno");
+    let mut counts = vec![
+        format!("running: {}", ps["running"]),
+        format!("suspended: {}", ps["suspended"]),
+        format!("pending: {}", ps["pending"]),
+    ];
+    if let Some(outcomes) = ps["outcomes"].as_object() {
+        let mut outcomes: Vec<_> = outcomes.iter().collect();
+        outcomes.sort_by(|a, b| a.0.cmp(b.0));
+        counts.extend(
+            outcomes
+                .iter()
+                .map(|(outcome, count)| format!("{}: {}", outcome, count)),
+        );
+    }
+    let opinion = ps["opinion"].as_str().unwrap_or("none yet");
+    println!("opinion: {}, {}", opinion, counts.join(", "));
+    let tasks = ps["tasks"].as_array().cloned().unwrap_or_default();
+    if tasks.is_empty() {
+        return;
+    }
+    println!(
+        "{:>8}  {:<9}  {:>11}  {:>9}  name",
+        "pid", "state", "elapsed", "rss"
+    );
+    for task in &tasks {
+        let pid = match task["pid"].as_u64() {
+            Some(pid) => pid.to_string(),
+            None => "-".to_string(),
         };
-        res.handler.args(args);
-        res
+        println!(
+            "{:>8}  {:<9}  {:>11}  {:>7}MB  {}",
+            pid,
+            task["state"].as_str().unwrap_or("-"),
+            format_elapsed(task["elapsed"].as_f64().unwrap_or(0.0) as u64),
+            task["rss_kib"].as_u64().unwrap_or(0) / 1024,
+            task["name"].as_str().unwrap_or("-")
+        );
     }
+}
 
-    fn spawn(&mut self) {
-        if self.child.is_some() {
-            self.stop().expect("Failed to respawn process");
-        }
+#[derive(clap::Args, Debug)]
+struct LogsArgs {
+    /// Name of the task
+    task: String,
+    /// Keep printing what the task writes, across rotations and attempts
+    #[arg(short, long)]
+    follow: bool,
+    /// Lines printed from the end of the log, 0 for all
+    #[arg(short = 'n', long, default_value_t = 10)]
+    lines: usize,
+    /// Show the stderr log instead
+    #[arg(long)]
+    err: bool,
+    /// Run directory of the cirno, also tried as <RUN_DIR>/latest
+    #[arg(long, default_value = "run")]
+    run_dir: PathBuf,
+}
 
-        let p = match self.handler.spawn() {
-            Ok(p) => Some(p),
-            Err(e) => {
-                println!("Failed to spawn process: {}", e);
-                None
+/// Print the end of the log of a task, then with `--follow` what is written
+/// to it. A log that shrinks was rotated and is read again from its start;
+/// the log of a newer attempt is switched to as soon as it shows up.
+fn show_logs(args: &LogsArgs) {
+    let extension = if args.err { "err" } else { "txtlog" };
+    let dirs = [args.run_dir.clone(), args.run_dir.join("latest")];
+    let find = || {
+        dirs.iter()
+            .find_map(|dir| logfile::find(dir, &args.task, extension))
+    };
+    let mut path = find().unwrap_or_else(|| {
+        panic!(
+            "No {} log of {} in {}",
+            extension,
+            args.task,
+            args.run_dir.display()
+        )
+    });
+    // the rotated copies first, oldest to newest
+    let mut logs = logfile::rotated(&path);
+    logs.reverse();
+    logs.push(path.clone());
+    let mut lines = VecDeque::new();
+    let mut offset = 0;
+    for log in &logs {
+        let reader = logfile::open(log).expect("Failed to open log");
+        let mut reader = BufReader::new(reader);
+        offset = 0;
+        loop {
+            let mut line = Vec::new();
+            let n = reader
+                .read_until(b'\n', &mut line)
+                .expect("Failed to read log");
+            if n == 0 {
+                break;
             }
-        };
-        self.start_time = std::time::SystemTime::now();
-        self.child = p;
-    }
-
-    fn stop(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
-        let p = self.child.take();
-
-        match p {
-            Some(mut child) => {
-                let stautus = child.try_wait()?;
-                match stautus {
-                    Some(status) => {
-                        return Ok(Some(status));
-                    }
-                    None => {
-                        kill_process(Pid::from_child(&child),Signal::Term)?;
-                        // try three more times 
-                        for _ in 0..3 {
-                            std::thread::sleep(Duration::from_secs(1));
-                            match child.try_wait() {
-                                Ok(Some(status)) => {
-                                    return Ok(Some(status));
-                                }
-                                Ok(None) => {
-                                    kill_process(Pid::from_child(&child),Signal::Term)?;
-                                }
-                                Err(e) => {
-                                    return Err(e);
-                                }
-                            }
-                        }
-                        // kill it
-                        kill_process_tree(Pid::from_child(&child),Signal::Kill)?;
-                        // wait for free
-                        return Ok(Some(child.wait()?));
-                    }
-                }
+            offset += n as u64;
+            lines.push_back(line);
+            if args.lines > 0 && lines.len() > args.lines {
+                lines.pop_front();
             }
-            None => Ok(None),
         }
     }
-
-    fn try_wait(&mut self, timeout: usize) -> std::io::Result<Option<std::process::ExitStatus>> {
-        match &mut self.child {
-            Some(child) => {
-                let result = child.try_wait();
-                match result {
-                    Ok(Some(status)) => {
-                        return Ok(Some(status));
-                    }
-                    Ok(None) => {
-                        let elapsed = self.start_time.elapsed().unwrap_or(Duration::from_secs(0));
-                        if elapsed.as_secs() > timeout as u64 && timeout > 0 {
-                            println!("task: {} timeout", self.name);
-                            kill_process(Pid::from_child(&child),Signal::Alarm)?;
-
-                            // try ⑨ more times
-                            for _ in 0..9 {
-                                std::thread::sleep(Duration::from_millis(100));
-                                match child.try_wait() {
-                                    Ok(Some(status)) => {
-                                        return Ok(Some(status));
-                                    }
-                                    Ok(None) => {
-                                        kill_process(Pid::from_child(&child),Signal::Alarm)?;
-                                    }
-                                    Err(e) => {
-                                        return Err(e);
-                                    }
-                                }
-                            }
-                            // just return
-                            return Ok(None);
-                        } else {
-                            return Ok(None);
-                        }
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
-            },
-            None => Ok(None),
+    let mut stdout = io::stdout().lock();
+    for line in lines {
+        if stdout.write_all(&line).is_err() {
+            return;
         }
     }
-
-    fn stdout(&mut self, pipe: Stdio) -> &mut Self {
-        self.handler.stdout(pipe);
-        self
-    }
-
-    fn stdout_from_file(&mut self, filename: &Path) -> &mut Self {
-        filename
-            .parent()
-            .map(|p| fs::create_dir_all(p).expect("Failed to create directory"));
-        let file = fs::File::create(filename).expect("Failed to create file");
-        self.stdout(Stdio::from(file));
-        self
+    let plain = |path: &Path| path.extension() == Some(extension.as_ref());
+    if !args.follow || !plain(&path) {
+        return;
     }
-
-    fn args(&mut self, args: Vec<String>) -> &mut Self {
-        self.args = args;
-        self.handler.args(&self.args);
-        self
+    loop {
+        if stdout.flush().is_err() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+        if let Some(newest) = find().filter(|newest| *newest != path && plain(newest)) {
+            path = newest;
+            offset = 0;
+            if writeln!(stdout, "==> {} <==", path.display()).is_err() {
+                return;
+            }
+        }
+        let len = match fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(_) => continue,
+        };
+        if len < offset {
+            // what was written since the last look went to the rotated copy
+            if let Some(copy) = logfile::rotated(&path).first() {
+                if copy_from(copy, offset, u64::MAX, &mut stdout).is_err() {
+                    return;
+                }
+            }
+            offset = 0;
+        }
+        if len == offset {
+            continue;
+        }
+        match copy_from(&path, offset, len - offset, &mut stdout) {
+            Ok(n) => offset += n,
+            Err(_) => return,
+        }
     }
 }
 
-enum CirnoOpinion {
-    Health,
-    Normal,
-    Bad,
+/// Copy up to `limit` bytes of the file at `path` from `offset` on to `out`.
+fn copy_from(path: &Path, offset: u64, limit: u64, out: &mut impl Write) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut file.take(limit), out)
 }
 
-struct Scheduler {
-    todo_tasks: Vec<Task>,
-    max_workers: usize,
-    runing_tasks: Vec<Task>,
-    system: System,
-    sleep_duration: usize,
-    reserved_mem: usize,
-    per_task_mem: usize,
-    timeout: usize,
-    force_task: usize,
-    load_max: f64,
-    load_min: f64,
+#[derive(clap::Args, Debug)]
+struct CtlArgs {
+    /// Control socket of the cirno, cirno.sock of --run-dir by default
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Run directory of the cirno, also tried as <RUN_DIR>/latest
+    #[arg(long, default_value = "run")]
+    run_dir: PathBuf,
+    /// The command and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
 }
 
-impl Scheduler {
-    fn new(max_workers: usize) -> Scheduler {
-        Scheduler {
-            todo_tasks: Vec::new(),
-            max_workers,
-            runing_tasks: Vec::new(),
-            system: System::new(),
-            sleep_duration: 10,
-            reserved_mem: 6,
-            per_task_mem: 3,
-            timeout: 7200,
-            force_task: 1,
-            load_max: 2.0,
-            load_min: 1.0,
-        }
+/// Print the reply to a `ctl` command, exiting with 1 if the command failed.
+fn run_ctl(args: &CtlArgs) {
+    let mut command = args.command.clone();
+    // `ctl submit -- python extra.py` keeps the task's own flags away from clap
+    if command.get(1).map(String::as_str) == Some("--") {
+        command.remove(1);
     }
-
-    fn set_sleep_duration(&mut self, duration: usize) {
-        self.sleep_duration = duration;
+    let socket = args
+        .socket
+        .clone()
+        .unwrap_or_else(|| find_run_file(&args.run_dir, "cirno.sock"));
+    let reply = control::send(&socket, &command.join(" "))
+        .unwrap_or_else(|e| panic!("Failed to reach cirno at {}: {}", socket.display(), e));
+    print!("{}", reply);
+    if reply.starts_with("error:") {
+        std::process::exit(1);
     }
+}
 
-    fn set_reserved_mem(&mut self, mem: usize) {
-        self.reserved_mem = mem;
-    }
+#[derive(clap::Args, Debug)]
+struct HistoryArgs {
+    /// Only show runs that errored or exited unsuccessfully
+    #[arg(long)]
+    failed: bool,
+    /// Only show runs started since a UTC date (2024-06-01[T12:00]) or a duration ago (12h, 7d)
+    #[arg(long)]
+    since: Option<String>,
+    /// Only show tasks whose name matches this SQL LIKE pattern, e.g. 'train-%'
+    #[arg(long)]
+    name_like: Option<String>,
+    /// Only show runs of the sessions with this name
+    #[arg(long)]
+    session: Option<String>,
+    /// Only print the per-task statistics
+    #[arg(long)]
+    stats: bool,
+    /// List the recorded sessions and their metadata instead
+    #[arg(long)]
+    sessions: bool,
+    #[arg(long, default_value = "run/history.db")]
+    db: String,
+}
 
-    fn set_timeout(&mut self, timeout: usize) {
-        self.timeout = timeout;
-    }
+fn show_history(args: &HistoryArgs) {
+    let history = History::open(Path::new(&args.db)).expect("Failed to open history database");
+    let since = args.since.as_deref().map(|since| {
+        parse_since(since, unix_now()).expect("Invalid --since, expected a date or a duration")
+    });
+    let filter = Filter {
+        failed: args.failed,
+        since,
+        name_like: args.name_like.clone(),
+        session: args.session.clone(),
+    };
 
-    fn set_per_task_mem(&mut self, mem: usize) {
-        self.per_task_mem = mem;
+    if args.sessions {
+        let sessions = history
+            .sessions(args.session.as_deref())
+            .expect("Failed to query history");
+        for one in &sessions {
+            let end = match one.end_time {
+                Some(end) => format_utc(end, ' ', ':'),
+                None => "unfinished".to_string(),
+            };
+            let metadata: Vec<String> = one
+                .metadata
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            println!(
+                "{}  {} .. {}  {}",
+                one.name.as_deref().unwrap_or("-"),
+                format_utc(one.start_time, ' ', ':'),
+                end,
+                metadata.join(" ")
+            );
+        }
+        return;
     }
 
-    fn set_force_task(&mut self, force_task: usize) {
-        self.force_task = force_task;
+    if !args.stats {
+        let executions = history.query(&filter).expect("Failed to query history");
+        println!(
+            "{:<19}  {:>9}  {:<9}  {:>6}  {:>9}  {:<16}  name",
+            "start", "duration", "outcome", "status", "max_rss", "session"
+        );
+        for one in &executions {
+            let status = match (one.exit_code, one.signal) {
+                (Some(code), _) => code.to_string(),
+                (None, Some(signal)) => format!("sig{}", signal),
+                (None, None) => "-".to_string(),
+            };
+            println!(
+                "{:<19}  {:>8.1}s  {:<9}  {:>6}  {:>7}MB  {:<16}  {}",
+                format_utc(one.start_time, ' ', ':'),
+                one.duration(),
+                one.outcome,
+                status,
+                one.max_rss / 1024,
+                one.session.as_deref().unwrap_or("-"),
+                one.name
+            );
+        }
+        println!();
     }
 
-    fn set_load_max(&mut self, load_max: f64) {
-        self.load_max = load_max;
+    let stats = history.stats(&filter).expect("Failed to query history");
+    println!("{:>6}  {:>8}  {:>9}  name", "runs", "failed", "mean");
+    for one in &stats {
+        let mean = match one.mean_duration {
+            Some(mean) => format!("{:.1}s", mean),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:>6}  {:>7.1}%  {:>9}  {}",
+            one.runs,
+            one.failure_rate() * 100.0,
+            mean,
+            one.name
+        );
     }
+}
 
-    fn set_load_min(&mut self, load_min: f64) {
-        self.load_min = load_min;
+/// Parse the command line again behind the flags read from the config files,
+/// so that anything given on the command line wins. A setting given there is
+/// left out of the config, and one given again by a later file or a profile
+/// replaces the earlier values, so lists aren't added up. The files using
+/// deprecated names are added to `deprecated`.
+fn with_config(cli: CLIArgs, deprecated: &mut Vec<String>) -> CLIArgs {
+    let mut files = Vec::new();
+    for path in config::config_paths() {
+        let table = config::load(&path).unwrap_or_else(|e| panic!("{}", e));
+        files.push((path, table));
     }
-
-    fn submit(&mut self, task: Task) {
-        println!("submiting task: {}", task);
-        self.todo_tasks.push(task);
+    // every file's top level first, so a profile overrides all of them
+    let mut sections: Vec<_> = files.iter().map(|(path, table)| (path, table)).collect();
+    if let Some(name) = &cli.profile {
+        let len = sections.len();
+        for (path, table) in &files {
+            match config::profile(table, name) {
+                Ok(Some(profile)) => sections.push((path, profile)),
+                Ok(None) => {}
+                Err(e) => panic!("{}: {}", path.display(), e),
+            }
+        }
+        if sections.len() == len {
+            panic!("No [profile.{}] in the config files", name);
+        }
     }
 
-    fn do_it(&mut self) {
-        while self.todo_tasks.len() + self.runing_tasks.len() > 0 {
-            // check finished or timeout task
-            let mut next_runing_tasks = Vec::new();
-            for mut task in self.runing_tasks.drain(..) {
-                match task.try_wait(self.timeout) {
-                    Ok(Some(status)) => {
-                        println!("task: {} finished with status: {}", task.name, status);
-                    }
-                    Ok(None) => {
-                        next_runing_tasks.push(task);
-                    }
-                    Err(e) => {
-                        println!("task: {} failed with error: {}", task.name, e);
-                    }
-                }
+    let command = CLIArgs::command();
+    let known: Vec<(&str, &str)> = command
+        .get_arguments()
+        .flat_map(|arg| {
+            let names = arg
+                .get_long()
+                .into_iter()
+                .chain(arg.get_all_aliases().unwrap_or_default());
+            names.map(|name| (name, arg.get_id().as_str()))
+        })
+        .collect();
+    let given = CLIArgs::command().get_matches();
+    let mut config_args: Vec<(&str, String)> = Vec::new();
+    for (path, table) in sections {
+        let args =
+            config::table_args(table).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut section = Vec::new();
+        for arg in args {
+            if is_deprecated(&arg) {
+                deprecated.push(format!("in {}", path.display()));
             }
-            self.runing_tasks = next_runing_tasks;
-
-            // check cirno's opinion
-            let opinion = self.cirno_check();
-            match opinion {
-                CirnoOpinion::Health => {
-                    // try to add new task
-                    if self.todo_tasks.len() > 0 {
-                        let mut task = self.todo_tasks.pop().unwrap();
-                        task.stdout_from_file(Path::new(&format!("run/{}.txtlog", task.name)));
-                        task.spawn();
-                        println!("task: {} started", task);
-                        self.runing_tasks.push(task);
-                    }
-                    // sleep
-                    std::thread::sleep(Duration::from_secs(self.sleep_duration as u64));
-                }
-                CirnoOpinion::Normal => {
-                    // just sleep
-                    std::thread::sleep(Duration::from_secs(self.sleep_duration as u64));
-                }
-                CirnoOpinion::Bad => {
-                    // try to stop one task and sleep
-                    if self.runing_tasks.len() > self.force_task {
-                        let mut task = self.runing_tasks.pop().unwrap();
-                        println!("task: {} stopped", task.name);
-                        task.stop().expect("Failed to stop task");
-                        self.todo_tasks.push(task);
-                    }
-                    std::thread::sleep(Duration::from_secs(self.sleep_duration as u64));
-                }
+            let name = arg
+                .trim_start_matches("--")
+                .split('=')
+                .next()
+                .unwrap_or_default();
+            let id = match known.iter().find(|(known, _)| *known == name) {
+                Some((_, id)) => *id,
+                None => panic!(
+                    "Unknown setting `{}` in {}",
+                    name.replace('-', "_"),
+                    path.display()
+                ),
+            };
+            if given.value_source(id) != Some(ValueSource::CommandLine) {
+                section.push((id, arg));
             }
-
         }
+        config_args.retain(|(id, _)| !section.iter().any(|(again, _)| again == id));
+        config_args.extend(section);
     }
-
-    fn cirno_check(&mut self) -> CirnoOpinion {
-        let runing_amount = self.runing_tasks.len();
-
-        if runing_amount > self.max_workers {
-            return CirnoOpinion::Bad;
-        }
-
-        self.system.refresh_memory();
-        self.system.refresh_cpu();
-        
-        let load = self.system.load_average().five / self.system.cpus().len() as f64;
-        let free_mem = (self.system.available_memory() / (1024 * 1024 * 1024))as usize;
-
-        if free_mem < self.reserved_mem || load > self.load_max {
-            return CirnoOpinion::Bad;
-        }
-
-        if runing_amount == self.max_workers {
-            return CirnoOpinion::Normal;
-        }
-
-        if free_mem >= (self.reserved_mem + self.per_task_mem) && load <= self.load_min {
-            return CirnoOpinion::Health;
-        }
-
-        CirnoOpinion::Normal
+    if config_args.is_empty() {
+        return cli;
     }
+    let config_args = config_args
+        .into_iter()
+        .map(|(_, arg)| std::ffi::OsString::from(arg));
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap_or_default();
+    CLIArgs::parse_from(std::iter::once(argv0).chain(config_args).chain(args))
 }
 
-fn init_runtime(dirname: &str) {
-    fs::create_dir_all(dirname).expect("Failed to create runtime directory");
+/// One worker per physical core.
+fn default_max_workers() -> usize {
+    System::new()
+        .physical_core_count()
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
 }
 
-fn gen_tasks_from_file(filename: &Path) -> Vec<Task> {
-    let contents = fs::read_to_string(filename).expect("Failed to read task list");
-    let contents = contents.trim();
-    if contents.len() == 0 {
-        return Vec::new();
-    }
-    let mut task_list = Vec::new();
-    for line in contents.split("\n") {
-        let name: &str = line
-            .split_whitespace()
-            .collect::<Vec<&str>>()
-            .last()
-            .unwrap();
-        let task = Task::new(name, line);
-        println!("generate task from: {line}");
-        task_list.push(task);
-    }
-
-    return task_list;
+/// A tenth of the total memory, in GiB and at least one.
+fn default_reserved_mem() -> usize {
+    let mut system = System::new();
+    system.refresh_memory();
+    let total = system.total_memory() / (1024 * 1024 * 1024);
+    (total as usize).div_ceil(10).max(1)
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct CLIArgs {
-    input_list: String,
-    #[arg(short, long)]
-    max_workers: usize,
-    #[arg(short, long)]
-    force_task: usize,
-    #[arg(short, long)]
-    sleep_duartion: usize,
-    #[arg(short, long)]
-    reserved_mem: usize,
-    #[arg(short, long)]
-    per_task_mem: usize,
-    #[arg(short, long)]
-    timeout: usize,
-    #[arg(long)]
-    load_max: Option<f64>,
-    #[arg(long)]
-    load_min: Option<f64>,
+/// Whether `arg` is `--sleep-duartion`, the misspelt name `--sleep-duration`
+/// used to have.
+fn is_deprecated(arg: &str) -> bool {
+    arg.split('=').next() == Some("--sleep-duartion")
+}
+
+/// Log scheduler events to stdout, at info level unless -v or -q move it.
+fn init_logging(verbose: u8, quiet: u8, format: LogFormat) {
+    let level = match 2 + verbose as i32 - quiet as i32 {
+        i32::MIN..=0 => LevelFilter::ERROR,
+        1 => LevelFilter::WARN,
+        2 => LevelFilter::INFO,
+        3 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stdout()))
+        .with_writer(|| progress::Stdout);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
 fn main() {
     // parse args
     let cli = CLIArgs::parse();
-    let input_filename = &cli.input_list;
+    match &cli.command {
+        Some(Commands::History(args)) => {
+            show_history(args);
+            return;
+        }
+        Some(Commands::Ctl(args)) => {
+            run_ctl(args);
+            return;
+        }
+        Some(Commands::Logs(args)) => {
+            show_logs(args);
+            return;
+        }
+        Some(Commands::Ps { socket, run_dir }) => {
+            let socket = socket
+                .clone()
+                .unwrap_or_else(|| find_run_file(run_dir, "cirno.sock"));
+            show_ps(&socket);
+            return;
+        }
+        Some(Commands::Drain { pid, run_dir }) => {
+            let pid_path = find_run_file(run_dir, "cirno.pid");
+            let pid = pid
+                .or_else(|| read_pid(&pid_path))
+                .unwrap_or_else(|| panic!("No pid given and no {}", pid_path.display()));
+            let pid = Pid::from_raw(pid).expect("Invalid pid");
+            kill_process(pid, Signal::Usr2).expect("Failed to signal cirno");
+            return;
+        }
+        None => {}
+    }
+    let mut deprecated = Vec::new();
+    if std::env::args().skip(1).any(|arg| is_deprecated(&arg)) {
+        deprecated.push("on the command line".to_string());
+    }
+    let cli = with_config(cli, &mut deprecated);
+    init_logging(cli.verbose, cli.quiet, cli.log_format);
+    for source in deprecated {
+        warn!(
+            "--sleep-duartion {} is deprecated, use --sleep-duration",
+            source
+        );
+    }
 
     // init runtime dir
-    init_runtime("run");
+    let owner = cli
+        .log_owner
+        .as_deref()
+        .map(|owner| parse_owner(owner).expect("Invalid --log-owner"));
+    let dir_access = FileAccess {
+        mode: cli
+            .run_dir_mode
+            .as_deref()
+            .map(|mode| parse_mode(mode).expect("Invalid --run-dir-mode")),
+        owner,
+    };
+    let log_access = FileAccess {
+        mode: cli
+            .log_mode
+            .as_deref()
+            .map(|mode| parse_mode(mode).expect("Invalid --log-mode")),
+        owner,
+    };
+    init_runtime(&cli.run_dir, &dir_access);
+    let run_dir = if cli.timestamped {
+        let name = format_utc(unix_now(), 'T', '-');
+        let run_dir = cli.run_dir.join(&name);
+        init_runtime(&run_dir, &dir_access);
+        if let Err(e) = link_latest(&cli.run_dir, &name) {
+            warn!("failed to link {}/latest: {}", cli.run_dir.display(), e);
+        }
+        run_dir
+    } else {
+        cli.run_dir.clone()
+    };
+    // two schedulers in one run directory would mix up their logs and state
+    let pid_path = run_dir.join("cirno.pid");
+    let mut pid_file = match PidFile::lock(&pid_path) {
+        Ok(pid_file) => pid_file,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+            let pid = read_pid(&pid_path)
+                .map(|pid| pid.to_string())
+                .unwrap_or("unknown".to_string());
+            error!(
+                "another cirno (pid {}) is running in {}, use `cirno-rs ctl` to control it",
+                pid,
+                run_dir.display()
+            );
+            std::process::exit(1);
+        }
+        Err(e) => panic!("Failed to lock {}: {}", pid_path.display(), e),
+    };
+    if cli.daemon {
+        let log = run_dir.join("cirno.log");
+        daemonize(&log).expect("Failed to daemonize");
+        // the lock is shared with the forked child, only the pid changed
+        pid_file.write_pid().expect("Failed to write cirno.pid");
+        if let Err(e) = log_access.apply(&log) {
+            warn!("failed to set permissions of {}: {}", log.display(), e);
+        }
+    }
+
+    if let Some(name) = &cli.session_name {
+        info!("session: {}", name);
+    }
+    for (key, value) in &cli.metadata {
+        info!("session {}: {}", key, value);
+    }
 
     let mut scheduler = Scheduler::new(cli.max_workers);
-    scheduler.set_sleep_duration(cli.sleep_duartion);
+    scheduler.set_sleep_duration(cli.sleep_duration);
+    if let Some(interval) = cli.probe_interval {
+        scheduler.set_probe_interval(interval);
+    }
     scheduler.set_reserved_mem(cli.reserved_mem);
     scheduler.set_per_task_mem(cli.per_task_mem);
     scheduler.set_timeout(cli.timeout);
+    scheduler.set_timeout_escalation(cli.timeout_signal, cli.timeout_grace);
     scheduler.set_force_task(cli.force_task);
     if let Some(load_max) = cli.load_max {
         scheduler.set_load_max(load_max);
@@ -415,9 +963,209 @@ fn main() {
         scheduler.set_load_min(load_min);
     }
 
-    for one in gen_tasks_from_file(Path::new(input_filename)) {
-        scheduler.submit(one);
+    scheduler.set_preempt_mode(cli.preempt_mode);
+    scheduler.set_stderr_mode(cli.stderr);
+    scheduler.set_log_policy(cli.log_policy);
+    scheduler.set_stream(cli.stream);
+    scheduler.set_explain(cli.explain);
+    if let Some(max_size) = cli.log_max_size {
+        scheduler.set_log_rotation(max_size, cli.log_keep);
+    }
+    if let Some(compression) = cli.compress_logs {
+        scheduler.set_compress_logs(compression);
+    }
+    if let Some(alpha) = cli.load_smoothing {
+        scheduler.set_load_smoothing(alpha);
+    }
+    scheduler.set_min_dwell(cli.min_dwell);
+    scheduler.set_pressure_max(cli.psi_cpu_max, cli.psi_memory_max, cli.psi_io_max);
+    scheduler.set_max_swap_used(cli.max_swap_used);
+    scheduler.set_max_swapin_rate(cli.max_swapin_rate);
+    if let Some(max) = cli.io_max {
+        scheduler.set_io_max(max, cli.io_device.clone());
+    }
+    scheduler.set_gpu_util_max(cli.gpu_util_max);
+    let mut pre_exec = PreExec::default();
+    pre_exec.nice = cli.nice;
+    pre_exec.ionice = cli
+        .ionice
+        .as_deref()
+        .map(|ionice| IoPriority::parse(ionice).expect("Invalid --ionice"));
+    pre_exec.oom_score_adj = cli
+        .oom_score_adj
+        .as_deref()
+        .map(|adj| parse_oom_score_adj(adj).expect("Invalid --oom-score-adj"));
+    for limit in &cli.rlimit {
+        pre_exec.set_rlimit(*limit);
+    }
+    pre_exec.unshare = cli.unshare;
+    if cli.keep_tasks_on_exit {
+        pre_exec.death_signal = None;
+    }
+    scheduler.set_default_pre_exec(pre_exec);
+    scheduler.set_stop_policy(StopPolicy {
+        signal: cli.stop_signal,
+        grace: cli.stop_grace,
+        attempts: cli.stop_attempts,
+    });
+    if let Some(parent) = &cli.cgroup_parent {
+        scheduler.set_cgroup_parent(parent.clone());
+    }
+    if let Some(max) = cli.temp_max {
+        scheduler.set_temp_max(max);
+    }
+    if let Some(min) = cli.min_disk_free {
+        scheduler.set_min_disk_free(min, cli.disk_path.clone());
+    }
+    if let Some(policy) = cli.preempt_policy {
+        scheduler.set_preempt_policy(policy);
+    }
+    scheduler.set_policy(cli.policy.build());
+    if cli.backend == BackendKind::Kubernetes {
+        scheduler.set_backend(Box::new(KubernetesBackend::new(
+            cli.k8s_namespace.clone(),
+            cli.k8s_image.clone(),
+        )));
+    }
+    scheduler.set_hosts(cli.hosts.clone());
+    scheduler.set_auto_tune(cli.auto_tune);
+    scheduler.set_adaptive_mem(cli.adaptive_mem);
+    scheduler.set_oom_retries(cli.oom_retries);
+    if cli.subreaper {
+        scheduler.set_subreaper();
+    }
+    scheduler.set_kill_leftovers(cli.kill_leftovers);
+    scheduler.set_run_dir(run_dir.clone(), cli.run_dir.clone());
+    scheduler.set_skip_done(cli.skip_done);
+    if !cli.no_events {
+        let path = run_dir.join("events.jsonl");
+        match EventLog::open(&path) {
+            Ok(events) => {
+                if let Err(e) = log_access.apply(&path) {
+                    warn!("failed to set permissions of {}: {}", path.display(), e);
+                }
+                scheduler.set_events(events);
+            }
+            Err(e) => warn!("failed to open {}: {}", path.display(), e),
+        }
+    }
+    scheduler.set_log_access(log_access);
+    scheduler.set_session(cli.session_name.clone(), cli.metadata.clone());
+    if !cli.no_history {
+        let history = History::open(&cli.run_dir.join("history.db")).and_then(|history| {
+            let session =
+                history.begin_session(cli.session_name.as_deref(), unix_now(), &cli.metadata)?;
+            Ok((history, session))
+        });
+        match history {
+            Ok((history, session)) => scheduler.set_history(history, session),
+            Err(e) => warn!("failed to open history database: {}", e),
+        }
+    }
+
+    if let Some(input_filename) = &cli.input_list {
+        let contents = fs::read_to_string(input_filename).expect("Failed to read task list");
+        for one in gen_tasks(&contents).unwrap_or_else(|e| panic!("{}", e)) {
+            scheduler.submit(one);
+        }
+        scheduler.set_task_list(Path::new(input_filename), &contents);
+    }
+    match ControlSocket::bind(&run_dir.join("cirno.sock")) {
+        Ok(control) => scheduler.set_control(control),
+        Err(e) => warn!("no control socket: {}", e),
+    }
+    if let Some(tracer) = &cli.otlp_endpoint {
+        scheduler.set_tracer(tracer.clone());
+    }
+    scheduler.set_progress(!cli.no_progress && progress::supported());
+    scheduler.set_notify(cli.notify.clone(), cli.notify_failures);
+    if let Some(webhook) = &cli.webhook {
+        scheduler.set_webhook(webhook.clone());
+    }
+    if !cli.mail_to.is_empty() {
+        let from = cli.mail_from.clone().unwrap_or_else(|| {
+            let host = System::new().host_name().unwrap_or("localhost".to_string());
+            format!("cirno@{}", host)
+        });
+        scheduler.set_mailer(Mailer {
+            to: cli.mail_to.clone(),
+            from,
+            server: cli.smtp_server.clone(),
+        });
+    }
+    if let Some(addr) = &cli.api_addr {
+        let token = match &cli.api_token {
+            Some(token) => token.clone(),
+            None => {
+                let token = random_hex(16);
+                let path = run_dir.join("api.token");
+                // readable by the owner only from the start
+                let _ = fs::remove_file(&path);
+                let written = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&path)
+                    .and_then(|mut file| writeln!(file, "{}", token));
+                match written {
+                    Ok(()) => info!("api token written to {}", path.display()),
+                    Err(e) => warn!("failed to write {}: {}", path.display(), e),
+                }
+                token
+            }
+        };
+        match HttpServer::bind(addr) {
+            Ok(server) => scheduler.set_api(server, token),
+            Err(e) => warn!("no api on {}: {}", addr, e),
+        }
+    }
+    #[cfg(feature = "dashboard")]
+    if let Some(addr) = &cli.dashboard_addr {
+        match HttpServer::bind(addr) {
+            Ok(server) => scheduler.set_dashboard(server),
+            Err(e) => warn!("no dashboard on {}: {}", addr, e),
+        }
+    }
+    #[cfg(feature = "telegram")]
+    if let Some(token) = &cli.telegram_token {
+        telegram::spawn(
+            token.clone(),
+            cli.telegram_chat.clone(),
+            run_dir.join("cirno.sock"),
+        );
+    }
+    if let Some(addr) = &cli.metrics_addr {
+        match HttpServer::bind(addr) {
+            Ok(server) => scheduler.set_metrics(server),
+            Err(e) => warn!("no metrics endpoint on {}: {}", addr, e),
+        }
+    }
+    if let Some(dir) = &cli.watch {
+        scheduler.set_watch(DirWatch::new(dir).expect("Failed to watch --watch directory"));
+    }
+
+    let handled = [
+        libc::SIGCHLD,
+        libc::SIGINT,
+        libc::SIGTERM,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+        libc::SIGHUP,
+        libc::SIGTSTP,
+        libc::SIGCONT,
+    ];
+    if let Err(e) = signals::install(&handled) {
+        warn!(
+            "failed to install signal handlers, exits are noticed once per tick: {}",
+            e
+        );
     }
 
+    if systemd::is_notify() {
+        scheduler.set_systemd();
+        if let Err(e) = systemd::notify("READY=1") {
+            warn!("failed to notify systemd: {}", e);
+        }
+    }
     scheduler.do_it();
 }
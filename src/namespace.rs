@@ -0,0 +1,234 @@
+use std::io;
+
+/// The namespaces a task gets of its own, see `unshare(2)`. All of them
+/// need root.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Namespaces {
+    /// its own mounts, and with `pid` its own `/proc`
+    pub mount: bool,
+    /// its own pids: the task can't see or signal other processes, and
+    /// whatever it leaves behind is killed when it exits
+    pub pid: bool,
+    /// its own network with nothing but the loopback device
+    pub net: bool,
+}
+
+impl Namespaces {
+    /// Parse a comma separated list of `mount`, `pid` and `net`, or `none`.
+    pub fn parse(s: &str) -> Result<Namespaces, String> {
+        let mut namespaces = Namespaces::default();
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "mount" => namespaces.mount = true,
+                "pid" => namespaces.pid = true,
+                "net" => namespaces.net = true,
+                "none" => {}
+                _ => return Err(format!("unknown namespace: {}", name)),
+            }
+        }
+        Ok(namespaces)
+    }
+
+    /// Move the calling process into the new namespaces. Runs between fork
+    /// and exec, so only async-signal-safe calls belong here.
+    ///
+    /// A new pid namespace is only entered by the children of whoever
+    /// unshares it, so with `pid` this forks twice: the first child becomes
+    /// the init of the namespace, reaping orphans and taking everything left
+    /// along when it exits, the second returns to exec the task. The process
+    /// cirno started stays outside and exits the way the task did.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        let mut flags = 0;
+        if self.mount {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if self.pid {
+            flags |= libc::CLONE_NEWPID;
+        }
+        if self.net {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if flags == 0 {
+            return Ok(());
+        }
+        if unsafe { libc::unshare(flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut status = [-1; 2];
+        if self.pid {
+            if unsafe { libc::pipe2(status.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            match unsafe { libc::fork() } {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                init => outside(init, status[0]),
+            }
+            unsafe { libc::close(status[0]) };
+            // die with the process outside, which takes the namespace along
+            if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL as libc::c_ulong) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if self.mount {
+            // mounts made by the task stay in its namespace
+            let root = b"/\0";
+            let ret = unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    root.as_ptr() as *const libc::c_char,
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if self.pid {
+                mount_proc()?;
+            }
+        }
+        if self.net {
+            loopback_up()?;
+        }
+        if self.pid {
+            match unsafe { libc::fork() } {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                task => init(task, status[1]),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        if *self == Namespaces::default() {
+            return Ok(());
+        }
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+}
+
+/// A `/proc` that only shows the processes of the namespace.
+#[cfg(target_os = "linux")]
+fn mount_proc() -> io::Result<()> {
+    let proc = b"proc\0";
+    let target = b"/proc\0";
+    let ret = unsafe {
+        libc::mount(
+            proc.as_ptr() as *const libc::c_char,
+            target.as_ptr() as *const libc::c_char,
+            proc.as_ptr() as *const libc::c_char,
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A new network namespace starts with its loopback device down.
+#[cfg(target_os = "linux")]
+fn loopback_up() -> io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (to, from) in request.ifr_name.iter_mut().zip(b"lo") {
+        *to = *from as libc::c_char;
+    }
+    let mut ret = unsafe { libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request) };
+    if ret == 0 {
+        unsafe { request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+        ret = unsafe { libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request) };
+    }
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(socket) };
+    if ret != 0 {
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The process cirno waits for: wait for the init of the namespace, then
+/// exit with the status of the task it reports through `status`, or its own
+/// if it was killed before.
+#[cfg(target_os = "linux")]
+fn outside(init: libc::pid_t, status: libc::c_int) -> ! {
+    close_fds_except(status);
+    // signals for the task reach it through the process group
+    for sig in 1..32 {
+        if sig != libc::SIGKILL && sig != libc::SIGSTOP && sig != libc::SIGCHLD {
+            unsafe { libc::signal(sig, libc::SIG_IGN) };
+        }
+    }
+    let mut own = 0;
+    while unsafe { libc::waitpid(init, &mut own, 0) } < 0 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            unsafe { libc::_exit(1) };
+        }
+    }
+    let mut task = [0u8; 4];
+    let read = unsafe { libc::read(status, task.as_mut_ptr() as *mut libc::c_void, task.len()) };
+    let status = if read == task.len() as isize {
+        libc::c_int::from_ne_bytes(task)
+    } else {
+        own
+    };
+    if libc::WIFSIGNALED(status) {
+        let sig = libc::WTERMSIG(status);
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            libc::kill(libc::getpid(), sig);
+        }
+        unsafe { libc::_exit(128 + sig) };
+    }
+    unsafe { libc::_exit(libc::WEXITSTATUS(status)) }
+}
+
+/// The init of the namespace: reap whatever exits until the task does,
+/// report how it ended through `status` and exit, which kills the rest.
+#[cfg(target_os = "linux")]
+fn init(task: libc::pid_t, status: libc::c_int) -> ! {
+    close_fds_except(status);
+    loop {
+        let mut exited = 0;
+        let pid = unsafe { libc::waitpid(-1, &mut exited, 0) };
+        if pid == task {
+            let bytes = exited.to_ne_bytes();
+            unsafe { libc::write(status, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+            unsafe { libc::_exit(0) };
+        }
+        if pid < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            unsafe { libc::_exit(1) };
+        }
+    }
+}
+
+/// Close every fd but stdio and `keep`. Above all the pipe `Command::spawn`
+/// waits on until the task execs, which these processes never do.
+#[cfg(target_os = "linux")]
+fn close_fds_except(keep: libc::c_int) {
+    if keep > 3 {
+        close_range(3, keep as libc::c_uint - 1);
+    }
+    close_range(keep as libc::c_uint + 1, libc::c_uint::MAX);
+}
+
+#[cfg(target_os = "linux")]
+fn close_range(first: libc::c_uint, last: libc::c_uint) {
+    if unsafe { libc::syscall(libc::SYS_close_range, first, last, 0) } == 0 {
+        return;
+    }
+    // close_range(2) is only there since Linux 5.9
+    let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(0, 1 << 20) as libc::c_uint;
+    for fd in first..=last.min(max) {
+        unsafe { libc::close(fd as libc::c_int) };
+    }
+}
@@ -0,0 +1,25 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Show a desktop notification with `notify-send` from libnotify, waiting
+/// for it to be handed to the notification daemon.
+pub fn desktop(summary: &str, body: &str) -> io::Result<()> {
+    let status = Command::new("notify-send")
+        .args(["--app-name=cirno", summary, body])
+        .stdin(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "notify-send exited with {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Ring the terminal bell.
+pub fn bell() -> io::Result<()> {
+    let mut stderr = io::stderr();
+    stderr.write_all(b"\x07")?;
+    stderr.flush()
+}
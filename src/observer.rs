@@ -0,0 +1,24 @@
+use std::process::ExitStatus;
+
+use crate::scheduler::{CirnoOpinion, PreemptMode};
+use crate::task::Task;
+
+/// Told by the scheduler what it does, for custom logging, metrics or
+/// notifications. Every method does nothing by default.
+pub trait SchedulerObserver {
+    /// An attempt of `task` was spawned.
+    fn on_task_start(&mut self, _task: &Task) {}
+
+    /// An attempt of `task` ended with `outcome`, e.g. `finished`, `oom`,
+    /// `interrupted` or `cancelled`, and `status` if it exited. A task
+    /// killed by an OOM with retries left is queued again after this.
+    fn on_task_finish(&mut self, _task: &Task, _outcome: &str, _status: Option<ExitStatus>) {}
+
+    /// `task` was killed to be queued again, or suspended, as `mode` says,
+    /// because resources ran short.
+    fn on_task_preempted(&mut self, _task: &Task, _mode: PreemptMode) {}
+
+    /// The opinion of the resources changed from `previous`, none at the
+    /// first check, to `opinion`.
+    fn on_opinion_change(&mut self, _previous: Option<CirnoOpinion>, _opinion: CirnoOpinion) {}
+}
@@ -0,0 +1,190 @@
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::http::random_hex;
+
+/// How long the collector gets to take a span.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where spans are sent, an OTLP/HTTP collector taking JSON such as Jaeger
+/// or Tempo. All spans of a run belong to one trace.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    /// `host:port` to connect to, and the path spans are posted to
+    addr: String,
+    path: String,
+    trace_id: String,
+}
+
+/// A task from its first start until it leaves cirno, with what happened in
+/// between as events.
+#[derive(Debug)]
+pub struct Span {
+    trace_id: String,
+    span_id: String,
+    name: String,
+    start: u64,
+    attributes: Vec<Value>,
+    events: Vec<Value>,
+}
+
+impl Exporter {
+    /// Parse an endpoint such as `http://localhost:4318`; spans go to its
+    /// `/v1/traces` unless the path already ends there.
+    pub fn parse(s: &str) -> Result<Exporter, String> {
+        let rest = match s.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if s.starts_with("https://") => {
+                return Err("https endpoints are not supported".to_string())
+            }
+            None => s,
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(format!("no host in endpoint: {}", s));
+        }
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:4318", host)
+        };
+        let path = if path.ends_with("/v1/traces") {
+            path.to_string()
+        } else {
+            format!("{}/v1/traces", path)
+        };
+        Ok(Exporter {
+            addr,
+            path,
+            trace_id: random_hex(16),
+        })
+    }
+
+    /// Open the span of the task `name`, starting now.
+    pub fn start(&self, name: &str) -> Span {
+        Span {
+            trace_id: self.trace_id.clone(),
+            span_id: random_hex(8),
+            name: name.to_string(),
+            start: now_nanos(),
+            attributes: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Close `span` now and post it to the collector. Blocks until the
+    /// collector answers, so better run off the scheduling loop.
+    pub fn export(&self, span: Span, ok: bool) -> io::Result<()> {
+        let span = json!({
+            "traceId": span.trace_id,
+            "spanId": span.span_id,
+            "name": span.name,
+            // internal
+            "kind": 1,
+            "startTimeUnixNano": span.start.to_string(),
+            "endTimeUnixNano": now_nanos().to_string(),
+            "attributes": span.attributes,
+            "events": span.events,
+            "status": { "code": if ok { 1 } else { 2 } },
+        });
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [attribute("service.name", &json!("cirno"))] },
+                "scopeSpans": [{
+                    "scope": { "name": "cirno-rs", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": [span],
+                }],
+            }],
+        })
+        .to_string();
+        post(&self.addr, &self.path, &body)
+    }
+}
+
+impl Span {
+    /// The W3C `traceparent` of the span, given to the task so that its own
+    /// spans become children of it.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// Set the attribute `key`, replacing an earlier value.
+    pub fn set(&mut self, key: &str, value: Value) {
+        self.attributes.retain(|attribute| attribute["key"] != key);
+        self.attributes.push(attribute(key, &value));
+    }
+
+    /// Add the event `name` with the fields of `fields`, which should be an
+    /// object, happening now.
+    pub fn event(&mut self, name: &str, fields: &Value) {
+        let attributes: Vec<Value> = match fields {
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| attribute(key, value))
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.events.push(json!({
+            "timeUnixNano": now_nanos().to_string(),
+            "name": name,
+            "attributes": attributes,
+        }));
+    }
+}
+
+/// A key value pair in the OTLP encoding, which spells out the type.
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        // 64 bit integers are strings in OTLP JSON
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(values) => {
+            json!({ "arrayValue": { "values": values.iter().map(any_value).collect::<Vec<_>>() } })
+        }
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+fn post(addr: &str, path: &str, body: &str) -> io::Result<()> {
+    let socket = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", addr))
+    })?;
+    let mut stream = TcpStream::connect_timeout(&socket, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        addr,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "collector answered: {}",
+            status.trim()
+        ))),
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
@@ -0,0 +1,42 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+/// A pid file locked with `flock(2)` for as long as it is kept. The lock goes
+/// away with the process, so a file left by a crashed cirno doesn't count.
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+}
+
+impl PidFile {
+    /// Lock `path` and write this process' pid into it. Fails with
+    /// `WouldBlock` while another process holds the lock.
+    pub fn lock(path: &Path) -> io::Result<PidFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut pid_file = PidFile { file };
+        pid_file.write_pid()?;
+        Ok(pid_file)
+    }
+
+    /// Write this process' pid again, for a forked child that keeps the lock.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())
+    }
+}
+
+/// The pid written to `path`.
+pub fn read_pid(path: &Path) -> Option<i32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+
+use crate::scheduler::CirnoOpinion;
+use crate::task::Task;
+
+/// What the scheduler found at the last resource check, for a policy to
+/// decide on.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    /// the resource checks over their threshold
+    pub bad: Vec<&'static str>,
+    /// the resource checks not yet low enough to launch another task
+    pub not_calm: Vec<&'static str>,
+    /// cores taken by the running tasks, and how many cirno hands out
+    pub reserved_cores: usize,
+    pub worker_cap: usize,
+    /// available memory, the part kept free and the default need of a
+    /// task, all in KiB
+    pub free_kib: u64,
+    pub reserved_kib: u64,
+    pub per_task_kib: u64,
+    /// whether memory or swap ran short
+    pub low_memory: bool,
+}
+
+/// When the scheduler launches, waits or preempts, and which tasks it
+/// launches and preempts. Every method defaults to what cirno does without
+/// `--policy`.
+///
+/// The worker cap, free disk space and draining are enforced by the scheduler
+/// whatever the policy says.
+pub trait SchedulingPolicy {
+    /// Health to launch a task, Normal to wait and Bad to preempt one. By
+    /// default Bad if any check is over its threshold, Health once all are
+    /// calm.
+    fn opinion(&mut self, conditions: &Conditions) -> CirnoOpinion {
+        if !conditions.bad.is_empty() {
+            CirnoOpinion::Bad
+        } else if conditions.not_calm.is_empty() {
+            CirnoOpinion::Health
+        } else {
+            CirnoOpinion::Normal
+        }
+    }
+
+    /// Index into `running`, which is never empty, of the task to preempt.
+    /// By default the biggest one when memory ran short, else the newest.
+    /// `--preempt-policy` takes precedence.
+    fn victim(&mut self, running: &[Task], conditions: &Conditions) -> usize {
+        if conditions.low_memory {
+            if let Some(i) = biggest(running) {
+                return i;
+            }
+        }
+        running.len() - 1
+    }
+
+    /// Indices into `waiting` in the order they are tried, skipping those
+    /// without enough free cores or gpus. By default the newest first.
+    fn candidates(&mut self, waiting: &[Task], _conditions: &Conditions) -> Vec<usize> {
+        (0..waiting.len()).rev().collect()
+    }
+}
+
+/// Index of the task using the most memory.
+fn biggest(tasks: &[Task]) -> Option<usize> {
+    tasks
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, task)| task.rss())
+        .map(|(i, _)| i)
+}
+
+/// The policies `--policy` chooses from.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// launch while the resources are calm, preempt once one is short
+    Resources,
+    /// run up to --max-workers whatever the resources, never preempt
+    Strict,
+    /// like resources, but launch the biggest task that fits in free memory
+    BinPack,
+}
+
+impl Policy {
+    pub fn build(self) -> Box<dyn SchedulingPolicy> {
+        match self {
+            Policy::Resources => Box::new(ResourcePolicy),
+            Policy::Strict => Box::new(StrictPolicy),
+            Policy::BinPack => Box::new(BinPackPolicy),
+        }
+    }
+}
+
+/// Launches while the resources are calm and preempts once one is short,
+/// the default.
+pub struct ResourcePolicy;
+
+impl SchedulingPolicy for ResourcePolicy {}
+
+/// Runs as many tasks as the worker cap allows regardless of the resources.
+pub struct StrictPolicy;
+
+impl SchedulingPolicy for StrictPolicy {
+    fn opinion(&mut self, _conditions: &Conditions) -> CirnoOpinion {
+        CirnoOpinion::Health
+    }
+}
+
+/// Packs memory: launches the biggest waiting task expected to fit in the
+/// free memory, and preempts the biggest running one.
+pub struct BinPackPolicy;
+
+impl BinPackPolicy {
+    /// What `task` is expected to need in KiB, going by its last attempt.
+    fn need(task: &Task, conditions: &Conditions) -> u64 {
+        task.mem_reserve()
+            .or(Some(task.peak_rss()).filter(|peak| *peak > 0))
+            .unwrap_or(conditions.per_task_kib)
+    }
+}
+
+impl SchedulingPolicy for BinPackPolicy {
+    fn victim(&mut self, running: &[Task], _conditions: &Conditions) -> usize {
+        biggest(running).unwrap_or(running.len() - 1)
+    }
+
+    fn candidates(&mut self, waiting: &[Task], conditions: &Conditions) -> Vec<usize> {
+        let room = conditions.free_kib.saturating_sub(conditions.reserved_kib);
+        let mut order: Vec<usize> = (0..waiting.len()).rev().collect();
+        // those that fit first, the biggest of them first, the newest among equals
+        order.sort_by_key(|&i| {
+            let need = BinPackPolicy::need(&waiting[i], conditions);
+            (need > room, std::cmp::Reverse(need))
+        });
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::TaskBuilder;
+
+    /// Tasks using `rss` KiB each.
+    fn tasks(rss: &[u64]) -> Vec<Task> {
+        rss.iter()
+            .enumerate()
+            .map(|(i, rss)| {
+                let mut task = TaskBuilder::new(&format!("task{}", i), "true").build();
+                task.rss = *rss;
+                task
+            })
+            .collect()
+    }
+
+    #[test]
+    fn victim_is_the_biggest_task_when_memory_ran_short() {
+        let running = tasks(&[100, 300, 200]);
+        let low_memory = Conditions {
+            low_memory: true,
+            ..Default::default()
+        };
+        assert_eq!(ResourcePolicy.victim(&running, &low_memory), 1);
+        assert_eq!(BinPackPolicy.victim(&running, &Conditions::default()), 1);
+    }
+
+    #[test]
+    fn victim_is_the_newest_task_by_default() {
+        let running = tasks(&[100, 300, 200]);
+        assert_eq!(ResourcePolicy.victim(&running, &Conditions::default()), 2);
+        assert_eq!(StrictPolicy.victim(&running, &Conditions::default()), 2);
+    }
+
+    #[test]
+    fn bin_packing_tries_the_biggest_task_that_fits_first() {
+        let mut waiting = tasks(&[0, 0, 0, 0, 0]);
+        // by reservation, by the peak of the last attempt, or the default need
+        waiting[0].mem_reserve = Some(300);
+        waiting[1].peak_rss = 900;
+        waiting[2].peak_rss = 500;
+        waiting[4].mem_reserve = Some(500);
+        let conditions = Conditions {
+            free_kib: 700,
+            reserved_kib: 100,
+            per_task_kib: 200,
+            ..Default::default()
+        };
+        assert_eq!(
+            BinPackPolicy.candidates(&waiting, &conditions),
+            vec![4, 2, 0, 3, 1]
+        );
+        assert_eq!(
+            ResourcePolicy.candidates(&waiting, &conditions),
+            vec![4, 3, 2, 1, 0]
+        );
+    }
+}
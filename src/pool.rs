@@ -0,0 +1,97 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tracing::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A small pool for blocking work that must not stall the scheduling loop.
+///
+/// Threads are only created when a job is submitted and every existing
+/// worker is busy, up to `max_workers` threads.
+pub struct WorkerPool {
+    max_workers: usize,
+    sender: Option<Sender<Job>>,
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    idle: Arc<AtomicUsize>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(max_workers: usize) -> WorkerPool {
+        let (sender, receiver) = channel();
+        WorkerPool {
+            max_workers: max_workers.max(1),
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            idle: Arc::new(AtomicUsize::new(0)),
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn execute<F>(&mut self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.idle.load(Ordering::SeqCst) == 0 && self.workers.len() < self.max_workers {
+            self.spawn_worker();
+        }
+        if let Some(sender) = &self.sender {
+            sender.send(Box::new(job)).expect("Worker pool is gone");
+        }
+    }
+
+    fn spawn_worker(&mut self) {
+        let receiver = Arc::clone(&self.receiver);
+        let idle = Arc::clone(&self.idle);
+        let handle = std::thread::spawn(move || loop {
+            idle.fetch_add(1, Ordering::SeqCst);
+            let job = receiver.lock().unwrap().recv();
+            idle.fetch_sub(1, Ordering::SeqCst);
+            match job {
+                // a worker that died with its job would still count towards
+                // `max_workers`, and jobs would queue up behind it forever
+                Ok(job) => {
+                    if let Err(panic) = catch_unwind(AssertUnwindSafe(job)) {
+                        let message = panic
+                            .downcast_ref::<&str>()
+                            .copied()
+                            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                            .unwrap_or("unknown cause");
+                        error!("a pool job panicked: {}", message);
+                    }
+                }
+                Err(_) => break,
+            }
+        });
+        self.workers.push(handle);
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // closing the channel lets every worker finish its queue and exit
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn a_panicking_job_leaves_its_worker_running() {
+        let mut pool = WorkerPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let (done, finished) = channel();
+        pool.execute(move || done.send(()).unwrap());
+        assert!(finished.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(pool.workers.len(), 1);
+    }
+}
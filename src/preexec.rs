@@ -0,0 +1,406 @@
+use crate::cgroup::parse_size;
+use crate::clock::parse_duration;
+use crate::namespace::Namespaces;
+use crate::sandbox::{Confinement, Sandbox};
+use crate::user::{primary_group, supplementary_groups};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+/// I/O scheduling class and level as understood by `ioprio_set(2)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoPriority {
+    /// 1 realtime, 2 best-effort, 3 idle
+    pub class: u32,
+    /// 0 (highest) to 7, ignored for idle
+    pub level: u32,
+}
+
+impl IoPriority {
+    /// Parse `idle`, `best-effort[:N]`, `realtime[:N]` or their numeric
+    /// classes like `2:4`, the way `ionice -c` names them.
+    pub fn parse(s: &str) -> Result<IoPriority, String> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+        let class = match class {
+            "realtime" | "rt" | "1" => 1,
+            "best-effort" | "be" | "2" => 2,
+            "idle" | "3" => 3,
+            _ => return Err(format!("unknown io scheduling class: {}", class)),
+        };
+        let level = match level {
+            Some(level) => level
+                .parse()
+                .ok()
+                .filter(|level| *level <= 7)
+                .ok_or(format!("invalid io priority level: {}", level))?,
+            None => 4,
+        };
+        Ok(IoPriority { class, level })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn value(&self) -> libc::c_int {
+        ((self.class << 13) | self.level) as libc::c_int
+    }
+}
+
+/// The resources a task may be limited in with `setrlimit(2)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    /// address space in bytes
+    As,
+    /// cpu time in seconds
+    Cpu,
+    /// open files
+    Nofile,
+    /// core dump size in bytes
+    Core,
+}
+
+/// One resource limit, used as both soft and hard limit. `None` is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rlimit {
+    pub resource: Resource,
+    pub value: Option<u64>,
+}
+
+impl Rlimit {
+    /// Parse a limit like `as` and `8G`, `cpu` and `2h`, `nofile` and `1024`,
+    /// or `core` and `0`. Any of them may be `unlimited`.
+    pub fn parse(name: &str, value: &str) -> Result<Rlimit, String> {
+        let resource = match name {
+            "as" => Resource::As,
+            "cpu" => Resource::Cpu,
+            "nofile" => Resource::Nofile,
+            "core" => Resource::Core,
+            _ => return Err(format!("unknown rlimit: {}", name)),
+        };
+        let value = match (value, resource) {
+            ("unlimited", _) => None,
+            (value, Resource::As | Resource::Core) => Some(parse_size(value)?),
+            (value, Resource::Cpu) => {
+                Some(parse_duration(value).ok_or(format!("invalid cpu time: {}", value))? as u64)
+            }
+            (value, Resource::Nofile) => Some(
+                value
+                    .parse()
+                    .map_err(|e| format!("invalid nofile {}: {}", value, e))?,
+            ),
+        };
+        Ok(Rlimit { resource, value })
+    }
+
+    fn apply(&self) -> io::Result<()> {
+        let resource = match self.resource {
+            Resource::As => libc::RLIMIT_AS,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Nofile => libc::RLIMIT_NOFILE,
+            Resource::Core => libc::RLIMIT_CORE,
+        };
+        let value = self
+            .value
+            .map(|value| value as libc::rlim_t)
+            .unwrap_or(libc::RLIM_INFINITY);
+        let limit = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Settings applied in the forked child right before it execs the task.
+#[derive(Debug, Clone)]
+pub struct PreExec {
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+    /// -1000 to 1000, higher makes the OOM killer pick the task first
+    pub oom_score_adj: Option<i32>,
+    pub rlimits: Vec<Rlimit>,
+    /// signal the child gets when cirno dies, see `PR_SET_PDEATHSIG`
+    pub death_signal: Option<i32>,
+    /// uid to run as, which needs root unless it is cirno's own
+    pub user: Option<u32>,
+    /// gid to run as, the primary group of `user` by default
+    pub group: Option<u32>,
+    /// namespaces the task gets of its own
+    pub unshare: Option<Namespaces>,
+    pub sandbox: Sandbox,
+    /// open `cgroup.procs` of the cgroup to join, or -1. Shared with the
+    /// installed hook so every spawn can use a different cgroup.
+    cgroup_procs: Arc<AtomicI32>,
+}
+
+impl Default for PreExec {
+    fn default() -> PreExec {
+        PreExec {
+            nice: None,
+            ionice: None,
+            oom_score_adj: None,
+            rlimits: Vec::new(),
+            death_signal: Some(libc::SIGKILL),
+            user: None,
+            group: None,
+            unshare: None,
+            sandbox: Sandbox::default(),
+            cgroup_procs: Arc::new(AtomicI32::new(-1)),
+        }
+    }
+}
+
+impl PreExec {
+    /// Whether nothing is set to apply, the child dying with cirno aside.
+    pub fn is_default(&self) -> bool {
+        self.nice.is_none()
+            && self.ionice.is_none()
+            && self.oom_score_adj.is_none()
+            && self.rlimits.is_empty()
+            && self.user.is_none()
+            && self.group.is_none()
+            && self.unshare.is_none()
+            && self.sandbox == Sandbox::default()
+    }
+
+    /// Add `limit`, replacing an earlier one of the same resource.
+    pub fn set_rlimit(&mut self, limit: Rlimit) {
+        self.rlimits
+            .retain(|other| other.resource != limit.resource);
+        self.rlimits.push(limit);
+    }
+
+    /// Make the next spawned child join the cgroup whose `cgroup.procs` is
+    /// open as `fd`. The fd must stay open until the spawn returned.
+    pub fn set_cgroup_procs(&self, fd: Option<RawFd>) {
+        self.cgroup_procs.store(fd.unwrap_or(-1), Ordering::SeqCst);
+    }
+
+    /// Register the hook on a command. Hooks accumulate, so this must only be
+    /// called once per command. Fails if the sandbox can't be set up.
+    pub fn install(self, command: &mut Command) -> io::Result<()> {
+        let parent = std::process::id() as libc::pid_t;
+        // looking groups up allocates, so that happens before forking
+        let group = self
+            .group
+            .or_else(|| self.user.map(|uid| primary_group(uid).unwrap_or(uid)));
+        let groups: Option<Vec<libc::gid_t>> = self.user.map(|uid| {
+            supplementary_groups(uid, group.unwrap_or(uid))
+                .into_iter()
+                .map(|gid| gid as libc::gid_t)
+                .collect()
+        });
+        let confinement = self.sandbox.prepare()?;
+        unsafe {
+            command.pre_exec(move || self.apply(parent, group, groups.as_deref(), &confinement));
+        }
+        Ok(())
+    }
+
+    /// Runs between fork and exec, so only async-signal-safe calls belong here.
+    fn apply(
+        &self,
+        parent: libc::pid_t,
+        group: Option<u32>,
+        groups: Option<&[libc::gid_t]>,
+        confinement: &Confinement,
+    ) -> io::Result<()> {
+        // a session of its own keeps terminal signals meant for cirno away
+        // and puts the whole tree in one process group
+        if unsafe { libc::setsid() } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = self.cgroup_procs.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // writing 0 moves the writer itself
+            if unsafe { libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) } != 1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ionice) = self.ionice {
+            // IOPRIO_WHO_PROCESS, the calling process
+            if unsafe { libc::syscall(libc::SYS_ioprio_set, 1, 0, ionice.value()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.ionice.is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTSUP));
+        }
+        if let Some(adj) = self.oom_score_adj {
+            write_oom_score_adj(adj)?;
+        }
+        for limit in &self.rlimits {
+            limit.apply()?;
+        }
+        let namespaces = self.unshare.unwrap_or_default();
+        if namespaces.pid {
+            // the task runs in a grandchild, the death signal is for the
+            // process cirno started
+            self.set_death_signal(parent)?;
+        }
+        namespaces.enter()?;
+        // last, everything before may need root
+        if let Some(groups) = groups {
+            if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(gid) = group {
+            if unsafe { libc::setgid(gid) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(uid) = self.user {
+            if unsafe { libc::setuid(uid) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // changing the uid clears the death signal, so it comes after
+        if !namespaces.pid {
+            self.set_death_signal(parent)?;
+        }
+        confinement.enter()?;
+        Ok(())
+    }
+
+    /// The signal is sent when the thread that forked exits, cirno spawns
+    /// from its main thread only. Elsewhere tasks outlive cirno.
+    fn set_death_signal(&self, parent: libc::pid_t) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(sig) = self.death_signal {
+            if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, sig as libc::c_ulong) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // cirno may have died before the prctl
+            if unsafe { libc::getppid() } != parent {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = parent;
+        Ok(())
+    }
+}
+
+/// Write `adj` to `/proc/self/oom_score_adj` without allocating.
+fn write_oom_score_adj(adj: i32) -> io::Result<()> {
+    let mut buf = [0u8; 12];
+    let mut i = buf.len();
+    let mut n = adj.unsigned_abs();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if adj < 0 {
+        i -= 1;
+        buf[i] = b'-';
+    }
+    let path = b"/proc/self/oom_score_adj\0";
+    let fd = unsafe { libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.len() - i;
+    let written = unsafe { libc::write(fd, buf[i..].as_ptr() as *const libc::c_void, len) };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if written != len as isize {
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Parse an `oom_score_adj` value, -1000 to 1000.
+pub fn parse_oom_score_adj(s: &str) -> Result<i32, String> {
+    s.parse()
+        .ok()
+        .filter(|adj: &i32| (-1000..=1000).contains(adj))
+        .ok_or(format!(
+            "invalid oom_score_adj: {}, expected -1000 to 1000",
+            s
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_priority_takes_names_and_numbers() {
+        assert_eq!(
+            IoPriority::parse("idle"),
+            Ok(IoPriority { class: 3, level: 4 })
+        );
+        assert_eq!(
+            IoPriority::parse("be"),
+            Ok(IoPriority { class: 2, level: 4 })
+        );
+        assert_eq!(
+            IoPriority::parse("best-effort:7"),
+            Ok(IoPriority { class: 2, level: 7 })
+        );
+        assert_eq!(
+            IoPriority::parse("realtime:0"),
+            Ok(IoPriority { class: 1, level: 0 })
+        );
+        assert_eq!(
+            IoPriority::parse("2:4"),
+            Ok(IoPriority { class: 2, level: 4 })
+        );
+    }
+
+    #[test]
+    fn io_priority_rejects_unknown_classes_and_levels() {
+        for s in [
+            "", "none", "0", "4", "be:8", "be:-1", "be:", "rt:high", "BE",
+        ] {
+            assert!(IoPriority::parse(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn rlimit_reads_values_in_the_unit_of_the_resource() {
+        let limit =
+            |name, value| Rlimit::parse(name, value).map(|limit| (limit.resource, limit.value));
+        assert_eq!(limit("as", "8G"), Ok((Resource::As, Some(8 << 30))));
+        assert_eq!(limit("core", "0"), Ok((Resource::Core, Some(0))));
+        assert_eq!(limit("cpu", "2h"), Ok((Resource::Cpu, Some(7200))));
+        assert_eq!(limit("cpu", "90"), Ok((Resource::Cpu, Some(90))));
+        assert_eq!(limit("nofile", "1024"), Ok((Resource::Nofile, Some(1024))));
+        assert_eq!(limit("nofile", "unlimited"), Ok((Resource::Nofile, None)));
+        assert_eq!(limit("as", "unlimited"), Ok((Resource::As, None)));
+    }
+
+    #[test]
+    fn rlimit_rejects_unknown_resources_and_bad_values() {
+        let bad = [
+            ("stack", "8M"),
+            ("AS", "8G"),
+            ("as", "8X"),
+            ("cpu", "2x"),
+            ("nofile", "1k"),
+            ("nofile", "-1"),
+        ];
+        for (name, value) in bad {
+            assert!(Rlimit::parse(name, value).is_err(), "{} {}", name, value);
+        }
+    }
+}
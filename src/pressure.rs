@@ -0,0 +1,121 @@
+/// Pressure stall information of one resource, from `/proc/pressure/<resource>`.
+///
+/// The values are the share of wall time (in percent) in which some or all
+/// non-idle tasks were stalled on the resource, averaged over 10, 60 and 300
+/// seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pressure {
+    pub some_avg10: f64,
+    pub some_avg60: f64,
+    pub some_avg300: f64,
+    pub full_avg10: f64,
+    pub full_avg60: f64,
+    pub full_avg300: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    Cpu,
+    Memory,
+    Io,
+}
+
+impl Resource {
+    fn file(&self) -> &'static str {
+        match self {
+            Resource::Cpu => "/proc/pressure/cpu",
+            Resource::Memory => "/proc/pressure/memory",
+            Resource::Io => "/proc/pressure/io",
+        }
+    }
+}
+
+/// Whether the kernel exposes PSI at all.
+pub fn is_supported() -> bool {
+    read_pressure(Resource::Cpu).is_some()
+}
+
+pub fn read_pressure(resource: Resource) -> Option<Pressure> {
+    let contents = std::fs::read_to_string(resource.file()).ok()?;
+    parse_pressure(&contents)
+}
+
+/// Parse the `some ...` and `full ...` lines of a pressure file. Older
+/// kernels have no `full` line for cpu, those values stay zero.
+pub fn parse_pressure(contents: &str) -> Option<Pressure> {
+    let mut pressure = Pressure::default();
+    let mut found = false;
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = match fields.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let mut avg = [0.0; 3];
+        for field in fields {
+            let (key, value) = match field.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let slot = match key {
+                "avg10" => 0,
+                "avg60" => 1,
+                "avg300" => 2,
+                _ => continue,
+            };
+            avg[slot] = value.parse().ok()?;
+        }
+        match kind {
+            "some" => {
+                pressure.some_avg10 = avg[0];
+                pressure.some_avg60 = avg[1];
+                pressure.some_avg300 = avg[2];
+                found = true;
+            }
+            "full" => {
+                pressure.full_avg10 = avg[0];
+                pressure.full_avg60 = avg[1];
+                pressure.full_avg300 = avg[2];
+            }
+            _ => {}
+        }
+    }
+    if found {
+        Some(pressure)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pressure_reads_some_and_full() {
+        let contents = "some avg10=1.50 avg60=0.25 avg300=0.00 total=123456\n\
+                        full avg10=0.75 avg60=0.10 avg300=0.01 total=6543\n";
+        let pressure = parse_pressure(contents).unwrap();
+        assert_eq!(pressure.some_avg10, 1.5);
+        assert_eq!(pressure.some_avg60, 0.25);
+        assert_eq!(pressure.some_avg300, 0.0);
+        assert_eq!(pressure.full_avg10, 0.75);
+        assert_eq!(pressure.full_avg60, 0.1);
+        assert_eq!(pressure.full_avg300, 0.01);
+    }
+
+    #[test]
+    fn parse_pressure_does_without_full() {
+        let pressure =
+            parse_pressure("some avg10=2.00 avg60=1.00 avg300=0.50 total=42\n\n").unwrap();
+        assert_eq!(pressure.some_avg10, 2.0);
+        assert_eq!(pressure.full_avg10, 0.0);
+    }
+
+    #[test]
+    fn parse_pressure_needs_a_some_line_with_numbers() {
+        assert!(parse_pressure("").is_none());
+        assert!(parse_pressure("full avg10=0.75 avg60=0.10 avg300=0.01 total=6543\n").is_none());
+        assert!(parse_pressure("some avg10=lots avg60=0.10 avg300=0.01 total=6543\n").is_none());
+    }
+}
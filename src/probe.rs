@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use sysinfo::{CpuRefreshKind, System, SystemExt};
+
+use crate::disk::read_io_ticks;
+use crate::pressure::{read_pressure, Pressure, Resource};
+use crate::thermal;
+use crate::vmstat;
+
+/// What the machine looked like at one resource check. Counters are totals
+/// since boot, the scheduler turns them into rates between two checks.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceSnapshot {
+    /// 1 and 5-minute load averages, per cpu
+    pub load_one: f64,
+    pub load_five: f64,
+    /// memory available to new processes and swap in use, in bytes
+    pub available_memory: u64,
+    pub used_swap: u64,
+    /// pages swapped in
+    pub swapped_in: Option<u64>,
+    /// pressure stall information, if the kernel has it
+    pub cpu_pressure: Option<Pressure>,
+    pub memory_pressure: Option<Pressure>,
+    pub io_pressure: Option<Pressure>,
+    /// milliseconds each disk spent doing I/O
+    pub io_ticks: HashMap<String, u64>,
+    /// the hottest thermal zone in °C, and how often the cpu was throttled
+    pub temperature: Option<f64>,
+    pub throttles: Option<u64>,
+}
+
+/// Where the scheduler gets the measurements its opinion is made of, the
+/// machine it runs on unless one is set with `Scheduler::set_probe`.
+pub trait ResourceProbe {
+    /// Measure the resources now.
+    fn sample(&mut self) -> ResourceSnapshot;
+}
+
+/// Reads the resources of this machine from sysinfo, `/proc` and `/sys`.
+pub struct SystemProbe {
+    system: System,
+}
+
+impl SystemProbe {
+    pub fn new() -> SystemProbe {
+        let mut system = System::new();
+        // only the number of cpus is used
+        system.refresh_cpu_specifics(CpuRefreshKind::new());
+        SystemProbe { system }
+    }
+}
+
+impl Default for SystemProbe {
+    fn default() -> Self {
+        SystemProbe::new()
+    }
+}
+
+impl ResourceProbe for SystemProbe {
+    fn sample(&mut self) -> ResourceSnapshot {
+        // covers swap too, the load average needs no refresh
+        self.system.refresh_memory();
+        let cpus = self.system.cpus().len().max(1) as f64;
+        let load = self.system.load_average();
+        ResourceSnapshot {
+            load_one: load.one / cpus,
+            load_five: load.five / cpus,
+            available_memory: self.system.available_memory(),
+            used_swap: self.system.used_swap(),
+            swapped_in: vmstat::read_counter("pswpin"),
+            cpu_pressure: read_pressure(Resource::Cpu),
+            memory_pressure: read_pressure(Resource::Memory),
+            io_pressure: read_pressure(Resource::Io),
+            io_ticks: read_io_ticks(),
+            temperature: thermal::max_temperature(),
+            throttles: thermal::throttle_count(),
+        }
+    }
+}
@@ -1,66 +1,758 @@
-use rustix::process::{Pid, Signal, kill_process};
+use rustix::process::{getpgid, kill_process, kill_process_group, Pid, Signal};
+#[cfg(target_os = "linux")]
+use rustix::process::{pidfd_open, PidfdFlags};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
 
-pub fn kill_process_tree(pid: Pid, sig: Signal) -> std::io::Result<()> {
-    let mut process_to_kill = Vec::new();
-    let mut children = Vec::new();
-    let processes = get_processes();
-
-    children.push(pid);
-    while let Some(child) = children.pop() {
-        process_to_kill.push(child);
-        for process in processes.iter() {
-            if let Some(ppid) = getppid(process.clone()) {
-                if ppid == child {
-                    children.push(process.clone());
-                }
+use crate::error::CirnoError;
+
+// what the process table looks like differs per platform: `/proc` on
+// Linux, libproc and sysctl on macOS, `KERN_PROC` sysctls on FreeBSD
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+use linux as sys;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos as sys;
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "freebsd")]
+use freebsd as sys;
+
+/// A pid file descriptor. Unlike a pid it keeps referring to the same
+/// process, so signals sent through it can't hit a recycled pid.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct PidFd(OwnedFd);
+
+#[cfg(target_os = "linux")]
+impl PidFd {
+    pub fn open(pid: Pid) -> std::io::Result<PidFd> {
+        Ok(PidFd(pidfd_open(pid, PidfdFlags::empty())?))
+    }
+
+    /// Send `sig`, which is a no-op once the process has exited.
+    pub fn send_signal(&self, sig: Signal) -> std::io::Result<()> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.0.as_raw_fd(),
+                sig as libc::c_int,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if ret != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(e);
             }
         }
+        Ok(())
     }
+}
 
-    for process in process_to_kill {
-        if !is_exist(process) {
+/// The processes of one task as a unit, the process group it leads. On
+/// Windows, where this module is `process/windows.rs`, a Job Object.
+#[derive(Debug)]
+pub struct ProcessGroupHandle {
+    leader: Pid,
+}
+
+impl ProcessGroupHandle {
+    /// The group of `child`, made once as it is spawned.
+    pub fn new(child: &std::process::Child) -> std::io::Result<ProcessGroupHandle> {
+        Ok(ProcessGroupHandle {
+            leader: Pid::from_child(child),
+        })
+    }
+
+    /// Ask the processes to stop with `sig`, see `signal_group`.
+    pub fn signal(&self, sig: Signal) -> std::io::Result<()> {
+        signal_group(self.leader, sig)
+    }
+
+    /// End the processes at once, SIGKILL the whole tree.
+    pub fn kill(&self) -> std::io::Result<()> {
+        kill_process_tree(self.leader, Signal::Kill)
+    }
+}
+
+/// Signal the process group `pid` leads in one go, or just `pid` when it
+/// doesn't lead one. Meant for unreaped children, which hold on to their pid
+/// and group id, so there is no reuse race.
+pub fn signal_group(pid: Pid, sig: Signal) -> std::io::Result<()> {
+    if getpgid(Some(pid)).ok() == Some(pid) {
+        kill_process_group(pid, sig)?;
+    } else {
+        kill_process(pid, sig)?;
+    }
+    Ok(())
+}
+
+/// Signal `pid` together with its process group and all of its descendants,
+/// including those that moved to a group of their own. Each process gets
+/// the signal once.
+///
+/// Except for SIGSTOP and SIGCONT the tree is stopped first, root first,
+/// then signalled and continued leaves first, which closes the window for
+/// forks racing the kill and keeps parents from respawning or reaping their
+/// children halfway through. Members of the group the walk missed, like
+/// those whose parent exited, are signalled last.
+pub fn kill_process_tree(pid: Pid, sig: Signal) -> std::io::Result<()> {
+    let leader = getpgid(Some(pid)).ok() == Some(pid);
+    if sig == Signal::Stop || sig == Signal::Cont {
+        let mut walked = HashSet::new();
+        for (process, _, start_time) in walk_tree(pid) {
+            signal_checked(process, start_time, sig)?;
+            walked.insert(process);
+        }
+        if leader {
+            signal_rest_of_group(pid, &walked, sig)?;
+        }
+        return Ok(());
+    }
+
+    // freeze the tree first so nothing in it can fork while it is signalled,
+    // walking again until no new processes show up
+    let mut parents = HashMap::new();
+    let mut start_times = HashMap::new();
+    for _ in 0..FREEZE_ROUNDS {
+        let fresh: Vec<(Pid, Pid, u64)> = walk_tree(pid)
+            .into_iter()
+            .filter(|(process, _, start_time)| start_times.get(process) != Some(start_time))
+            .collect();
+        if fresh.is_empty() {
+            break;
+        }
+        for (process, ppid, start_time) in fresh {
+            signal_checked(process, start_time, Signal::Stop)?;
+            parents.insert(process, ppid);
+            start_times.insert(process, start_time);
+        }
+    }
+    // the root is missing when it was gone before the first walk
+    let order: Vec<(Pid, u64)> = kill_order(pid, &parents)
+        .into_iter()
+        .filter_map(|process| Some((process, *start_times.get(&process)?)))
+        .collect();
+    for (process, start_time) in &order {
+        signal_checked(*process, *start_time, sig)?;
+    }
+    // stopped processes only act on the signal once continued
+    for (process, start_time) in &order {
+        signal_checked(*process, *start_time, Signal::Cont)?;
+    }
+    if leader {
+        let walked = start_times.keys().copied().collect();
+        signal_rest_of_group(pid, &walked, sig)?;
+    }
+
+    Ok(())
+}
+
+/// Signal the members of the process group `pgid` that are not in `walked`.
+fn signal_rest_of_group(pgid: Pid, walked: &HashSet<Pid>, sig: Signal) -> std::io::Result<()> {
+    for process in all_processes() {
+        // rustix's getpgid panics on kernel threads, whose group is 0
+        let group = unsafe { libc::getpgid(process.as_raw_nonzero().get()) };
+        if walked.contains(&process) || group != pgid.as_raw_nonzero().get() {
             continue;
         }
-        kill_process(process, sig)?;
+        if let Some(start_time) = get_start_time(process) {
+            signal_checked(process, start_time, sig)?;
+        }
     }
+    Ok(())
+}
 
+/// The order to signal the tree below `root` in, given every process's
+/// parent: each process comes before its parent and `root` comes last.
+pub fn kill_order(root: Pid, parents: &HashMap<Pid, Pid>) -> Vec<Pid> {
+    let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+    for (pid, ppid) in parents {
+        if *pid != root {
+            children.entry(*ppid).or_default().push(*pid);
+        }
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|pid| pid.as_raw_nonzero());
+    }
+
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(pid) = stack.pop() {
+        // a broken map may have cycles
+        if !seen.insert(pid) {
+            continue;
+        }
+        order.push(pid);
+        if let Some(siblings) = children.get(&pid) {
+            stack.extend(siblings.iter().rev());
+        }
+    }
+    // parents precede their children in the walk, so reversed it is leaves first
+    order.reverse();
+    order
+}
+
+const FREEZE_ROUNDS: usize = 5;
+
+/// Signal a process found by a tree walk, unless its pid was recycled since:
+/// once a pidfd is open it can't change identity, so the start time read
+/// after opening tells whether it is still the process that was walked.
+#[cfg(target_os = "linux")]
+fn signal_checked(pid: Pid, start_time: u64, sig: Signal) -> std::io::Result<()> {
+    let pidfd = match PidFd::open(pid) {
+        Ok(pidfd) => pidfd,
+        Err(e) if e.raw_os_error() == Some(libc::ESRCH) => return Ok(()),
+        // no pidfds before linux 5.3
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+            if get_start_time(pid) == Some(start_time) {
+                kill_process(pid, sig)?;
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if get_start_time(pid) != Some(start_time) {
+        return Ok(());
+    }
+    pidfd.send_signal(sig)
+}
+
+/// Signal a process found by a tree walk unless it has another start time
+/// now. Without pidfds a pid recycled right after the check is still hit.
+#[cfg(not(target_os = "linux"))]
+fn signal_checked(pid: Pid, start_time: u64, sig: Signal) -> std::io::Result<()> {
+    if get_start_time(pid) == Some(start_time) {
+        if let Err(e) = kill_process(pid, sig) {
+            if e.raw_os_error() != libc::ESRCH {
+                return Err(e.into());
+            }
+        }
+    }
     Ok(())
 }
 
-pub fn is_exist(pid: Pid) -> bool {
-    let pid = pid.as_raw_nonzero().get();
-    match  std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
-        Ok(_) => true,
-        Err(_) => false,
-        
-    }
-}
-
-pub fn get_processes() -> Vec<Pid> {
-    let mut processes = Vec::new();
-    
-    let mut proc_dir = std::fs::read_dir("/proc").unwrap();
-    
-    while let Some(entry) = proc_dir.next() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_dir() {
-            let file_name = path.file_name().unwrap().to_str().unwrap();
-            if let Ok(pid) = file_name.parse::<i32>() {
-                processes.push(Pid::from_raw(pid).unwrap());
+/// The pid itself followed by all of its descendants.
+pub fn collect_tree(pid: Pid) -> Vec<Pid> {
+    walk_tree(pid).into_iter().map(|(pid, _, _)| pid).collect()
+}
+
+/// Like `collect_tree`, together with the parent and start time of each
+/// process.
+fn walk_tree(pid: Pid) -> Vec<(Pid, Pid, u64)> {
+    if sys::has_children_lists() {
+        walk_children(pid)
+    } else {
+        scan_tree(pid)
+    }
+}
+
+/// Walk the tree through the children lists, only touching the processes in it.
+fn walk_children(pid: Pid) -> Vec<(Pid, Pid, u64)> {
+    let mut tree = Vec::new();
+    let mut pending = Vec::new();
+    if let Some((ppid, start_time)) = read_stat(pid) {
+        pending.push((pid, ppid, start_time));
+    }
+    while let Some((process, ppid, start_time)) = pending.pop() {
+        tree.push((process, ppid, start_time));
+        for child in get_children(process) {
+            if let Some((ppid, start_time)) = read_stat(child) {
+                pending.push((child, ppid, start_time));
+            }
+        }
+    }
+    tree
+}
+
+/// Direct children of `pid`, of any of its threads.
+pub fn get_children(pid: Pid) -> Vec<Pid> {
+    if sys::has_children_lists() {
+        sys::listed_children(pid)
+    } else {
+        all_processes()
+            .into_iter()
+            .filter(|process| getppid(*process) == Some(pid))
+            .collect()
+    }
+}
+
+/// Every descendant of `pid`, parents before their children.
+pub fn get_descendants(pid: Pid) -> Vec<Pid> {
+    collect_tree(pid).into_iter().skip(1).collect()
+}
+
+/// A snapshot of a process and its descendants.
+#[derive(Debug, Clone)]
+pub struct ProcessTree {
+    pub pid: Pid,
+    pub name: String,
+    /// KiB
+    pub rss: u64,
+    pub children: Vec<ProcessTree>,
+}
+
+impl ProcessTree {
+    /// `None` when `pid` doesn't exist.
+    pub fn new(pid: Pid) -> Option<ProcessTree> {
+        let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+        let walked = walk_tree(pid);
+        if walked.is_empty() {
+            return None;
+        }
+        for (process, ppid, _) in walked.into_iter().skip(1) {
+            children.entry(ppid).or_default().push(process);
+        }
+        Some(ProcessTree::build(pid, &children))
+    }
+
+    fn build(pid: Pid, children: &HashMap<Pid, Vec<Pid>>) -> ProcessTree {
+        let mut below: Vec<ProcessTree> = children
+            .get(&pid)
+            .map(|pids| {
+                pids.iter()
+                    .map(|child| ProcessTree::build(*child, children))
+                    .collect()
+            })
+            .unwrap_or_default();
+        below.sort_by_key(|tree| tree.pid.as_raw_nonzero());
+        let info = read_proc_info(pid);
+        ProcessTree {
+            pid,
+            name: info
+                .as_ref()
+                .map(|info| info.name.clone())
+                .unwrap_or_default(),
+            rss: info.map(|info| info.rss).unwrap_or(0),
+            children: below,
+        }
+    }
+
+    /// Every process in the tree, parents before their children.
+    pub fn iter(&self) -> Vec<&ProcessTree> {
+        let mut all = vec![self];
+        for child in &self.children {
+            all.extend(child.iter());
+        }
+        all
+    }
+
+    pub fn pids(&self) -> Vec<Pid> {
+        self.iter().into_iter().map(|tree| tree.pid).collect()
+    }
+
+    /// Summed RSS of the whole tree in KiB.
+    pub fn total_rss(&self) -> u64 {
+        self.iter().into_iter().map(|tree| tree.rss).sum()
+    }
+}
+
+/// Command name of a process, `/proc/<pid>/comm` on Linux.
+pub fn get_name(pid: Pid) -> Option<String> {
+    sys::name(pid)
+}
+
+/// The slow walk for kernels without children lists: read the parent of
+/// every process.
+fn scan_tree(pid: Pid) -> Vec<(Pid, Pid, u64)> {
+    let mut tree = Vec::new();
+    let mut children = Vec::new();
+    let processes: Vec<(Pid, Pid, u64)> = all_processes()
+        .into_iter()
+        .filter_map(|process| {
+            let (ppid, start_time) = read_stat(process)?;
+            Some((process, ppid, start_time))
+        })
+        .collect();
+
+    match read_stat(pid) {
+        Some((ppid, start_time)) => children.push((pid, ppid, start_time)),
+        None => return tree,
+    }
+    while let Some((child, ppid, start_time)) = children.pop() {
+        tree.push((child, ppid, start_time));
+        for (process, ppid, start_time) in processes.iter() {
+            if *ppid == child {
+                children.push((*process, *ppid, *start_time));
             }
         }
     }
-    processes
+    tree
+}
+
+/// Parent and start time of a process, see `ProcInfo::start_time`.
+fn read_stat(pid: Pid) -> Option<(Pid, u64)> {
+    sys::stat(pid)
+}
+
+pub fn get_start_time(pid: Pid) -> Option<u64> {
+    read_stat(pid).map(|(_, start_time)| start_time)
+}
+
+/// Processes of a tree that sit in uninterruptible sleep (`D` state) and so
+/// cannot be killed until the kernel lets go of them.
+pub fn stuck_processes(pid: Pid) -> Vec<Pid> {
+    collect_tree(pid)
+        .into_iter()
+        .filter(|p| get_state(*p) == Some('D'))
+        .collect()
+}
+
+/// Single letter state as in `/proc/<pid>/stat`, e.g. `R`, `S`, `D` or `Z`.
+pub fn get_state(pid: Pid) -> Option<char> {
+    sys::state(pid)
+}
+
+/// Resident set size of a single process in KiB.
+pub fn get_rss(pid: Pid) -> Option<u64> {
+    sys::rss(pid)
+}
+
+/// Summed resident set size of a process and all of its descendants in KiB,
+/// so a task that forks workers is charged for all of them. Pages shared
+/// between the processes are counted once per process.
+pub fn tree_memory_usage(pid: Pid) -> u64 {
+    collect_tree(pid).into_iter().filter_map(get_rss).sum()
+}
+
+pub fn is_exist(pid: Pid) -> bool {
+    read_stat(pid).is_some()
+}
+
+pub fn get_processes() -> Result<Vec<Pid>, CirnoError> {
+    sys::pids().map_err(CirnoError::Processes)
+}
+
+/// `get_processes`, none if the process table can't be read.
+fn all_processes() -> Vec<Pid> {
+    get_processes().unwrap_or_else(|e| {
+        tracing::warn!("{}", e);
+        Vec::new()
+    })
+}
+
+/// Make orphaned descendants of this process get reparented to it instead of
+/// init, so they can still be found, signalled and reaped.
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper() -> std::io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1 as libc::c_ulong) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_child_subreaper() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "subreapers are Linux only",
+    ))
+}
+
+/// Continue in a forked child detached from the terminal, with stdin from
+/// `/dev/null` and stdout and stderr appended to `log`. The parent prints the
+/// child's pid and exits. Must be called before any thread is started.
+pub fn daemonize(log: &Path) -> std::io::Result<()> {
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)?;
+    let null = std::fs::File::open("/dev/null")?;
+    std::io::stdout().flush()?;
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()),
+        0 => {}
+        child => {
+            println!("cirno running in the background, pid {}", child);
+            std::process::exit(0);
+        }
+    }
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    for (fd, target) in [
+        (null.as_raw_fd(), 0),
+        (log.as_raw_fd(), 1),
+        (log.as_raw_fd(), 2),
+    ] {
+        if unsafe { libc::dup2(fd, target) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
 }
 
 pub fn getppid(pid: Pid) -> Option<Pid> {
-    let pid = pid.as_raw_nonzero().get();
-    let proc_contents = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
-    let mut proc_contents = proc_contents.trim().split_whitespace();
-    let _pid = proc_contents.next()?.parse::<i32>().ok()?;
-    let _comm = proc_contents.next()?;
-    let _state = proc_contents.next()?;
-    let ppid = proc_contents.next()?.parse::<i32>().ok()?;
-    Some(Pid::from_raw(ppid)?)
-}
\ No newline at end of file
+    read_stat(pid).map(|(ppid, _)| ppid)
+}
+
+/// What the process table knows about one process.
+#[derive(Debug, Clone)]
+pub struct ProcInfo {
+    pub pid: Pid,
+    /// `None` for processes without a parent, like init
+    pub ppid: Option<Pid>,
+    /// command name, at most 15 bytes and possibly containing spaces or parentheses
+    pub name: String,
+    /// e.g. `R`, `S`, `D` or `Z`
+    pub state: char,
+    /// clock ticks after boot on Linux, microseconds since the epoch on
+    /// macOS; it tells apart processes that had the same pid
+    pub start_time: u64,
+    /// KiB, zero for zombies and kernel threads
+    pub rss: u64,
+    /// empty for zombies and kernel threads
+    pub cmdline: Vec<String>,
+}
+
+/// Read what is known about `pid`, from `/proc/<pid>/{stat,status,cmdline}`
+/// on Linux. `None` when the process is gone.
+pub fn read_proc_info(pid: Pid) -> Option<ProcInfo> {
+    sys::info(pid)
+}
+
+/// Parse a signal given by name, with or without `SIG`, or by number, like
+/// `TERM`, `SIGINT` or `9`.
+pub fn parse_signal(s: &str) -> Result<Signal, String> {
+    let name = s.trim().to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    let signal = match name {
+        "HUP" => Signal::Hup,
+        "INT" => Signal::Int,
+        "QUIT" => Signal::Quit,
+        "ABRT" => Signal::Abort,
+        "KILL" => Signal::Kill,
+        "USR1" => Signal::Usr1,
+        "USR2" => Signal::Usr2,
+        "ALRM" => Signal::Alarm,
+        "TERM" => Signal::Term,
+        "CONT" => Signal::Cont,
+        "STOP" => Signal::Stop,
+        "XCPU" => Signal::Xcpu,
+        _ => name
+            .parse()
+            .ok()
+            .and_then(Signal::from_raw)
+            .ok_or(format!("unknown signal: {}", s))?,
+    };
+    Ok(signal)
+}
+
+/// Resource usage of a reaped child, as reported by `wait4`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChildUsage {
+    /// cpu time in seconds
+    pub user_time: f64,
+    pub sys_time: f64,
+    /// peak resident set size in KiB
+    pub max_rss: u64,
+}
+
+/// Non-blocking reap of a child that also collects its resource usage.
+///
+/// Once this returns a status the child is gone, so it must not be waited
+/// again through `std::process::Child`.
+pub fn try_wait_with_usage(pid: Pid) -> std::io::Result<Option<(ExitStatus, ChildUsage)>> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::wait4(
+            pid.as_raw_nonzero().get(),
+            &mut status,
+            libc::WNOHANG,
+            &mut rusage,
+        )
+    };
+    match ret {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => {
+            let timeval = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1e6;
+            let usage = ChildUsage {
+                user_time: timeval(rusage.ru_utime),
+                sys_time: timeval(rusage.ru_stime),
+                max_rss: rusage.ru_maxrss as u64,
+            };
+            Ok(Some((ExitStatus::from_raw(status), usage)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::process::getpid;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    fn pid(raw: i32) -> Pid {
+        Pid::from_raw(raw).unwrap()
+    }
+
+    /// `kill_order` from 1 with the parents given as (pid, parent) pairs.
+    fn order(parents: &[(i32, i32)]) -> Vec<i32> {
+        let parents = parents
+            .iter()
+            .map(|(child, parent)| (pid(*child), pid(*parent)))
+            .collect();
+        kill_order(pid(1), &parents)
+            .into_iter()
+            .map(Pid::as_raw_nonzero)
+            .map(i32::from)
+            .collect()
+    }
+
+    #[test]
+    fn kill_order_of_a_chain_goes_up_from_the_deepest() {
+        assert_eq!(order(&[(1, 100), (2, 1), (3, 2), (4, 3)]), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn kill_order_puts_siblings_before_their_parent() {
+        let order = order(&[(1, 100), (3, 1), (2, 1), (5, 2), (4, 3), (6, 100)]);
+        assert_eq!(order, vec![4, 3, 5, 2, 1]);
+    }
+
+    #[test]
+    fn kill_order_survives_a_parent_cycle() {
+        // a table read while pids were reused
+        assert_eq!(order(&[(1, 2), (2, 1), (3, 2)]), vec![3, 2, 1]);
+        assert_eq!(order(&[(2, 3), (3, 2)]), vec![1]);
+    }
+
+    #[test]
+    fn kill_order_keeps_a_root_missing_from_the_map() {
+        assert_eq!(order(&[(2, 1), (3, 2)]), vec![3, 2, 1]);
+        assert_eq!(order(&[]), vec![1]);
+    }
+
+    #[test]
+    fn read_proc_info_reads_a_child_with_an_awkward_name() {
+        let dir = std::env::temp_dir().join(format!("cirno-proc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prog = dir.join("sl) e (p");
+        std::fs::copy("/bin/sleep", &prog).unwrap();
+        let mut child = Command::new(&prog).arg("10").spawn().unwrap();
+        let pid = Pid::from_raw(child.id() as i32).unwrap();
+
+        // spawn returns once the exec started, the arguments are set up a little later
+        let mut read = read_proc_info(pid);
+        for _ in 0..100 {
+            if read.as_ref().is_some_and(|info| !info.cmdline.is_empty()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            read = read_proc_info(pid);
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let info = read.unwrap();
+        assert_eq!(info.name, "sl) e (p");
+        assert_eq!(info.ppid, Some(getpid()));
+        assert_eq!(
+            info.cmdline,
+            vec![prog.to_string_lossy().into_owned(), "10".to_string()]
+        );
+        assert!(info.start_time > 0);
+    }
+
+    #[test]
+    fn parse_signal_takes_names_and_numbers() {
+        assert_eq!(parse_signal("TERM"), Ok(Signal::Term));
+        assert_eq!(parse_signal("SIGINT"), Ok(Signal::Int));
+        assert_eq!(parse_signal(" sigkill "), Ok(Signal::Kill));
+        assert_eq!(parse_signal("usr1"), Ok(Signal::Usr1));
+        assert_eq!(parse_signal("9"), Ok(Signal::Kill));
+        assert_eq!(parse_signal("15"), Ok(Signal::Term));
+    }
+
+    #[test]
+    fn parse_signal_rejects_unknown_and_out_of_range_signals() {
+        for s in [
+            "",
+            "SIG",
+            "BOGUS",
+            "SIGSIGTERM",
+            "0",
+            "-9",
+            "65",
+            "999",
+            "4294967305",
+            "9.0",
+        ] {
+            assert!(parse_signal(s).is_err(), "{:?}", s);
+        }
+    }
+
+    /// Lines of `path`, none while it doesn't exist yet.
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn kill_process_tree_signals_every_process_once() {
+        // a root, its child and an orphan left in the group, each counting USR1
+        const SCRIPT: &str = r#"
+            trapper() {
+                trap "echo $1 >> $2.log" USR1
+                echo "$1" >> "$2.ready"
+                while :; do sleep 0.05; done
+            }
+            (trapper orphan "$1" &)
+            trapper child "$1" &
+            trapper root "$1"
+        "#;
+        let dir = std::env::temp_dir().join(format!("cirno-tree-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("trap");
+        let mut child = Command::new("sh")
+            .args(["-c", SCRIPT, "sh"])
+            .arg(&base)
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let root = Pid::from_raw(child.id() as i32).unwrap();
+        let ready = base.with_extension("ready");
+        let log = base.with_extension("log");
+        let wait_for = |path: &Path, n: usize| {
+            for _ in 0..200 {
+                if lines(path).len() >= n {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        };
+
+        wait_for(&ready, 3);
+        kill_process_tree(root, Signal::Usr1).unwrap();
+        wait_for(&log, 3);
+        // time for a second delivery to show up
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        let mut trapped = lines(&log);
+        kill_process_tree(root, Signal::Kill).unwrap();
+        child.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        trapped.sort();
+        assert_eq!(trapped, ["child", "orphan", "root"]);
+    }
+}
@@ -0,0 +1,170 @@
+use rustix::process::Pid;
+use std::ffi::CStr;
+use std::mem::size_of;
+
+use super::ProcInfo;
+
+/// Every pid from `KERN_PROC_PROC`, one entry per process rather than per
+/// thread.
+pub(super) fn pids() -> std::io::Result<Vec<Pid>> {
+    let processes = kinfo(&[libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PROC, 0])?;
+    Ok(processes
+        .iter()
+        .filter_map(|process| Pid::from_raw(process.ki_pid))
+        .collect())
+}
+
+/// There are no children lists, they are found by scanning every process.
+pub(super) fn has_children_lists() -> bool {
+    false
+}
+
+pub(super) fn listed_children(_pid: Pid) -> Vec<Pid> {
+    Vec::new()
+}
+
+/// The `kinfo_proc` entries a `KERN_PROC` sysctl returns, with room for
+/// processes started between sizing the buffer and filling it.
+fn kinfo(mib: &[libc::c_int]) -> std::io::Result<Vec<libc::kinfo_proc>> {
+    let mut size = 0;
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            std::ptr::null_mut(),
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut processes: Vec<libc::kinfo_proc> =
+        Vec::with_capacity(size / size_of::<libc::kinfo_proc>() + 64);
+    let mut size = processes.capacity() * size_of::<libc::kinfo_proc>();
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            processes.as_mut_ptr() as *mut libc::c_void,
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    unsafe { processes.set_len(size / size_of::<libc::kinfo_proc>()) };
+    Ok(processes)
+}
+
+fn process(pid: Pid) -> Option<libc::kinfo_proc> {
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID,
+        pid.as_raw_nonzero().get(),
+    ];
+    kinfo(&mib).ok()?.into_iter().next()
+}
+
+/// Start time in microseconds since the epoch, which, like the clock ticks
+/// on Linux, only has to tell two processes with the same pid apart.
+fn start_time(process: &libc::kinfo_proc) -> u64 {
+    process.ki_start.tv_sec as u64 * 1_000_000 + process.ki_start.tv_usec as u64
+}
+
+/// Parent and start time from `KERN_PROC_PID`.
+pub(super) fn stat(pid: Pid) -> Option<(Pid, u64)> {
+    let process = process(pid)?;
+    Some((Pid::from_raw(process.ki_ppid)?, start_time(&process)))
+}
+
+pub(super) fn name(pid: Pid) -> Option<String> {
+    process(pid).map(|process| comm(&process))
+}
+
+fn comm(process: &libc::kinfo_proc) -> String {
+    let bytes: Vec<u8> = process
+        .ki_comm
+        .iter()
+        .map(|c| *c as u8)
+        .chain(Some(0))
+        .collect();
+    CStr::from_bytes_until_nul(&bytes)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The state as the letter Linux would show, `D` for processes waiting on a
+/// lock, `T` for stopped and `Z` for zombies.
+pub(super) fn state(pid: Pid) -> Option<char> {
+    process(pid).map(|process| letter(process.ki_stat))
+}
+
+fn letter(stat: libc::c_char) -> char {
+    match stat {
+        libc::SSLEEP | libc::SWAIT => 'S',
+        libc::SLOCK => 'D',
+        libc::SSTOP => 'T',
+        libc::SZOMB => 'Z',
+        _ => 'R',
+    }
+}
+
+/// Resident set size in KiB, `ki_rssize` is in pages.
+pub(super) fn rss(pid: Pid) -> Option<u64> {
+    process(pid).map(|process| resident(&process))
+}
+
+fn resident(process: &libc::kinfo_proc) -> u64 {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+    process.ki_rssize.max(0) as u64 * page_size / 1024
+}
+
+pub(super) fn info(pid: Pid) -> Option<ProcInfo> {
+    let process = process(pid)?;
+    Some(ProcInfo {
+        pid,
+        ppid: Pid::from_raw(process.ki_ppid),
+        name: comm(&process),
+        state: letter(process.ki_stat),
+        start_time: start_time(&process),
+        rss: resident(&process),
+        cmdline: cmdline(pid).unwrap_or_default(),
+    })
+}
+
+/// The arguments from `KERN_PROC_ARGS`, each ended by a NUL.
+fn cmdline(pid: Pid) -> Option<Vec<String>> {
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_ARGS,
+        pid.as_raw_nonzero().get(),
+    ];
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = buffer.len();
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            &mut size,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    Some(
+        buffer[..size]
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
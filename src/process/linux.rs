@@ -0,0 +1,151 @@
+use rustix::process::Pid;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use super::ProcInfo;
+
+/// Every pid in `/proc`. Entries of processes that exit meanwhile may fail,
+/// they are skipped.
+pub(super) fn pids() -> std::io::Result<Vec<Pid>> {
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        if entry.path().is_dir() {
+            let pid = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<i32>().ok());
+            if let Some(pid) = pid.and_then(Pid::from_raw) {
+                processes.push(pid);
+            }
+        }
+    }
+    Ok(processes)
+}
+
+/// Whether the kernel keeps `/proc/<pid>/task/<tid>/children` lists
+/// (`CONFIG_PROC_CHILDREN`).
+pub(super) fn has_children_lists() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        let main_thread = std::process::id();
+        Path::new(&format!(
+            "/proc/{}/task/{}/children",
+            main_thread, main_thread
+        ))
+        .exists()
+    })
+}
+
+/// Children of every thread of `pid` from the kernel's lists, empty once it
+/// is gone.
+pub(super) fn listed_children(pid: Pid) -> Vec<Pid> {
+    let tasks = match std::fs::read_dir(format!("/proc/{}/task", pid.as_raw_nonzero())) {
+        Ok(tasks) => tasks,
+        Err(_) => return Vec::new(),
+    };
+    let mut children = Vec::new();
+    for task in tasks.flatten() {
+        if let Ok(list) = std::fs::read_to_string(task.path().join("children")) {
+            children.extend(
+                list.split_whitespace()
+                    .filter_map(|child| child.parse().ok())
+                    .filter_map(Pid::from_raw),
+            );
+        }
+    }
+    children
+}
+
+/// Parent and start time (clock ticks after boot) from `/proc/<pid>/stat`.
+pub(super) fn stat(pid: Pid) -> Option<(Pid, u64)> {
+    let pid = pid.as_raw_nonzero().get();
+    let proc_contents = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = split_stat(&proc_contents)?;
+    let ppid = Pid::from_raw(fields.get(1)?.parse().ok()?)?;
+    let start_time = fields.get(19)?.parse().ok()?;
+    Some((ppid, start_time))
+}
+
+/// Command name from `/proc/<pid>/comm`.
+pub(super) fn name(pid: Pid) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid.as_raw_nonzero())).ok()?;
+    Some(comm.trim_end_matches('\n').to_string())
+}
+
+/// Single letter state from `/proc/<pid>/stat`.
+pub(super) fn state(pid: Pid) -> Option<char> {
+    let pid = pid.as_raw_nonzero().get();
+    let proc_contents = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = split_stat(&proc_contents)?;
+    fields.first()?.chars().next()
+}
+
+/// Resident set size in KiB, from `/proc/<pid>/status`.
+pub(super) fn rss(pid: Pid) -> Option<u64> {
+    let pid = pid.as_raw_nonzero().get();
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Read `/proc/<pid>/{stat,status,cmdline}`.
+pub(super) fn info(pid: Pid) -> Option<ProcInfo> {
+    let raw = pid.as_raw_nonzero().get();
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", raw)).ok()?;
+    let (name, fields) = split_stat(&stat)?;
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", raw))
+        .map(|cmdline| {
+            cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ProcInfo {
+        pid,
+        ppid: Pid::from_raw(fields.get(1)?.parse().ok()?),
+        name: name.to_string(),
+        state: fields.first()?.chars().next()?,
+        start_time: fields.get(19)?.parse().ok()?,
+        rss: rss(pid).unwrap_or(0),
+        cmdline,
+    })
+}
+
+/// Split a `/proc/<pid>/stat` line into the comm and the fields after it,
+/// starting with the state. comm is in parentheses and may itself contain
+/// spaces and parentheses, so it ends at the last `)`.
+fn split_stat(stat: &str) -> Option<(&str, Vec<&str>)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    if close < open {
+        return None;
+    }
+    Some((
+        &stat[open + 1..close],
+        stat[close + 1..].split_whitespace().collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_stat_ends_comm_at_the_last_parenthesis() {
+        let (comm, fields) = split_stat("42 (a) b (c) S 1 42 42 0").unwrap();
+        assert_eq!(comm, "a) b (c");
+        assert_eq!(fields, vec!["S", "1", "42", "42", "0"]);
+
+        let (comm, fields) = split_stat("7 () R 1").unwrap();
+        assert_eq!(comm, "");
+        assert_eq!(fields, vec!["R", "1"]);
+    }
+
+    #[test]
+    fn split_stat_rejects_a_line_without_comm() {
+        assert_eq!(split_stat("42 S 1"), None);
+        assert_eq!(split_stat("42 )x( S 1"), None);
+    }
+}
@@ -0,0 +1,178 @@
+use rustix::process::Pid;
+use std::ffi::CStr;
+use std::mem::size_of;
+
+use super::ProcInfo;
+
+/// Every pid from `proc_listallpids`, with room for processes started
+/// between sizing the buffer and filling it.
+pub(super) fn pids() -> std::io::Result<Vec<Pid>> {
+    list(|buffer, size| unsafe { libc::proc_listallpids(buffer, size) })
+}
+
+/// `proc_listchildpids` lists the children of any process.
+pub(super) fn has_children_lists() -> bool {
+    true
+}
+
+/// Direct children of `pid`, empty once it is gone.
+pub(super) fn listed_children(pid: Pid) -> Vec<Pid> {
+    let raw = pid.as_raw_nonzero().get();
+    list(|buffer, size| unsafe { libc::proc_listchildpids(raw, buffer, size) }).unwrap_or_default()
+}
+
+/// Call a `proc_list*pids` function, which return how many pids they wrote
+/// or, given no buffer, how many there are.
+fn list(call: impl Fn(*mut libc::c_void, libc::c_int) -> libc::c_int) -> std::io::Result<Vec<Pid>> {
+    let count = call(std::ptr::null_mut(), 0);
+    if count < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut pids: Vec<libc::pid_t> = vec![0; count as usize + 64];
+    let size = (pids.len() * size_of::<libc::pid_t>()) as libc::c_int;
+    let count = call(pids.as_mut_ptr() as *mut libc::c_void, size);
+    if count < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    pids.truncate(count as usize);
+    Ok(pids.into_iter().filter_map(Pid::from_raw).collect())
+}
+
+fn bsd_info(pid: Pid) -> Option<libc::proc_bsdinfo> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let ret = unsafe {
+        libc::proc_pidinfo(
+            pid.as_raw_nonzero().get(),
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut libc::proc_bsdinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    (ret == size).then_some(info)
+}
+
+/// Start time in microseconds since the epoch, which, like the clock ticks
+/// on Linux, only has to tell two processes with the same pid apart.
+fn start_time(info: &libc::proc_bsdinfo) -> u64 {
+    info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec
+}
+
+/// Parent and start time from `proc_pidinfo`.
+pub(super) fn stat(pid: Pid) -> Option<(Pid, u64)> {
+    let info = bsd_info(pid)?;
+    Some((Pid::from_raw(info.pbi_ppid as i32)?, start_time(&info)))
+}
+
+pub(super) fn name(pid: Pid) -> Option<String> {
+    bsd_info(pid).map(|info| comm(&info))
+}
+
+fn comm(info: &libc::proc_bsdinfo) -> String {
+    let bytes: Vec<u8> = info
+        .pbi_comm
+        .iter()
+        .map(|c| *c as u8)
+        .chain(Some(0))
+        .collect();
+    CStr::from_bytes_until_nul(&bytes)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The state as the letter Linux would show, `T` for stopped and `Z` for
+/// zombies. There is no uninterruptible sleep to tell from the others.
+pub(super) fn state(pid: Pid) -> Option<char> {
+    bsd_info(pid).map(|info| letter(info.pbi_status))
+}
+
+fn letter(status: u32) -> char {
+    match status {
+        libc::SSLEEP => 'S',
+        libc::SSTOP => 'T',
+        libc::SZOMB => 'Z',
+        _ => 'R',
+    }
+}
+
+/// Resident set size in KiB, from `proc_pidinfo`.
+pub(super) fn rss(pid: Pid) -> Option<u64> {
+    let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+    let size = size_of::<libc::proc_taskinfo>() as libc::c_int;
+    let ret = unsafe {
+        libc::proc_pidinfo(
+            pid.as_raw_nonzero().get(),
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut libc::proc_taskinfo as *mut libc::c_void,
+            size,
+        )
+    };
+    (ret == size).then_some(info.pti_resident_size / 1024)
+}
+
+pub(super) fn info(pid: Pid) -> Option<ProcInfo> {
+    let info = bsd_info(pid)?;
+    Some(ProcInfo {
+        pid,
+        ppid: Pid::from_raw(info.pbi_ppid as i32),
+        name: comm(&info),
+        state: letter(info.pbi_status),
+        start_time: start_time(&info),
+        rss: rss(pid).unwrap_or(0),
+        cmdline: cmdline(pid).unwrap_or_default(),
+    })
+}
+
+/// The arguments from `KERN_PROCARGS2`: the argument count, the path of the
+/// executable padded with NULs, then the arguments, each ended by a NUL.
+fn cmdline(pid: Pid) -> Option<Vec<String>> {
+    let mut argmax: libc::c_int = 0;
+    let mut size = size_of::<libc::c_int>();
+    let mut mib = [libc::CTL_KERN, libc::KERN_ARGMAX];
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            2,
+            &mut argmax as *mut libc::c_int as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let mut buffer = vec![0u8; argmax as usize];
+    let mut size = buffer.len();
+    let mut mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROCARGS2,
+        pid.as_raw_nonzero().get(),
+    ];
+    let ret = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            3,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 || size < size_of::<libc::c_int>() {
+        return None;
+    }
+    let (argc, rest) = buffer[..size].split_at(size_of::<libc::c_int>());
+    let argc = libc::c_int::from_ne_bytes(argc.try_into().ok()?) as usize;
+    let path_end = rest.iter().position(|b| *b == 0)?;
+    let args_start = path_end + rest[path_end..].iter().position(|b| *b != 0)?;
+    Some(
+        rest[args_start..]
+            .split(|b| *b == 0)
+            .take(argc)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
@@ -0,0 +1,55 @@
+use std::mem::size_of;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+use std::process::Child;
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+
+/// The processes of one task as a unit, a Job Object with kill-on-close
+/// holding the task and every process it starts. Dropping the handle kills
+/// them all.
+#[derive(Debug)]
+pub struct ProcessGroupHandle(OwnedHandle);
+
+impl ProcessGroupHandle {
+    /// A new job with `child` in it. Processes the child started before
+    /// joining stay outside of it, so make it right after spawning, and
+    /// only once: it lives as long as the task should.
+    pub fn new(child: &Child) -> std::io::Result<ProcessGroupHandle> {
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let job = ProcessGroupHandle(unsafe { OwnedHandle::from_raw_handle(handle as _) });
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let set = unsafe {
+            SetInformationJobObject(
+                job.0.as_raw_handle() as _,
+                JobObjectExtendedLimitInformation,
+                &limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const _,
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if set == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if unsafe {
+            AssignProcessToJobObject(job.0.as_raw_handle() as _, child.as_raw_handle() as _)
+        } == 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(job)
+    }
+
+    /// End the processes at once, Windows has no signals to ask first.
+    pub fn kill(&self) -> std::io::Result<()> {
+        if unsafe { TerminateJobObject(self.0.as_raw_handle() as _, 1) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// The progress line shown at the bottom of the terminal, if any.
+static BAR: Mutex<Option<String>> = Mutex::new(None);
+
+const WIDTH: usize = 30;
+
+/// Whether a progress bar can be drawn, i.e. stderr is a terminal.
+pub fn supported() -> bool {
+    io::stderr().is_terminal()
+}
+
+/// Draw `line` as the progress bar on stderr, replacing the last one.
+pub fn set(line: String) {
+    let mut bar = BAR.lock().unwrap();
+    let _ = write!(io::stderr(), "\r\x1b[K{}", line);
+    *bar = Some(line);
+}
+
+/// Leave the last progress bar on the terminal and stop redrawing it.
+pub fn finish() {
+    if BAR.lock().unwrap().take().is_some() {
+        let _ = writeln!(io::stderr());
+    }
+}
+
+/// `[#####-----] done/total` for `done` of `total` tasks.
+pub fn bar(done: usize, total: usize) -> String {
+    let filled = (done * WIDTH).checked_div(total).unwrap_or(0).min(WIDTH);
+    format!(
+        "[{}{}] {}/{}",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        done,
+        total
+    )
+}
+
+/// Write `data` to stdout, moving the progress bar out of the way and back
+/// below it.
+pub fn print(data: &[u8]) -> io::Result<()> {
+    let bar = BAR.lock().unwrap();
+    if bar.is_some() {
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    }
+    let mut stdout = io::stdout().lock();
+    let written = stdout.write_all(data).and_then(|_| stdout.flush());
+    if let Some(line) = &*bar {
+        let _ = write!(io::stderr(), "\r{}", line);
+    }
+    written
+}
+
+/// Stdout for the log, printing through `print`.
+pub struct Stdout;
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        print(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
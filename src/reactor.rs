@@ -0,0 +1,621 @@
+use rustix::process::{Pid, Signal};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::cgroup::CgroupLimits;
+use crate::observer::SchedulerObserver;
+use crate::policy::{Conditions, ResourcePolicy, SchedulingPolicy};
+use crate::probe::{ResourceProbe, SystemProbe};
+use crate::process::signal_group;
+use crate::scheduler::{CirnoOpinion, PreemptMode};
+use crate::task::Task;
+
+/// What an `AsyncHandle` asks of a running `AsyncScheduler`.
+enum Message {
+    Submit(Box<Task>),
+    Cancel(String),
+    Drain,
+}
+
+/// Submits, cancels and drains from anywhere while `AsyncScheduler::run`
+/// is running.
+#[derive(Clone)]
+pub struct AsyncHandle {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl AsyncHandle {
+    /// Queue `task` to be launched.
+    pub fn submit(&self, task: Task) {
+        let _ = self.sender.send(Message::Submit(Box::new(task)));
+    }
+
+    /// Drop the waiting task `name`, or stop it if it runs.
+    pub fn cancel(&self, name: &str) {
+        let _ = self.sender.send(Message::Cancel(name.to_string()));
+    }
+
+    /// Launch nothing more and return once the running tasks are done.
+    pub fn drain(&self) {
+        let _ = self.sender.send(Message::Drain);
+    }
+}
+
+/// The pid of a running task, and what tells its waiter to stop it.
+struct Waiter {
+    pid: u32,
+    /// of the waiter in the `JoinSet`, which is all a failed one tells
+    id: tokio::task::Id,
+    stop: Option<oneshot::Sender<()>>,
+    /// why it is being stopped, `preempted` or `cancelled`
+    stopping: Option<&'static str>,
+}
+
+/// How a waiter saw its child end.
+struct Exit {
+    pid: u32,
+    status: std::io::Result<ExitStatus>,
+    timed_out: bool,
+}
+
+/// The scheduler as a tokio event loop: children are `tokio::process`
+/// children, stopping one is awaited in the background instead of sleeping
+/// through its grace period, and child exits, handle messages and resource
+/// checks are taken as they come.
+///
+/// It covers the core of `Scheduler`: the worker cap, the load and memory
+/// thresholds, the scheduling policy, preemption by killing and requeueing,
+/// per-task timeouts and stop policies, and observers. Tasks get no cgroups,
+/// pre-exec setup or log rotation, a warning says so when they ask for them,
+/// and their attempts are not recorded in the history.
+pub struct AsyncScheduler {
+    max_workers: usize,
+    load_max: f64,
+    load_min: f64,
+    /// in GB
+    reserved_mem: usize,
+    per_task_mem: usize,
+    check_interval: Duration,
+    run_dir: PathBuf,
+    probe: Box<dyn ResourceProbe>,
+    policy: Box<dyn SchedulingPolicy>,
+    observers: Vec<Box<dyn SchedulerObserver>>,
+    opinion: Option<CirnoOpinion>,
+    conditions: Conditions,
+    /// tasks kept running whatever the resources
+    force_task: usize,
+    todo: Vec<Task>,
+    /// the running tasks, and their waiters at the same index
+    running: Vec<Task>,
+    waiters: Vec<Waiter>,
+    outcomes: HashMap<String, usize>,
+    draining: bool,
+    sender: mpsc::UnboundedSender<Message>,
+    receiver: mpsc::UnboundedReceiver<Message>,
+}
+
+impl AsyncScheduler {
+    pub fn new(max_workers: usize) -> AsyncScheduler {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        AsyncScheduler {
+            max_workers,
+            load_max: 1.0,
+            load_min: 0.9,
+            reserved_mem: 6,
+            per_task_mem: 0,
+            check_interval: Duration::from_secs(10),
+            run_dir: PathBuf::from("run"),
+            probe: Box::new(SystemProbe::new()),
+            policy: Box::new(ResourcePolicy),
+            observers: Vec::new(),
+            opinion: None,
+            conditions: Conditions::default(),
+            force_task: 1,
+            todo: Vec::new(),
+            running: Vec::new(),
+            waiters: Vec::new(),
+            outcomes: HashMap::new(),
+            draining: false,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Preempt above this per-cpu load, launch only at or below `min`.
+    pub fn set_load(&mut self, max: f64, min: f64) {
+        self.load_max = max;
+        self.load_min = min;
+    }
+
+    /// Keep `reserved` GB free, expecting each new task to need `per_task` GB.
+    pub fn set_memory(&mut self, reserved: usize, per_task: usize) {
+        self.reserved_mem = reserved;
+        self.per_task_mem = per_task;
+    }
+
+    /// Keep this many tasks running whatever the resources.
+    pub fn set_force_task(&mut self, force_task: usize) {
+        self.force_task = force_task;
+    }
+
+    /// How often the resources are checked and a task may be launched.
+    pub fn set_check_interval(&mut self, interval: Duration) {
+        self.check_interval = interval;
+    }
+
+    /// Where the logs of the tasks go, created if needed.
+    pub fn set_run_dir(&mut self, run_dir: PathBuf) {
+        self.run_dir = run_dir;
+    }
+
+    /// Take the resource readings from `probe` instead of this machine.
+    pub fn set_probe(&mut self, probe: Box<dyn ResourceProbe>) {
+        self.probe = probe;
+    }
+
+    /// Decide when to launch, wait or preempt, and which task, with `policy`.
+    pub fn set_policy(&mut self, policy: Box<dyn SchedulingPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Tell `observer` about tasks starting, finishing and being preempted,
+    /// and about changes of opinion.
+    pub fn add_observer(&mut self, observer: Box<dyn SchedulerObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Queue `task` before running.
+    pub fn submit(&mut self, task: Task) {
+        self.todo.push(task);
+    }
+
+    /// A handle for submitting, cancelling and draining while running.
+    pub fn handle(&self) -> AsyncHandle {
+        AsyncHandle {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Run until every task is done, or until the running ones are after a
+    /// drain, and return how many attempts ended with each outcome.
+    pub async fn run(mut self) -> HashMap<String, usize> {
+        if let Err(e) = fs::create_dir_all(&self.run_dir) {
+            warn!("failed to create {}: {}", self.run_dir.display(), e);
+        }
+        let mut exits = JoinSet::new();
+        let mut ticks = tokio::time::interval(self.check_interval);
+        while !self.running.is_empty() || (!self.todo.is_empty() && !self.draining) {
+            tokio::select! {
+                Some(exit) = exits.join_next() => match exit {
+                    Ok(exit) => self.exited(exit),
+                    Err(e) => self.lost(e),
+                },
+                Some(message) = self.receiver.recv() => self.handle_message(message),
+                _ = ticks.tick() => self.check(&mut exits),
+            }
+        }
+        self.outcomes
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Submit(task) => self.todo.push(*task),
+            Message::Cancel(name) => {
+                if let Some(i) = self.todo.iter().position(|task| task.name == name) {
+                    let task = self.todo.remove(i);
+                    info!("task: {} cancelled", task.name);
+                    self.record(&task, "cancelled", None);
+                } else if let Some(i) = self.running.iter().position(|task| task.name == name) {
+                    self.stop(i, "cancelled");
+                } else {
+                    warn!("task: {} is neither waiting nor running", name);
+                }
+            }
+            Message::Drain => {
+                info!("draining, {} tasks left waiting", self.todo.len());
+                self.draining = true;
+            }
+        }
+    }
+
+    /// Check the resources, launch a task when they are plentiful and
+    /// preempt one when they are short.
+    fn check(&mut self, exits: &mut JoinSet<Exit>) {
+        let snapshot = self.probe.sample();
+        let free_kib = snapshot.available_memory / 1024;
+        let reserved_kib = (self.reserved_mem as u64) << 20;
+        let per_task_kib = (self.per_task_mem as u64) << 20;
+        let bad = [
+            ("memory", free_kib < reserved_kib),
+            ("load", snapshot.load_five > self.load_max),
+        ];
+        let calm = [
+            ("memory", free_kib >= reserved_kib + per_task_kib),
+            ("load", snapshot.load_five <= self.load_min),
+        ];
+        self.conditions = Conditions {
+            bad: bad
+                .iter()
+                .filter(|(_, bad)| *bad)
+                .map(|(check, _)| *check)
+                .collect(),
+            not_calm: calm
+                .iter()
+                .filter(|(_, calm)| !*calm)
+                .map(|(check, _)| *check)
+                .collect(),
+            reserved_cores: self.running.iter().map(|task| task.cpus).sum(),
+            worker_cap: self.max_workers,
+            free_kib,
+            reserved_kib,
+            per_task_kib,
+            low_memory: free_kib < reserved_kib,
+        };
+        let mut opinion = self.policy.opinion(&self.conditions);
+        if opinion == CirnoOpinion::Health && self.conditions.reserved_cores >= self.max_workers {
+            opinion = CirnoOpinion::Normal;
+        }
+        if self.opinion != Some(opinion) {
+            info!("opinion: {:?}", opinion);
+            for observer in &mut self.observers {
+                observer.on_opinion_change(self.opinion, opinion);
+            }
+            self.opinion = Some(opinion);
+        }
+        match opinion {
+            CirnoOpinion::Health if !self.draining => self.launch_next(exits),
+            // one task is stopped at a time, the next check sees the effect
+            CirnoOpinion::Bad
+                if self.running.len() > self.force_task
+                    && self.waiters.iter().all(|waiter| waiter.stopping.is_none()) =>
+            {
+                let victim = self.policy.victim(&self.running, &self.conditions);
+                self.stop(victim.min(self.running.len() - 1), "preempted");
+            }
+            _ => {}
+        }
+    }
+
+    fn launch_next(&mut self, exits: &mut JoinSet<Exit>) {
+        let free_cores = self
+            .max_workers
+            .saturating_sub(self.conditions.reserved_cores);
+        let idle = self.running.is_empty();
+        let candidates = self.policy.candidates(&self.todo, &self.conditions);
+        let picked = candidates.into_iter().find(|&i| {
+            self.todo
+                .get(i)
+                .is_some_and(|task| task.cpus <= free_cores || idle)
+        });
+        if let Some(i) = picked {
+            let task = self.todo.remove(i);
+            self.launch(task, exits);
+        }
+    }
+
+    fn launch(&mut self, mut task: Task, exits: &mut JoinSet<Exit>) {
+        let mut command = task.plain_command();
+        // signals then reach the whole group, as with `Scheduler`
+        command.process_group(0);
+        let stdout = fs::File::create(self.run_dir.join(format!("{}.txtlog", task.name)));
+        let stderr = fs::File::create(self.run_dir.join(format!("{}.err", task.name)));
+        match (stdout, stderr) {
+            (Ok(stdout), Ok(stderr)) => {
+                command.stdout(stdout).stderr(stderr);
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("task: {} failed to open its logs: {}", task.name, e);
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+            }
+        }
+        command.stdin(Stdio::null());
+        if task.cgroup_parent.is_some()
+            || task.limits != CgroupLimits::default()
+            || !task.pre_exec.is_default()
+        {
+            warn!(
+                "task: {} asks for a cgroup or pre-exec settings, which the async scheduler ignores",
+                task.name
+            );
+        }
+        task.attempts += 1;
+        task.start_time = SystemTime::now();
+        let mut child = match tokio::process::Command::from(command).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("task: {} failed to spawn: {}", task.name, e);
+                self.record(&task, "error", None);
+                return;
+            }
+        };
+        let pid = child.id().unwrap_or_default();
+        info!("task: {} started", task.name);
+        for observer in &mut self.observers {
+            observer.on_task_start(&task);
+        }
+        let (stop, stopped) = oneshot::channel();
+        let signal = task.stop_policy.signal();
+        let grace = task.stop_policy.grace();
+        let timeout = task.timeout;
+        let waiter = exits.spawn(async move {
+            let deadline = async {
+                match timeout {
+                    Some(timeout) => tokio::time::sleep(timeout).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let mut timed_out = false;
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = stopped => terminate(&mut child, pid, signal, grace).await,
+                _ = deadline => {
+                    timed_out = true;
+                    terminate(&mut child, pid, signal, grace).await
+                }
+            };
+            Exit {
+                pid,
+                status,
+                timed_out,
+            }
+        });
+        self.running.push(task);
+        self.waiters.push(Waiter {
+            pid,
+            id: waiter.id(),
+            stop: Some(stop),
+            stopping: None,
+        });
+    }
+
+    /// Have the waiter of `self.running[i]` stop it, in the background.
+    fn stop(&mut self, i: usize, why: &'static str) {
+        info!("task: {} stopping", self.running[i].name);
+        let waiter = &mut self.waiters[i];
+        waiter.stopping = Some(why);
+        if let Some(stop) = waiter.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+
+    fn exited(&mut self, exit: Exit) {
+        let i = match self
+            .waiters
+            .iter()
+            .position(|waiter| waiter.pid == exit.pid)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        let waiter = self.waiters.remove(i);
+        let task = self.running.remove(i);
+        let status = match exit.status {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("task: {} failed to wait: {}", task.name, e);
+                self.record(&task, "error", None);
+                return;
+            }
+        };
+        match waiter.stopping {
+            Some("preempted") => {
+                info!("task: {} preempted", task.name);
+                for observer in &mut self.observers {
+                    observer.on_task_preempted(&task, PreemptMode::Kill);
+                }
+                *self.outcomes.entry("preempted".to_string()).or_default() += 1;
+                self.todo.push(task);
+            }
+            Some(why) => {
+                info!("task: {} {} with status: {}", task.name, why, status);
+                self.record(&task, why, Some(status));
+            }
+            None if exit.timed_out => {
+                warn!("task: {} timed out with status: {}", task.name, status);
+                self.record(&task, "timed_out", Some(status));
+            }
+            None => {
+                info!("task: {} finished with status: {}", task.name, status);
+                self.record(&task, "finished", Some(status));
+            }
+        }
+    }
+
+    /// Drop the task of a waiter that panicked or was cancelled, killing
+    /// what may be left of it since nothing waits for it anymore.
+    fn lost(&mut self, e: tokio::task::JoinError) {
+        let i = match self.waiters.iter().position(|waiter| waiter.id == e.id()) {
+            Some(i) => i,
+            None => return,
+        };
+        let waiter = self.waiters.remove(i);
+        let task = self.running.remove(i);
+        warn!("task: {} lost its waiter: {}", task.name, e);
+        if let Some(pid) = Pid::from_raw(waiter.pid as i32) {
+            let _ = signal_group(pid, Signal::Kill);
+        }
+        self.record(&task, "error", None);
+    }
+
+    fn record(&mut self, task: &Task, outcome: &str, status: Option<ExitStatus>) {
+        for observer in &mut self.observers {
+            observer.on_task_finish(task, outcome, status);
+        }
+        *self.outcomes.entry(outcome.to_string()).or_default() += 1;
+    }
+}
+
+/// Send `signal` to the group of `child`, then SIGKILL once `grace` passed
+/// without it exiting, and reap it.
+async fn terminate(
+    child: &mut tokio::process::Child,
+    pid: u32,
+    signal: Signal,
+    grace: Duration,
+) -> std::io::Result<ExitStatus> {
+    if let Some(pid) = Pid::from_raw(pid as i32) {
+        let _ = signal_group(pid, signal);
+    }
+    match tokio::time::timeout(grace, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            if let Some(pid) = Pid::from_raw(pid as i32) {
+                let _ = signal_group(pid, Signal::Kill);
+            }
+            child.wait().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::ResourceSnapshot;
+    use crate::task::TaskBuilder;
+    use std::collections::VecDeque;
+    use std::time::Instant;
+
+    const GIB: u64 = 1 << 30;
+
+    /// Hands out the snapshots in order, the last one over and over.
+    struct ScriptedProbe {
+        script: VecDeque<ResourceSnapshot>,
+    }
+
+    impl ResourceProbe for ScriptedProbe {
+        fn sample(&mut self) -> ResourceSnapshot {
+            match self.script.len() {
+                0 => ResourceSnapshot::default(),
+                1 => self.script[0].clone(),
+                _ => self.script.pop_front().unwrap(),
+            }
+        }
+    }
+
+    fn snapshot(load_five: f64, available_gib: u64) -> ResourceSnapshot {
+        ResourceSnapshot {
+            load_five,
+            available_memory: available_gib * GIB,
+            ..Default::default()
+        }
+    }
+
+    /// A scheduler of `max_workers` checking often, with its logs in a
+    /// directory of its own for the run of `test`.
+    fn scheduler(test: &str, max_workers: usize, script: Vec<ResourceSnapshot>) -> AsyncScheduler {
+        let dir = std::env::temp_dir().join(format!("cirno-async-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut scheduler = AsyncScheduler::new(max_workers);
+        scheduler.set_probe(Box::new(ScriptedProbe {
+            script: script.into(),
+        }));
+        scheduler.set_check_interval(Duration::from_millis(20));
+        scheduler.set_run_dir(dir);
+        scheduler
+    }
+
+    fn sleep(name: &str, secs: &str) -> Task {
+        TaskBuilder::new(name, "sleep").arg(secs).build()
+    }
+
+    fn outcomes(pairs: &[(&str, usize)]) -> HashMap<String, usize> {
+        pairs
+            .iter()
+            .map(|(outcome, n)| (outcome.to_string(), *n))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn launches_every_task_and_counts_how_it_ended() {
+        let mut scheduler = scheduler("finish", 2, vec![snapshot(0.0, 64)]);
+        scheduler.submit(TaskBuilder::new("ok", "true").build());
+        scheduler.submit(TaskBuilder::new("failing", "false").build());
+        scheduler.submit(TaskBuilder::new("missing", "/nonexistent/cirno-test").build());
+        let outcomes = scheduler.run().await;
+        assert_eq!(outcomes, self::outcomes(&[("finished", 2), ("error", 1)]));
+    }
+
+    #[tokio::test]
+    async fn waits_while_memory_is_short() {
+        // short at first, later plenty
+        let script = vec![
+            snapshot(0.0, 1),
+            snapshot(0.0, 1),
+            snapshot(0.0, 1),
+            snapshot(0.0, 64),
+        ];
+        let mut scheduler = scheduler("wait", 1, script);
+        scheduler.submit(TaskBuilder::new("ok", "true").build());
+        let start = Instant::now();
+        assert_eq!(scheduler.run().await, outcomes(&[("finished", 1)]));
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tokio::test]
+    async fn stops_a_task_past_its_timeout() {
+        let mut scheduler = scheduler("timeout", 1, vec![snapshot(0.0, 64)]);
+        scheduler.submit(
+            TaskBuilder::new("slow", "sleep")
+                .arg("10")
+                .timeout(Duration::from_millis(100))
+                .build(),
+        );
+        let start = Instant::now();
+        assert_eq!(scheduler.run().await, outcomes(&[("timed_out", 1)]));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn cancels_waiting_and_running_tasks() {
+        let mut scheduler = scheduler("cancel", 1, vec![snapshot(0.0, 64)]);
+        scheduler.submit(sleep("a", "10"));
+        scheduler.submit(sleep("b", "10"));
+        let handle = scheduler.handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            handle.cancel("a");
+            handle.cancel("b");
+        });
+        let start = Instant::now();
+        assert_eq!(scheduler.run().await, outcomes(&[("cancelled", 2)]));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn drain_lets_the_running_task_finish_and_launches_no_more() {
+        let mut scheduler = scheduler("drain", 1, vec![snapshot(0.0, 64)]);
+        for name in ["a", "b", "c"] {
+            scheduler.submit(sleep(name, "0.5"));
+        }
+        let handle = scheduler.handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            handle.drain();
+        });
+        assert_eq!(scheduler.run().await, outcomes(&[("finished", 1)]));
+    }
+
+    #[tokio::test]
+    async fn a_failed_waiter_takes_its_task_along() {
+        let mut scheduler = scheduler("lost", 1, vec![snapshot(0.0, 64)]);
+        let mut exits = JoinSet::new();
+        let id = exits.spawn(async { panic!("the waiter failed") }).id();
+        scheduler.running.push(sleep("a", "10"));
+        scheduler.waiters.push(Waiter {
+            pid: 0,
+            id,
+            stop: None,
+            stopping: None,
+        });
+        scheduler.lost(exits.join_next().await.unwrap().unwrap_err());
+        assert!(scheduler.running.is_empty() && scheduler.waiters.is_empty());
+        assert_eq!(scheduler.run().await, outcomes(&[("error", 1)]));
+    }
+}
@@ -0,0 +1,284 @@
+use std::io;
+use std::path::PathBuf;
+
+/// What a task may touch, enforced by the kernel with Landlock and a
+/// seccomp filter. Once any path is given everything else on the file
+/// system is off limits, so the list has to include what the task itself
+/// needs, like `/usr` and `/lib`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    /// paths the task may read and execute, with everything below
+    pub read: Vec<PathBuf>,
+    /// paths the task may also write, create and remove things in
+    pub write: Vec<PathBuf>,
+    /// whether the task may open sockets other than Unix ones
+    pub network: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            read: Vec::new(),
+            write: Vec::new(),
+            network: true,
+        }
+    }
+}
+
+impl Sandbox {
+    /// Split a `:` separated list of paths, like `PATH`.
+    pub fn parse_paths(s: &str) -> Vec<PathBuf> {
+        s.split(':')
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Open the paths and build the filter ahead of forking, where it may
+    /// allocate and fail with a proper message.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn prepare(&self) -> io::Result<Confinement> {
+        let ruleset = if self.read.is_empty() && self.write.is_empty() {
+            None
+        } else {
+            Some(self.ruleset()?)
+        };
+        let filter = if self.network {
+            None
+        } else {
+            Some(network_filter()?)
+        };
+        Ok(Confinement { ruleset, filter })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn prepare(&self) -> io::Result<Confinement> {
+        if *self != Sandbox::default() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sandboxes need Landlock and seccomp, which are Linux only",
+            ));
+        }
+        Ok(Confinement {})
+    }
+
+    #[cfg(target_os = "linux")]
+    fn ruleset(&self) -> io::Result<std::os::fd::OwnedFd> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Landlock is not available: {}", e),
+            ));
+        }
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+        let rules = self
+            .read
+            .iter()
+            .map(|path| (path, ACCESS_FS_READ))
+            .chain(self.write.iter().map(|path| (path, handled)));
+        for (path, access) in rules {
+            let opened = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+                .and_then(|file| Ok((file.metadata()?.is_dir(), file)));
+            let (is_dir, file) = opened
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            // rights on directory entries can't be granted on a file
+            let access = if is_dir {
+                access
+            } else {
+                access & ACCESS_FS_FILE
+            };
+            let rule = PathBeneathAttr {
+                allowed_access: access & handled,
+                parent_fd: file.as_raw_fd(),
+            };
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(ruleset)
+    }
+}
+
+/// A prepared sandbox, entered by the child right before it execs.
+pub(crate) struct Confinement {
+    #[cfg(target_os = "linux")]
+    ruleset: Option<std::os::fd::OwnedFd>,
+    #[cfg(target_os = "linux")]
+    filter: Option<Vec<libc::sock_filter>>,
+}
+
+impl Confinement {
+    /// Runs between fork and exec, so only async-signal-safe calls belong
+    /// here. Neither can be undone, not even by a setuid program.
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            if self.ruleset.is_none() && self.filter.is_none() {
+                return Ok(());
+            }
+            if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(ruleset) = &self.ruleset {
+                let ret = unsafe {
+                    libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0)
+                };
+                if ret != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(filter) = &self.filter {
+                let program = libc::sock_fprog {
+                    len: filter.len() as libc::c_ushort,
+                    filter: filter.as_ptr() as *mut libc::sock_filter,
+                };
+                let ret = unsafe {
+                    libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &program as *const libc::sock_fprog,
+                    )
+                };
+                if ret != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+#[cfg(target_os = "linux")]
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+// execute, write, read, read dir, remove dir and file, make char device,
+// dir, file, socket, fifo, block device and symlink
+#[cfg(target_os = "linux")]
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+#[cfg(target_os = "linux")]
+const ACCESS_FS_READ: u64 = 1 | 1 << 2 | 1 << 3;
+#[cfg(target_os = "linux")]
+const ACCESS_FS_FILE: u64 = 1 | 1 << 1 | 1 << 2 | ACCESS_FS_TRUNCATE;
+#[cfg(target_os = "linux")]
+const ACCESS_FS_REFER: u64 = 1 << 13;
+#[cfg(target_os = "linux")]
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// A seccomp filter failing `socket(2)` with `EACCES` for anything but Unix
+/// sockets. io_uring is failed too, since it can open sockets on its own.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn network_filter() -> io::Result<Vec<libc::sock_filter>> {
+    use libc::{sock_filter, BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let instruction = |code: u32, k, jt, jf| sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset| instruction(BPF_LD | BPF_W | BPF_ABS, offset, 0, 0);
+    let jump_if =
+        |value, then, otherwise| instruction(BPF_JMP | BPF_JEQ | BPF_K, value, then, otherwise);
+    let ret = |action| instruction(BPF_RET | BPF_K, action, 0, 0);
+    // offsets into `struct seccomp_data`, the domain is the low half of
+    // the first argument on these little endian machines
+    let (nr, arch, domain) = (0, 4, 16);
+    let mut filter = vec![
+        load(arch),
+        jump_if(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(nr),
+    ];
+    // x32 calls carry a flag in the number and would slip past the checks
+    #[cfg(target_arch = "x86_64")]
+    filter.push(instruction(BPF_JMP | BPF_JGE | BPF_K, 0x4000_0000, 4, 0));
+    filter.extend([
+        jump_if(libc::SYS_io_uring_setup as u32, 3, 0),
+        jump_if(libc::SYS_socket as u32, 0, 3),
+        load(domain),
+        jump_if(libc::AF_UNIX as u32, 1, 0),
+        ret(libc::SECCOMP_RET_ERRNO | libc::EACCES as u32),
+        ret(libc::SECCOMP_RET_ALLOW),
+    ]);
+    Ok(filter)
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+fn network_filter() -> io::Result<Vec<libc::sock_filter>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "turning the network off is only supported on x86_64 and aarch64",
+    ))
+}
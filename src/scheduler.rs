@@ -0,0 +1,3459 @@
+use clap::ValueEnum;
+use rustix::process::{getpid, Pid, Signal};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use sysinfo::{System, SystemExt};
+use tracing::{debug, error, info, warn};
+
+use crate::backend::{Backend, LocalBackend};
+use crate::cgroup::{Cgroup, CgroupLimits};
+use crate::clock::{format_elapsed, format_utc, parse_interval, unix_now, unix_time};
+use crate::control::{ControlSocket, Request};
+#[cfg(feature = "dashboard")]
+use crate::dashboard;
+use crate::disk::{free_space, max_utilization};
+use crate::error::CirnoError;
+use crate::events::EventLog;
+use crate::gpu::{pick_gpus, query_gpus, GpuAllocations};
+use crate::handle::{Command, Inbox, SchedulerHandle};
+use crate::history::{Execution, History};
+use crate::http::{constant_time_eq, percent_decode, HttpRequest, HttpServer};
+use crate::logfile::{self, Compression};
+use crate::mail::Mailer;
+use crate::notify;
+use crate::observer::SchedulerObserver;
+use crate::otlp::Exporter;
+use crate::policy::{Conditions, ResourcePolicy, SchedulingPolicy};
+use crate::pool::WorkerPool;
+use crate::preexec::PreExec;
+use crate::pressure;
+use crate::probe::{ResourceProbe, ResourceSnapshot, SystemProbe};
+use crate::process::{
+    get_children, get_name, get_start_time, get_state, is_exist, kill_process_tree,
+    set_child_subreaper, try_wait_with_usage,
+};
+use crate::progress;
+use crate::signals;
+use crate::stream;
+use crate::systemd;
+use crate::task::{count_lines, parse_task_line, task_lines, StopPolicy, Task};
+use crate::thermal;
+use crate::timeline::Timeline;
+use crate::user::FileAccess;
+use crate::vmstat;
+use crate::watch::DirWatch;
+use crate::webhook::Webhook;
+
+/// Tell systemd the service is shutting down, if it runs under it.
+fn notify_stopping() {
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        warn!("failed to notify systemd: {}", e);
+    }
+}
+
+/// Parse `value` for the setting `name`, naming both if it is invalid.
+fn parse_setting<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid {} {}: {}", name, value, e))
+}
+
+/// What happens to the task picked when conditions are Bad.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PreemptMode {
+    /// terminate it and put it back into the queue
+    Kill,
+    /// SIGSTOP it and SIGCONT it once resources recover
+    Suspend,
+}
+
+/// Which running task gets preempted.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PreemptPolicy {
+    /// the most recently started task
+    Newest,
+    /// the task that has been running the longest
+    Oldest,
+    /// the task with the lowest `@priority`
+    LowestPriority,
+    /// the task whose process tree uses the most memory
+    BiggestMem,
+}
+
+/// Where the stderr of a task goes.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum StderrMode {
+    /// to `<name>.err` next to its `.txtlog`
+    Separate,
+    /// into its `.txtlog`, interleaved with stdout
+    Merge,
+    /// to /dev/null
+    Discard,
+}
+
+/// What a task's logs from an earlier attempt become when it is launched
+/// again, e.g. after being preempted.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LogPolicy {
+    /// overwritten
+    Truncate,
+    /// kept, the new attempt writes after them
+    Append,
+    /// kept, the new attempt writes to `<name>.attemptN.txtlog`
+    PerAttempt,
+}
+
+/// How cirno tells that the run is over.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum NotifyMode {
+    /// a desktop notification, with `notify-send`
+    Desktop,
+    /// the terminal bell
+    Bell,
+}
+
+/// What the last resource check measured.
+#[derive(Debug, Clone, Default)]
+struct Measurements {
+    /// per-cpu load and available memory in KiB
+    load: f64,
+    free_kib: u64,
+    reserved_cores: usize,
+    /// busiest disk in percent and hottest thermal zone in °C, if checked
+    io_utilization: Option<f64>,
+    temp: Option<f64>,
+    /// the checks that made the opinion Bad, or kept it from being Health
+    causes: Vec<&'static str>,
+    bad: bool,
+}
+
+/// A task that left cirno, kept to be shown and submitted again.
+#[derive(Debug, Clone)]
+struct Ended {
+    name: String,
+    line: String,
+    command: String,
+    outcome: String,
+    run_time: f64,
+    log: Option<PathBuf>,
+}
+
+/// How many ended tasks are kept.
+const MAX_ENDED: usize = 200;
+
+/// What cirno thinks of the resources at the last check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CirnoOpinion {
+    /// room for more tasks
+    Health,
+    /// keep what runs, launch nothing
+    Normal,
+    /// short of something, preempt a task
+    Bad,
+}
+
+/// Runs the submitted tasks as the resources of the machine allow, starting
+/// more while they are plentiful and preempting some once they run short.
+///
+/// Configure it with the `set_*` methods, `submit` the tasks and run them all
+/// with `do_it`.
+pub struct Scheduler {
+    todo_tasks: Vec<Task>,
+    max_workers: usize,
+    runing_tasks: Vec<Task>,
+    suspended_tasks: Vec<Task>,
+    preempt_mode: PreemptMode,
+    preempt_policy: Option<PreemptPolicy>,
+    policy: Box<dyn SchedulingPolicy>,
+    /// what the policy decided on at the last check
+    conditions: Conditions,
+    stderr_mode: StderrMode,
+    log_policy: LogPolicy,
+    stream: bool,
+    log_max_size: Option<u64>,
+    log_keep: usize,
+    compress_logs: Option<Compression>,
+    probe: Box<dyn ResourceProbe>,
+    backend: Box<dyn Backend>,
+    host: String,
+    /// the hosts tasks without `@host=` are spread over with ssh
+    hosts: Vec<String>,
+    sleep_duration: usize,
+    /// how often resources are checked, every tick if unset
+    probe_interval: Option<Duration>,
+    last_probe: Option<Instant>,
+    reserved_mem: usize,
+    per_task_mem: usize,
+    timeout: usize,
+    timeout_signal: Signal,
+    timeout_grace: Duration,
+    force_task: usize,
+    load_max: f64,
+    load_min: f64,
+    load_alpha: Option<f64>,
+    smoothed_load: Option<f64>,
+    min_dwell: usize,
+    last_opinion: Option<(CirnoOpinion, Instant)>,
+    measurements: Measurements,
+    /// why the last check launched nothing, logged with `--explain`
+    explanation: Vec<String>,
+    explain: bool,
+    events: Option<EventLog>,
+    psi_supported: bool,
+    psi_cpu_max: Option<f64>,
+    psi_memory_max: Option<f64>,
+    psi_io_max: Option<f64>,
+    max_swap_used: Option<usize>,
+    max_swapin_rate: Option<f64>,
+    last_swapin: Option<(u64, Instant)>,
+    min_disk_free: Option<usize>,
+    disk_paths: Vec<PathBuf>,
+    disk_low: bool,
+    io_max: Option<f64>,
+    io_devices: Vec<String>,
+    last_io: Option<(HashMap<String, u64>, Instant)>,
+    temp_max: Option<f64>,
+    last_throttles: Option<u64>,
+    gpu_util_max: u32,
+    gpu_warned: bool,
+    gpu_allocations: GpuAllocations,
+    default_pre_exec: PreExec,
+    stop_policy: StopPolicy,
+    cgroup_parent: Option<PathBuf>,
+    oom_retries: usize,
+    subreaper: bool,
+    /// adopted processes not reaped yet
+    orphans: Vec<Pid>,
+    leftovers: Vec<Leftover>,
+    kill_leftovers: bool,
+    /// SIGINT or SIGTERM was received, running tasks are being stopped
+    shutdown: bool,
+    /// SIGUSR2 was received, running tasks may finish but no new ones start
+    draining: bool,
+    /// neither launching nor preempting until resumed
+    paused: bool,
+    /// the input list, re-read on SIGHUP
+    task_list: Option<PathBuf>,
+    /// how often each line was in the task list when it was last read
+    task_lines: HashMap<String, usize>,
+    /// directory whose new files are submitted as task lists, keeping cirno
+    /// running when the queue is empty
+    watch: Option<DirWatch>,
+    /// the opinion last acted on
+    opinion: Option<CirnoOpinion>,
+    /// how many runs ended with each outcome recorded in the history
+    outcomes: HashMap<String, usize>,
+    /// runs that ended without exiting successfully
+    failed: usize,
+    /// how long the last run of each task took, in seconds
+    durations: HashMap<String, f64>,
+    /// the tasks that left cirno last, newest at the back
+    ended: VecDeque<Ended>,
+    /// how many tasks left cirno, and how long the ones that finished ran
+    done: usize,
+    /// the tasks that left cirno without succeeding, cancelled ones aside
+    failed_tasks: Vec<String>,
+    notify: Vec<NotifyMode>,
+    webhook: Option<Webhook>,
+    mailer: Option<Mailer>,
+    /// how many failed tasks are notified, once
+    notify_failures: Option<usize>,
+    run_start: Instant,
+    finished_time: (f64, usize),
+    progress: bool,
+    control: Option<ControlSocket>,
+    /// where the commands of `SchedulerHandle`s arrive, once one was made
+    inbox: Option<Inbox>,
+    metrics: Option<HttpServer>,
+    tracer: Option<Exporter>,
+    #[cfg(feature = "dashboard")]
+    dashboard: Option<HttpServer>,
+    /// the REST API and the token its clients have to send
+    api: Option<(HttpServer, String)>,
+    observers: Vec<Box<dyn SchedulerObserver>>,
+    timeline: Timeline,
+    /// running as a systemd notify service, SIGTERM drains
+    systemd: bool,
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+    /// zombie children of cirno, when they were first seen and whether they
+    /// were reported
+    zombies: Vec<(Pid, Instant, bool)>,
+    /// where this run writes its logs and status
+    run_dir: PathBuf,
+    /// where done markers are kept, shared by every run
+    done_dir: PathBuf,
+    skip_done: bool,
+    pool: WorkerPool,
+    /// compresses the logs of ended tasks, behind nothing but other logs
+    compressor: WorkerPool,
+    /// logs to compress once nothing writes to them anymore
+    uncompressed: Vec<Uncompressed>,
+    log_access: FileAccess,
+    history: Option<History>,
+    /// the session of the run in `history`
+    session_id: Option<i64>,
+    session_name: Option<String>,
+    metadata: Vec<(String, String)>,
+    adaptive_mem: bool,
+    /// whether the last Bad opinion was caused by low free memory
+    low_memory: bool,
+    auto_tune: bool,
+    tuned_workers: usize,
+    /// consecutive Healthy-but-capped (positive) or Bad (negative) checks
+    tune_streak: i32,
+}
+
+const TUNE_WINDOW: i32 = 3;
+
+/// A process that was part of a task's tree when the task ended.
+struct Leftover {
+    task: String,
+    pid: Pid,
+    start_time: u64,
+    since: Instant,
+    reported: bool,
+}
+
+/// The logs of an ended task, with what may still write to them.
+struct Uncompressed {
+    task: String,
+    logs: Vec<PathBuf>,
+    tees: Vec<JoinHandle<()>>,
+    /// processes left behind by the task, with their start times
+    writers: Vec<(Pid, u64)>,
+}
+
+impl Uncompressed {
+    fn written(&self) -> bool {
+        self.tees.iter().any(|tee| !tee.is_finished())
+            || self
+                .writers
+                .iter()
+                .any(|(pid, start_time)| get_start_time(*pid) == Some(*start_time))
+    }
+}
+
+/// How long a stopped task's processes get to go away before they count as
+/// left behind, longer than a stop takes.
+const LEFTOVER_GRACE: Duration = Duration::from_secs(10);
+
+/// How long a zombie child may wait for its reap before it is reported.
+const ZOMBIE_GRACE: Duration = Duration::from_secs(2);
+
+impl Scheduler {
+    /// A scheduler running at most `max_workers` tasks at once.
+    pub fn new(max_workers: usize) -> Scheduler {
+        Scheduler {
+            todo_tasks: Vec::new(),
+            max_workers,
+            runing_tasks: Vec::new(),
+            suspended_tasks: Vec::new(),
+            preempt_mode: PreemptMode::Kill,
+            stderr_mode: StderrMode::Separate,
+            log_policy: LogPolicy::Truncate,
+            stream: false,
+            log_max_size: None,
+            log_keep: 3,
+            compress_logs: None,
+            preempt_policy: None,
+            policy: Box::new(ResourcePolicy),
+            conditions: Conditions::default(),
+            probe: Box::new(SystemProbe::new()),
+            backend: Box::new(LocalBackend),
+            host: System::new().host_name().unwrap_or_default(),
+            hosts: Vec::new(),
+            sleep_duration: 10,
+            probe_interval: None,
+            last_probe: None,
+            reserved_mem: 6,
+            per_task_mem: 3,
+            timeout: 7200,
+            timeout_signal: Signal::Alarm,
+            timeout_grace: Duration::from_secs(60),
+            force_task: 1,
+            load_max: 2.0,
+            load_min: 1.0,
+            load_alpha: None,
+            smoothed_load: None,
+            min_dwell: 0,
+            last_opinion: None,
+            measurements: Measurements::default(),
+            explanation: Vec::new(),
+            explain: false,
+            events: None,
+            psi_supported: false,
+            psi_cpu_max: None,
+            psi_memory_max: None,
+            psi_io_max: None,
+            max_swap_used: None,
+            max_swapin_rate: None,
+            last_swapin: None,
+            min_disk_free: None,
+            disk_paths: Vec::new(),
+            disk_low: false,
+            io_max: None,
+            io_devices: Vec::new(),
+            last_io: None,
+            temp_max: None,
+            last_throttles: None,
+            gpu_util_max: 100,
+            gpu_warned: false,
+            gpu_allocations: GpuAllocations::default(),
+            default_pre_exec: PreExec::default(),
+            stop_policy: StopPolicy::default(),
+            cgroup_parent: None,
+            oom_retries: 0,
+            subreaper: false,
+            orphans: Vec::new(),
+            leftovers: Vec::new(),
+            kill_leftovers: false,
+            shutdown: false,
+            draining: false,
+            paused: false,
+            task_list: None,
+            task_lines: HashMap::new(),
+            watch: None,
+            opinion: None,
+            outcomes: HashMap::new(),
+            failed: 0,
+            durations: HashMap::new(),
+            ended: VecDeque::new(),
+            done: 0,
+            failed_tasks: Vec::new(),
+            notify: Vec::new(),
+            webhook: None,
+            mailer: None,
+            notify_failures: None,
+            run_start: Instant::now(),
+            finished_time: (0.0, 0),
+            progress: false,
+            control: None,
+            inbox: None,
+            metrics: None,
+            tracer: None,
+            #[cfg(feature = "dashboard")]
+            dashboard: None,
+            api: None,
+            observers: Vec::new(),
+            timeline: Timeline::new(),
+            systemd: false,
+            watchdog: None,
+            last_ping: Instant::now(),
+            zombies: Vec::new(),
+            run_dir: PathBuf::from("run"),
+            done_dir: PathBuf::from("run"),
+            skip_done: false,
+            pool: WorkerPool::new(4),
+            compressor: WorkerPool::new(1),
+            uncompressed: Vec::new(),
+            log_access: FileAccess::default(),
+            history: None,
+            session_id: None,
+            session_name: None,
+            metadata: Vec::new(),
+            adaptive_mem: false,
+            low_memory: false,
+            auto_tune: false,
+            tuned_workers: max_workers,
+            tune_streak: 0,
+        }
+    }
+
+    /// Write logs and status to `dir`, and done markers to `done_dir`, both
+    /// `run` by default.
+    pub fn set_run_dir(&mut self, dir: PathBuf, done_dir: PathBuf) {
+        self.run_dir = dir;
+        self.done_dir = done_dir;
+    }
+
+    /// Seconds between two checks of the children.
+    pub fn set_sleep_duration(&mut self, duration: usize) {
+        self.sleep_duration = duration;
+    }
+
+    /// Check resources and decide about launching and preempting every
+    /// `interval` instead of every tick. A task exiting always triggers a check.
+    pub fn set_probe_interval(&mut self, interval: Duration) {
+        self.probe_interval = Some(interval);
+    }
+
+    /// Submit the lines of every file that shows up in the watched directory.
+    pub fn set_watch(&mut self, watch: DirWatch) {
+        self.watch = Some(watch);
+    }
+
+    /// Take commands from the control socket, see `handle_request`.
+    pub fn set_control(&mut self, control: ControlSocket) {
+        self.control = Some(control);
+    }
+
+    /// A handle to submit, cancel and drain from other threads while `do_it`
+    /// runs.
+    pub fn handle(&mut self) -> std::io::Result<SchedulerHandle> {
+        let inbox = match self.inbox.take() {
+            Some(inbox) => inbox,
+            None => Inbox::new()?,
+        };
+        let handle = inbox.handle();
+        self.inbox = Some(inbox);
+        Ok(handle)
+    }
+
+    /// Serve the Prometheus metrics at `/metrics`, see `metrics`.
+    pub fn set_metrics(&mut self, server: HttpServer) {
+        self.metrics = Some(server);
+    }
+
+    /// Notify with every one of `modes` at the end of the run and once
+    /// `failures` tasks failed, if given.
+    pub fn set_notify(&mut self, modes: Vec<NotifyMode>, failures: Option<usize>) {
+        self.notify = modes;
+        self.notify_failures = failures;
+    }
+
+    fn notify(&mut self, summary: &str, body: &str) {
+        for mode in self.notify.clone() {
+            match mode {
+                NotifyMode::Bell => {
+                    if let Err(e) = notify::bell() {
+                        warn!("failed to ring the bell: {}", e);
+                    }
+                }
+                NotifyMode::Desktop => {
+                    let (summary, body) = (summary.to_string(), body.to_string());
+                    // notify-send may wait for a slow notification daemon
+                    self.pool.execute(move || {
+                        if let Err(e) = notify::desktop(&summary, &body) {
+                            warn!("failed to show a desktop notification: {}", e);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// What became of the tasks and how long the run took, e.g.
+    /// `3 succeeded, 1 failed (train-3) in 01:02:03 (session sweep)`.
+    fn summary(&self) -> String {
+        let cancelled = self.outcomes.get("cancelled").copied().unwrap_or(0);
+        let failed = self.failed_tasks.len();
+        let mut counts = vec![format!("{} succeeded", self.done - failed - cancelled)];
+        if failed > 0 {
+            let mut names = self.failed_tasks[..failed.min(10)].join(", ");
+            if failed > 10 {
+                names.push_str(", ...");
+            }
+            counts.push(format!("{} failed ({})", failed, names));
+        }
+        if cancelled > 0 {
+            counts.push(format!("{} cancelled", cancelled));
+        }
+        let left = self.todo_tasks.len() + self.runing_tasks.len() + self.suspended_tasks.len();
+        if left > 0 {
+            counts.push(format!("{} not done", left));
+        }
+        let mut summary = format!(
+            "{} in {}",
+            counts.join(", "),
+            format_elapsed(self.run_start.elapsed().as_secs())
+        );
+        if let Some(name) = &self.session_name {
+            summary.push_str(&format!(" (session {})", name));
+        }
+        summary
+    }
+
+    /// Mail the summary of the run when it ends with `mailer`.
+    pub fn set_mailer(&mut self, mailer: Mailer) {
+        self.mailer = Some(mailer);
+    }
+
+    /// What became of every task of the run that ended with `end`, for the
+    /// mail.
+    fn report(&self, end: &str) -> String {
+        let elapsed = self.run_start.elapsed();
+        let start = unix_now() - elapsed.as_secs_f64();
+        let cancelled = self.outcomes.get("cancelled").copied().unwrap_or(0);
+        let mut report = format!(
+            "The run on {} {}.\n\nstarted: {} UTC\nwall time: {}\nrun directory: {}\n\n",
+            self.host.clone(),
+            end,
+            format_utc(start, ' ', ':'),
+            format_elapsed(elapsed.as_secs()),
+            self.run_dir.display()
+        );
+        let session = self.session_lines();
+        if !session.is_empty() {
+            report.push_str(&format!("{}\n", session));
+        }
+        report.push_str(&format!(
+            "succeeded: {}\nfailed: {}\ncancelled: {}\nnot done: {}\n",
+            self.done - self.failed_tasks.len() - cancelled,
+            self.failed_tasks.len(),
+            cancelled,
+            self.todo_tasks.len() + self.runing_tasks.len() + self.suspended_tasks.len()
+        ));
+        if !self.failed_tasks.is_empty() {
+            report.push_str("\nfailed tasks:\n");
+            for name in &self.failed_tasks {
+                report.push_str(&format!("  {}\n", name));
+            }
+        }
+        report
+    }
+
+    /// Draw a progress bar on stderr, see `draw_progress`.
+    pub fn set_progress(&mut self, progress: bool) {
+        self.progress = progress;
+    }
+
+    /// The tasks done out of all seen so far, and when the rest should be
+    /// done: the mean run time of the finished ones spread over the tasks
+    /// running now.
+    fn draw_progress(&self) {
+        if !self.progress {
+            return;
+        }
+        let active: Vec<&Task> = self
+            .runing_tasks
+            .iter()
+            .chain(&self.suspended_tasks)
+            .collect();
+        let total = self.done + active.len() + self.todo_tasks.len();
+        let mut line = format!(
+            "{}  {} running",
+            progress::bar(self.done, total),
+            self.runing_tasks.len()
+        );
+        let (time, finished) = self.finished_time;
+        if finished > 0 {
+            let mean = time / finished as f64;
+            let left: f64 = active
+                .iter()
+                .map(|task| {
+                    (mean - task.start_time.elapsed().unwrap_or_default().as_secs_f64()).max(0.0)
+                })
+                .sum::<f64>()
+                + mean * self.todo_tasks.len() as f64;
+            let parallel = self.runing_tasks.len().max(1) as f64;
+            line.push_str(&format!(
+                "  ETA {}",
+                format_elapsed((left / parallel) as u64)
+            ));
+        }
+        progress::set(line);
+    }
+
+    /// Serve the REST API to clients sending `token`, see `serve_api`.
+    pub fn set_api(&mut self, server: HttpServer, token: String) {
+        self.api = Some((server, token));
+    }
+
+    /// Serve the web dashboard, see `serve_dashboard`.
+    #[cfg(feature = "dashboard")]
+    pub fn set_dashboard(&mut self, server: HttpServer) {
+        self.dashboard = Some(server);
+    }
+
+    /// Report to systemd, ping its watchdog and treat `systemctl stop` as a
+    /// drain.
+    pub fn set_systemd(&mut self) {
+        self.systemd = true;
+        self.watchdog = systemd::watchdog_interval();
+    }
+
+    /// Remember the task list submitted from `path`, see `reload`.
+    pub fn set_task_list(&mut self, path: &Path, contents: &str) {
+        self.task_list = Some(path.to_path_buf());
+        self.task_lines = count_lines(contents);
+    }
+
+    /// GiB of memory kept free.
+    pub fn set_reserved_mem(&mut self, mem: usize) {
+        self.reserved_mem = mem;
+    }
+
+    /// Seconds a task may run before it gets the timeout signal.
+    pub fn set_timeout(&mut self, timeout: usize) {
+        self.timeout = timeout;
+    }
+
+    /// Signal sent on timeout, and how long a task may ignore it before it
+    /// is stopped.
+    pub fn set_timeout_escalation(&mut self, signal: Signal, grace: Duration) {
+        self.timeout_signal = signal;
+        self.timeout_grace = grace;
+    }
+
+    /// GiB of memory a task is expected to take.
+    pub fn set_per_task_mem(&mut self, mem: usize) {
+        self.per_task_mem = mem;
+    }
+
+    /// Tasks kept running whatever the resources.
+    pub fn set_force_task(&mut self, force_task: usize) {
+        self.force_task = force_task;
+    }
+
+    /// Load per cpu above which conditions are Bad.
+    pub fn set_load_max(&mut self, load_max: f64) {
+        self.load_max = load_max;
+    }
+
+    /// Load per cpu below which conditions are Health.
+    pub fn set_load_min(&mut self, load_min: f64) {
+        self.load_min = load_min;
+    }
+
+    /// Smooth the 1-minute load with the EWMA weight `alpha` instead of using
+    /// the 5-minute load.
+    pub fn set_load_smoothing(&mut self, alpha: f64) {
+        self.load_alpha = Some(alpha.clamp(0.0, 1.0));
+    }
+
+    /// Seconds an opinion must last before it is acted on.
+    pub fn set_min_dwell(&mut self, min_dwell: usize) {
+        self.min_dwell = min_dwell;
+    }
+
+    /// Thresholds in percent for the PSI `some avg10` of cpu, memory and io.
+    pub fn set_pressure_max(&mut self, cpu: Option<f64>, memory: Option<f64>, io: Option<f64>) {
+        self.psi_cpu_max = cpu;
+        self.psi_memory_max = memory;
+        self.psi_io_max = io;
+        self.psi_supported = pressure::is_supported();
+        if !self.psi_supported && (cpu.is_some() || memory.is_some() || io.is_some()) {
+            warn!("pressure stall information is not available, ignoring --psi-* thresholds");
+        }
+    }
+
+    /// GB of swap in use above which memory counts as short.
+    pub fn set_max_swap_used(&mut self, max: Option<usize>) {
+        self.max_swap_used = max;
+    }
+
+    /// MB/s of swap-in above which memory counts as short.
+    pub fn set_max_swapin_rate(&mut self, max: Option<f64>) {
+        self.max_swapin_rate = max;
+    }
+
+    /// Refuse to launch tasks while the run directory, or any of `paths`, has
+    /// less than `min` GB free.
+    pub fn set_min_disk_free(&mut self, min: usize, paths: Vec<PathBuf>) {
+        self.min_disk_free = Some(min);
+        self.disk_paths.extend(paths);
+    }
+
+    /// Max utilization in percent of the busiest disk, or of `devices` only.
+    pub fn set_io_max(&mut self, max: f64, devices: Vec<String>) {
+        self.io_max = Some(max);
+        self.io_devices = devices;
+    }
+
+    /// °C of the hottest thermal zone above which conditions are Bad; throttling
+    /// counts too.
+    pub fn set_temp_max(&mut self, max: f64) {
+        self.temp_max = Some(max);
+        if thermal::max_temperature().is_none() {
+            warn!("no thermal zones found, --temp-max only reacts to throttling");
+        }
+    }
+
+    /// Devices busier than this (percent) are not handed to new tasks.
+    pub fn set_gpu_util_max(&mut self, max: u32) {
+        self.gpu_util_max = max;
+    }
+
+    /// Put OOM-killed tasks back into the queue up to `retries` times, each
+    /// time with a bigger memory reservation.
+    pub fn set_oom_retries(&mut self, retries: usize) {
+        self.oom_retries = retries;
+    }
+
+    /// Adopt orphaned grandchildren of the tasks, see `adopt_orphans`.
+    pub fn set_subreaper(&mut self) {
+        match set_child_subreaper() {
+            Ok(()) => self.subreaper = true,
+            Err(e) => warn!("failed to become a child subreaper: {}", e),
+        }
+    }
+
+    /// SIGKILL processes that outlived their task instead of only reporting them.
+    pub fn set_kill_leftovers(&mut self, kill_leftovers: bool) {
+        self.kill_leftovers = kill_leftovers;
+    }
+
+    /// Skip tasks with a done marker, and write one when a task succeeds.
+    pub fn set_skip_done(&mut self, skip_done: bool) {
+        self.skip_done = skip_done;
+    }
+
+    /// Mode and owner of the log files.
+    pub fn set_log_access(&mut self, access: FileAccess) {
+        self.log_access = access;
+    }
+
+    /// What happens to the task preempted when conditions are Bad.
+    pub fn set_preempt_mode(&mut self, mode: PreemptMode) {
+        self.preempt_mode = mode;
+    }
+
+    /// Where the stderr of tasks goes.
+    pub fn set_stderr_mode(&mut self, mode: StderrMode) {
+        self.stderr_mode = mode;
+    }
+
+    /// What the logs of an earlier attempt become when a task is launched again.
+    pub fn set_log_policy(&mut self, policy: LogPolicy) {
+        self.log_policy = policy;
+    }
+
+    /// Also print the output of tasks on stdout, every line behind its task.
+    pub fn set_stream(&mut self, stream: bool) {
+        self.stream = stream;
+    }
+
+    /// Rotate a task's log once it grows over `max_size` bytes, keeping `keep`
+    /// older copies of it.
+    pub fn set_log_rotation(&mut self, max_size: u64, keep: usize) {
+        self.log_max_size = Some(max_size);
+        self.log_keep = keep;
+    }
+
+    /// Compress the logs of tasks that are done for good in the background.
+    pub fn set_compress_logs(&mut self, compression: Compression) {
+        self.compress_logs = Some(compression);
+    }
+
+    /// Compress the logs of the ended `task` once its output is copied and
+    /// the processes it left behind are gone too.
+    fn compress_logs(&mut self, task: &mut Task) {
+        if self.compress_logs.is_none() {
+            return;
+        }
+        let mut logs = Vec::new();
+        for log in &task.logs {
+            logs.extend(logfile::rotated(log));
+            logs.push(log.clone());
+        }
+        let writers = task
+            .tree
+            .iter()
+            .skip(1)
+            .filter_map(|pid| Some((*pid, get_start_time(*pid)?)))
+            .collect();
+        self.uncompressed.push(Uncompressed {
+            task: task.name.clone(),
+            logs,
+            tees: std::mem::take(&mut task.tees),
+            writers,
+        });
+        self.compress_ready();
+    }
+
+    /// Hand the logs nothing writes to anymore to the compressor.
+    fn compress_ready(&mut self) {
+        let compression = match self.compress_logs {
+            Some(compression) => compression,
+            None => return,
+        };
+        let (waiting, ready) = std::mem::take(&mut self.uncompressed)
+            .into_iter()
+            .partition(Uncompressed::written);
+        self.uncompressed = waiting;
+        for Uncompressed { task, logs, .. } in ready {
+            self.compressor.execute(move || {
+                for log in logs {
+                    if let Err(e) = logfile::compress(&log, compression) {
+                        warn!("task: {} failed to compress {}: {}", task, log.display(), e);
+                    }
+                }
+            });
+        }
+    }
+
+    fn rotate_logs(&self) {
+        let max_size = match self.log_max_size {
+            Some(max_size) => max_size,
+            None => return,
+        };
+        for task in self.runing_tasks.iter().chain(&self.suspended_tasks) {
+            for log in &task.logs {
+                let size = fs::metadata(log).map(|meta| meta.len()).unwrap_or(0);
+                if size <= max_size {
+                    continue;
+                }
+                match logfile::rotate(log, self.log_keep) {
+                    Ok(()) => debug!("task: {} rotated {}", task.name, log.display()),
+                    Err(e) => warn!(
+                        "task: {} failed to rotate {}: {}",
+                        task.name,
+                        log.display(),
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// The `extension` log of the next attempt of `task`.
+    fn log_path(&self, task: &Task, extension: &str) -> PathBuf {
+        let attempt = task.attempts + 1;
+        let name = if self.log_policy == LogPolicy::PerAttempt && attempt > 1 {
+            format!("{}.attempt{}.{}", task.name, attempt, extension)
+        } else {
+            format!("{}.{}", task.name, extension)
+        };
+        self.run_dir.join(name)
+    }
+
+    /// Point the output of the next attempt of `task` at its logs, and return
+    /// them.
+    fn open_logs(&self, task: &mut Task) -> Result<Vec<PathBuf>, CirnoError> {
+        let log = match &task.stdout_file {
+            Some(path) => path.clone(),
+            None => self.log_path(task, "txtlog"),
+        };
+        let append = self.log_policy == LogPolicy::Append;
+        task.stdout_from_file(&log, self.stderr_mode == StderrMode::Merge, append)?;
+        let mut logs = vec![log];
+        match self.stderr_mode {
+            StderrMode::Separate => {
+                let err = match &task.stderr_file {
+                    Some(path) => path.clone(),
+                    None => self.log_path(task, "err"),
+                };
+                task.stderr_from_file(&err, append)?;
+                logs.push(err);
+            }
+            StderrMode::Merge => {}
+            StderrMode::Discard => {
+                task.stderr(Stdio::null());
+            }
+        }
+        for log in &logs {
+            if let Err(e) = self.log_access.apply(log) {
+                warn!("task: {} failed to set log permissions: {}", task.name, e);
+            }
+        }
+        Ok(logs)
+    }
+
+    /// Which running task is preempted, by default the biggest one under memory
+    /// pressure, else the newest.
+    pub fn set_preempt_policy(&mut self, policy: PreemptPolicy) {
+        self.preempt_policy = Some(policy);
+    }
+
+    /// Decide when to launch, wait or preempt, and which task, with `policy`
+    /// instead of the resource thresholds alone.
+    pub fn set_policy(&mut self, policy: Box<dyn SchedulingPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Admit tasks using their peak memory from previous runs instead of the
+    /// per-task memory.
+    pub fn set_adaptive_mem(&mut self, adaptive_mem: bool) {
+        self.adaptive_mem = adaptive_mem;
+    }
+
+    /// Treat `max_workers` as an upper bound and let the cap adapt, starting
+    /// from `force_task`.
+    pub fn set_auto_tune(&mut self, auto_tune: bool) {
+        self.auto_tune = auto_tune;
+        self.tuned_workers = self.force_task.max(1).min(self.max_workers);
+        self.tune_streak = 0;
+    }
+
+    /// Log why nothing was launched after every resource check that didn't.
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+    }
+
+    /// Note why the check that gave `opinion` launched nothing, with what was
+    /// measured against which threshold.
+    fn explain_idle(&mut self, opinion: CirnoOpinion) {
+        let explanation = if self.todo_tasks.is_empty() {
+            vec!["no task is queued".to_string()]
+        } else if self.draining {
+            vec!["draining".to_string()]
+        } else {
+            let mut reasons = self.reasons();
+            if reasons.is_empty() {
+                reasons.push(match opinion {
+                    CirnoOpinion::Health => {
+                        "no queued task fits the free cores and gpus".to_string()
+                    }
+                    _ => format!(
+                        "holding the last opinion for --min-dwell {}s",
+                        self.min_dwell
+                    ),
+                });
+            }
+            reasons
+        };
+        if self.explain {
+            info!("not launching: {}", explanation.join("; "));
+        }
+        self.explanation = explanation;
+    }
+
+    /// The causes of the last resource check, spelt out.
+    fn reasons(&self) -> Vec<String> {
+        let m = &self.measurements;
+        let half = if m.bad { "" } else { "half of " };
+        m.causes
+            .iter()
+            .map(|cause| match *cause {
+                "memory" if m.bad => format!(
+                    "free memory {} GB is below --reserved-mem {} GB",
+                    m.free_kib >> 20,
+                    self.reserved_mem
+                ),
+                "memory" => format!(
+                    "free memory {} MB is below --reserved-mem {} GB plus {} MB for the next task",
+                    m.free_kib >> 10,
+                    self.reserved_mem,
+                    self.next_task_mem() >> 10
+                ),
+                "load" if m.bad => format!(
+                    "load {:.2} is above --load-max {:.2}",
+                    m.load, self.load_max
+                ),
+                "load" => format!(
+                    "load {:.2} is above --load-min {:.2}",
+                    m.load, self.load_min
+                ),
+                "pressure" => format!("pressure stall is above {}a --psi-*-max", half),
+                "swap" => "swap use or swap-in rate is above --max-swap-used or --max-swapin-rate"
+                    .to_string(),
+                "io" => format!(
+                    "disk utilization {:.0}% is above {}--io-max {:.0}%",
+                    m.io_utilization.unwrap_or_default(),
+                    half,
+                    self.io_max.unwrap_or_default()
+                ),
+                "thermal" => match m.temp {
+                    Some(temp) => format!(
+                        "cpu at {:.0}°C or throttling, --temp-max is {:.0}°C",
+                        temp,
+                        self.temp_max.unwrap_or_default()
+                    ),
+                    None => "cpu is throttling".to_string(),
+                },
+                "workers" => format!(
+                    "{} of {} cores are taken",
+                    m.reserved_cores,
+                    self.worker_cap()
+                ),
+                "disk" => format!(
+                    "free disk space is below --min-disk-free {} GB",
+                    self.min_disk_free.unwrap_or_default()
+                ),
+                other => other.to_string(),
+            })
+            .collect()
+    }
+
+    /// Write every scheduler event to `events`.
+    pub fn set_events(&mut self, events: EventLog) {
+        self.events = Some(events);
+    }
+
+    fn event(&mut self, event: &str, fields: serde_json::Value) {
+        if let Some(events) = &mut self.events {
+            if let Err(e) = events.write(event, fields) {
+                warn!("failed to write {} event: {}", event, e);
+            }
+        }
+    }
+
+    /// Take the load, memory, pressure, swap, disk and temperature readings
+    /// from `probe` instead of this machine.
+    pub fn set_probe(&mut self, probe: Box<dyn ResourceProbe>) {
+        self.probe = probe;
+    }
+
+    /// Run the attempts of the tasks with `backend` instead of as children
+    /// of cirno.
+    pub fn set_backend(&mut self, backend: Box<dyn Backend>) {
+        self.backend = backend;
+    }
+
+    /// Run tasks without a host of their own over ssh on one of `hosts`,
+    /// the one running the fewest at launch.
+    pub fn set_hosts(&mut self, hosts: Vec<String>) {
+        self.hosts = hosts;
+    }
+
+    /// Tell `observer` about tasks starting, finishing and being preempted,
+    /// and about changes of opinion.
+    pub fn add_observer(&mut self, observer: Box<dyn SchedulerObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Send a span per task to an OTLP collector.
+    pub fn set_tracer(&mut self, tracer: Exporter) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Open the span of `task` on its first start and hand it to the task as
+    /// `TRACEPARENT`.
+    fn open_span(&self, task: &mut Task) {
+        if let Some(tracer) = &self.tracer {
+            let span = task.span.get_or_insert_with(|| tracer.start(&task.name));
+            task.handler.env("TRACEPARENT", span.traceparent());
+        }
+    }
+
+    /// An event about `task`, also added to its span and the timeline.
+    fn task_event(&mut self, task: &mut Task, event: &str, mut fields: serde_json::Value) {
+        if self.session_name.is_some() || !self.metadata.is_empty() {
+            fields["session"] = self.session_json();
+        }
+        if let Some(span) = &mut task.span {
+            span.event(event, &fields);
+        }
+        match (event, task.lane) {
+            ("started" | "resumed", _) => task.lane = Some(self.timeline.begin()),
+            ("timed_out", Some(lane)) => self.timeline.mark(lane, &task.name, event),
+            (_, Some(lane)) => {
+                let args = json!({ "attempt": task.attempts });
+                self.timeline.end(lane, &task.name, event, args);
+                task.lane = None;
+            }
+            (_, None) => {}
+        }
+        self.event(event, fields);
+    }
+
+    /// Close the span of `task`, which is leaving cirno, and export it in
+    /// the background.
+    fn close_span(
+        &mut self,
+        task: &mut Task,
+        outcome: &str,
+        status: Option<std::process::ExitStatus>,
+    ) {
+        let (tracer, mut span) = match (&self.tracer, task.span.take()) {
+            (Some(tracer), Some(span)) => (tracer.clone(), span),
+            _ => return,
+        };
+        span.set("cirno.outcome", json!(outcome));
+        span.set("cirno.attempts", json!(task.attempts));
+        span.set("cirno.oom_retries", json!(task.oom_retries));
+        span.set("cirno.peak_rss_kib", json!(task.peak_rss));
+        span.set("process.command_line", json!(task.command()));
+        if let Some(code) = status.and_then(|s| s.code()) {
+            span.set("process.exit_code", json!(code));
+        }
+        if let Some(signal) = status.and_then(|s| s.signal()) {
+            span.set("process.signal", json!(signal));
+        }
+        let ok = status.is_some_and(|s| s.success());
+        let name = task.name.clone();
+        self.pool.execute(move || {
+            if let Err(e) = tracer.export(span, ok) {
+                warn!("task: {} failed to export span: {}", name, e);
+            }
+        });
+    }
+
+    /// Post the start and end of the run and failing and timed out tasks to
+    /// `webhook`.
+    pub fn set_webhook(&mut self, webhook: Webhook) {
+        self.webhook = Some(webhook);
+    }
+
+    /// Post `event` in the background, with `text` for chat services to
+    /// show: Slack and Matrix read `text`, Discord `content`.
+    fn post_webhook(&mut self, event: &str, text: String, fields: serde_json::Value) {
+        let webhook = match &self.webhook {
+            Some(webhook) => webhook.clone(),
+            None => return,
+        };
+        let mut body = json!({
+            "event": event,
+            "time": unix_now(),
+            "host": self.host,
+            "text": text,
+            "content": text,
+        });
+        if let (Some(body), serde_json::Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        let event = event.to_string();
+        self.pool.execute(move || {
+            if let Err(e) = webhook.post(&body.to_string()) {
+                warn!("failed to post {} to the webhook: {}", event, e);
+            }
+        });
+    }
+
+    /// Record every run of a task in `history`, as part of `session`.
+    pub fn set_history(&mut self, history: History, session: i64) {
+        self.history = Some(history);
+        self.session_id = Some(session);
+    }
+
+    /// Name the run and tag it with KEY=VALUE `metadata`, shown in the
+    /// status, events, notifications and dashboard.
+    pub fn set_session(&mut self, name: Option<String>, metadata: Vec<(String, String)>) {
+        self.session_name = name;
+        self.metadata = metadata;
+    }
+
+    /// The name and metadata of the run, for events and the dashboard.
+    fn session_json(&self) -> serde_json::Value {
+        let metadata: serde_json::Map<String, serde_json::Value> = self
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), json!(value)))
+            .collect();
+        json!({ "name": self.session_name, "metadata": metadata })
+    }
+
+    /// A `session: NAME` line and a `session KEY: VALUE` line per metadata,
+    /// for the status and the report.
+    fn session_lines(&self) -> String {
+        let mut lines = String::new();
+        if let Some(name) = &self.session_name {
+            lines.push_str(&format!("session: {}\n", name));
+        }
+        for (key, value) in &self.metadata {
+            lines.push_str(&format!("session {}: {}\n", key, value));
+        }
+        lines
+    }
+
+    fn done_marker(&self, name: &str) -> PathBuf {
+        self.done_dir.join(format!("{}.done", name))
+    }
+
+    fn remember_ended(&mut self, task: &Task, outcome: &str, run_time: f64, failed: bool) {
+        self.done += 1;
+        if failed {
+            self.failed_tasks.push(task.name.clone());
+            if self.notify_failures == Some(self.failed_tasks.len()) {
+                let body = format!(
+                    "{} tasks failed, the last one {}",
+                    self.failed_tasks.len(),
+                    task.name
+                );
+                self.notify("cirno: tasks are failing", &body);
+            }
+        }
+        if outcome == "finished" {
+            self.finished_time.0 += run_time;
+            self.finished_time.1 += 1;
+        }
+        if self.ended.len() == MAX_ENDED {
+            self.ended.pop_front();
+        }
+        self.ended.push_back(Ended {
+            name: task.name.clone(),
+            line: task.line.clone(),
+            command: task.command(),
+            outcome: outcome.to_string(),
+            run_time,
+            log: task.logs.first().cloned(),
+        });
+    }
+
+    fn record(&mut self, task: &mut Task, outcome: &str, status: Option<std::process::ExitStatus>) {
+        for observer in &mut self.observers {
+            if outcome == "preempted" {
+                observer.on_task_preempted(task, PreemptMode::Kill);
+            } else {
+                observer.on_task_finish(task, outcome, status);
+            }
+        }
+        *self.outcomes.entry(outcome.to_string()).or_default() += 1;
+        // preempted, cancelled and interrupted runs weren't given the chance
+        let failed = match outcome {
+            "finished" => !status.is_some_and(|s| s.success()),
+            "error" | "oom" => true,
+            _ => false,
+        };
+        if failed {
+            self.failed += 1;
+        }
+        let run_time = task.start_time.elapsed().unwrap_or_default().as_secs_f64();
+        self.durations.insert(task.name.clone(), run_time);
+        self.task_event(
+            task,
+            outcome,
+            json!({
+                "task": task.name,
+                "attempt": task.attempts,
+                "exit_code": status.and_then(|s| s.code()),
+                "signal": status.and_then(|s| s.signal()),
+                "run_time": run_time,
+                "peak_rss": task.peak_rss,
+            }),
+        );
+        // preempted and OOM-killed tasks with retries left are queued again
+        let requeued =
+            outcome == "preempted" || (outcome == "oom" && task.oom_retries < self.oom_retries);
+        if !requeued {
+            self.close_span(task, outcome, status);
+            let failed = outcome != "cancelled" && !status.is_some_and(|s| s.success());
+            self.remember_ended(task, outcome, run_time, failed);
+            if failed {
+                let exit = match (
+                    status.and_then(|s| s.code()),
+                    status.and_then(|s| s.signal()),
+                ) {
+                    (Some(code), _) => format!("exit code {}", code),
+                    (None, Some(signal)) => format!("signal {}", signal),
+                    (None, None) => outcome.to_string(),
+                };
+                let text = format!(
+                    "task {} failed ({}) after {}",
+                    task.name,
+                    exit,
+                    format_elapsed(run_time as u64)
+                );
+                self.post_webhook(
+                    "task_failed",
+                    text,
+                    json!({
+                        "task": task.name,
+                        "outcome": outcome,
+                        "exit_code": status.and_then(|s| s.code()),
+                        "signal": status.and_then(|s| s.signal()),
+                        "run_time": run_time,
+                    }),
+                );
+            }
+        }
+        let history = match &self.history {
+            Some(history) => history,
+            None => return,
+        };
+        let mut usage = task.usage.unwrap_or_default();
+        usage.user_time += task.adopted_usage.user_time;
+        usage.sys_time += task.adopted_usage.sys_time;
+        usage.max_rss = usage.max_rss.max(task.adopted_usage.max_rss);
+        let execution = Execution {
+            name: task.name.clone(),
+            command: task.command(),
+            start_time: unix_time(task.start_time),
+            end_time: unix_now(),
+            outcome: outcome.to_string(),
+            exit_code: status.and_then(|s| s.code()),
+            signal: status.and_then(|s| s.signal()),
+            user_time: usage.user_time,
+            sys_time: usage.sys_time,
+            max_rss: usage.max_rss.max(task.peak_rss),
+            session: self.session_name.clone(),
+        };
+        if let Err(e) = history.record(&execution, self.session_id) {
+            warn!("task: {} failed to record history: {}", task.name, e);
+        }
+    }
+
+    /// Settings for tasks that don't bring their own.
+    pub fn set_default_pre_exec(&mut self, pre_exec: PreExec) {
+        self.default_pre_exec = pre_exec;
+    }
+
+    /// How tasks are asked to stop, under their own `@stop_*` options.
+    pub fn set_stop_policy(&mut self, policy: StopPolicy) {
+        self.stop_policy = policy;
+    }
+
+    /// Run every task in its own cgroup below `parent`.
+    pub fn set_cgroup_parent(&mut self, parent: PathBuf) {
+        match Cgroup::prepare_parent(&parent) {
+            Ok(()) => self.cgroup_parent = Some(parent),
+            Err(e) => warn!(
+                "failed to prepare cgroup {}: {}, running tasks without cgroups",
+                parent.display(),
+                e
+            ),
+        }
+    }
+
+    /// Queue `task`, unless done tasks are skipped and it has a done marker.
+    pub fn submit(&mut self, mut task: Task) {
+        task.pre_exec.nice = task.pre_exec.nice.or(self.default_pre_exec.nice);
+        task.pre_exec.ionice = task.pre_exec.ionice.or(self.default_pre_exec.ionice);
+        task.pre_exec.oom_score_adj = task
+            .pre_exec
+            .oom_score_adj
+            .or(self.default_pre_exec.oom_score_adj);
+        task.pre_exec.unshare = task.pre_exec.unshare.or(self.default_pre_exec.unshare);
+        task.pre_exec.death_signal = self.default_pre_exec.death_signal;
+        for limit in &self.default_pre_exec.rlimits {
+            if !task
+                .pre_exec
+                .rlimits
+                .iter()
+                .any(|own| own.resource == limit.resource)
+            {
+                task.pre_exec.rlimits.push(*limit);
+            }
+        }
+        task.stop_policy = task.stop_policy.or(self.stop_policy);
+        task.cgroup_parent = self.cgroup_parent.clone();
+        if task.cgroup_parent.is_none() && task.limits != CgroupLimits::default() {
+            warn!(
+                "task: {} has cgroup limits but no --cgroup-parent, ignoring them",
+                task.name
+            );
+        }
+        if task.image.is_some() && (task.host.is_some() || !self.hosts.is_empty()) {
+            warn!(
+                "task: {} has an image but runs over ssh, ignoring the image",
+                task.name
+            );
+            task.image = None;
+        }
+        if task.runtime.is_some() && task.image.is_none() {
+            warn!(
+                "task: {} has a runtime but no image, running it on the host",
+                task.name
+            );
+        }
+        self.backend.prepare(&mut task, &self.run_dir);
+        if self.skip_done && self.done_marker(&task.name).exists() {
+            info!("skipping task: {} (already done)", task.name);
+            return;
+        }
+        debug!("submiting task: {}", task);
+        self.todo_tasks.push(task);
+    }
+
+    /// Take the next task that can be placed right now, the newest first,
+    /// together with the gpus picked for it. Tasks waiting for cores or gpus
+    /// don't hold back smaller ones. Nothing is placed while draining.
+    fn next_task(&mut self) -> Option<(Task, Vec<u32>)> {
+        if self.draining {
+            return None;
+        }
+        let mut gpus = Vec::new();
+        if self.todo_tasks.iter().any(|task| task.gpus > 0) {
+            match query_gpus() {
+                Ok(found) => gpus = found,
+                Err(e) => {
+                    if !self.gpu_warned {
+                        warn!("failed to query gpus: {}", e);
+                        self.gpu_warned = true;
+                    }
+                }
+            }
+        }
+        // a task bigger than the whole budget still gets to run on its own
+        let free_cores = self.worker_cap().saturating_sub(self.reserved_cores());
+        let idle = self.runing_tasks.is_empty();
+        for i in self.policy.candidates(&self.todo_tasks, &self.conditions) {
+            let task = match self.todo_tasks.get(i) {
+                Some(task) => task,
+                None => continue,
+            };
+            if task.cpus > free_cores && !idle {
+                continue;
+            }
+            if task.gpus == 0 {
+                return Some((self.todo_tasks.remove(i), Vec::new()));
+            }
+            let taken = self.gpu_allocations.taken();
+            if let Some(devices) =
+                pick_gpus(&gpus, &taken, task.gpus, task.gpu_mem, self.gpu_util_max)
+            {
+                return Some((self.todo_tasks.remove(i), devices));
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self, mut task: Task, status: std::process::ExitStatus) {
+        self.gpu_allocations.release(&task.assigned_gpus);
+        self.track_leftovers(&task);
+        if !task.adopted.is_empty() {
+            info!(
+                "task: {} exited leaving {} adopted processes behind, killing them",
+                task.name,
+                task.adopted.len()
+            );
+            task.signal_adopted(Signal::Kill);
+            task.adopted.clear();
+        }
+        if self.shutdown {
+            info!("task: {} interrupted with status: {}", task.name, status);
+            self.record(&mut task, "interrupted", Some(status));
+            self.compress_logs(&mut task);
+            return;
+        }
+        if task.was_oom_killed(status) {
+            warn!(
+                "task: {} was OOM-killed (peak memory: {} MB)",
+                task.name,
+                task.peak_rss / 1024
+            );
+            self.record(&mut task, "oom", Some(status));
+            if task.oom_retries < self.oom_retries {
+                task.oom_retries += 1;
+                let reserve = task
+                    .mem_reserve
+                    .unwrap_or((self.per_task_mem as u64) << 20)
+                    .max(task.peak_rss)
+                    * 3
+                    / 2;
+                task.mem_reserve = Some(reserve);
+                info!(
+                    "task: {} retrying ({}/{}) with {} MB reserved",
+                    task.name,
+                    task.oom_retries,
+                    self.oom_retries,
+                    reserve / 1024
+                );
+                self.todo_tasks.push(task);
+            } else {
+                self.compress_logs(&mut task);
+            }
+            return;
+        }
+        info!(
+            "task: {} finished with status: {} (peak memory: {} MB)",
+            task.name,
+            status,
+            task.peak_rss / 1024
+        );
+        self.record(&mut task, "finished", Some(status));
+        self.compress_logs(&mut task);
+        if self.skip_done && status.success() {
+            let marker = self.done_marker(&task.name);
+            let written = fs::write(&marker, format!("{}\n", status))
+                .and_then(|_| self.log_access.apply(&marker));
+            if let Err(e) = written {
+                warn!("task: {} failed to write done marker: {}", task.name, e);
+            }
+        }
+    }
+
+    /// Hand processes reparented to cirno to the task whose tree they were
+    /// last seen in, and reap the ones that exited, adding up what they used.
+    ///
+    /// Children of cirno that never were in a task's tree are left alone, the
+    /// tasks being stopped in the background are among them.
+    fn adopt_orphans(&mut self) {
+        let me = getpid();
+        let mut tasks: Vec<&mut Task> = self
+            .runing_tasks
+            .iter_mut()
+            .chain(self.suspended_tasks.iter_mut())
+            .collect();
+        for pid in get_children(me) {
+            if self.orphans.contains(&pid) {
+                continue;
+            }
+            let owner = tasks
+                .iter_mut()
+                .find(|task| task.tree.iter().skip(1).any(|p| *p == pid));
+            if let Some(task) = owner {
+                debug!(
+                    "task: {} adopted orphan {}",
+                    task.name,
+                    pid.as_raw_nonzero()
+                );
+                task.adopted.push(pid);
+                self.orphans.push(pid);
+            }
+        }
+
+        // adopted orphans outlive their task when it is stopped or finishes
+        let mut orphans = std::mem::take(&mut self.orphans);
+        orphans.retain(|pid| {
+            if get_state(*pid) != Some('Z') {
+                return is_exist(*pid);
+            }
+            if let Ok(Some((_, usage))) = try_wait_with_usage(*pid) {
+                let owner = tasks.iter_mut().find(|task| task.adopted.contains(pid));
+                if let Some(task) = owner {
+                    task.adopted.retain(|adopted| adopted != pid);
+                    task.adopted_usage.user_time += usage.user_time;
+                    task.adopted_usage.sys_time += usage.sys_time;
+                    task.adopted_usage.max_rss = task.adopted_usage.max_rss.max(usage.max_rss);
+                }
+            }
+            false
+        });
+        self.orphans = orphans;
+
+        for task in tasks {
+            task.refresh_tree();
+        }
+    }
+
+    /// Remember the descendants of a task that just ended, to check on them
+    /// in `sweep`.
+    fn track_leftovers(&mut self, task: &Task) {
+        for pid in task.tree.iter().skip(1) {
+            if let Some(start_time) = get_start_time(*pid) {
+                self.leftovers.push(Leftover {
+                    task: task.name.clone(),
+                    pid: *pid,
+                    start_time,
+                    since: Instant::now(),
+                    reported: false,
+                });
+            }
+        }
+    }
+
+    /// Report processes that outlived their task, and zombie children nobody
+    /// reaps. Both distort the load and memory cirno sees.
+    fn sweep(&mut self) {
+        let kill_leftovers = self.kill_leftovers;
+        self.leftovers.retain_mut(|leftover| {
+            if get_start_time(leftover.pid) != Some(leftover.start_time) {
+                return false;
+            }
+            if leftover.since.elapsed() < LEFTOVER_GRACE || leftover.reported {
+                return true;
+            }
+            leftover.reported = true;
+            warn!(
+                "task: {} left process {} ({}) behind",
+                leftover.task,
+                leftover.pid.as_raw_nonzero(),
+                get_name(leftover.pid).unwrap_or_default()
+            );
+            if kill_leftovers {
+                info!(
+                    "task: {} killing leftover {}",
+                    leftover.task,
+                    leftover.pid.as_raw_nonzero()
+                );
+                if let Err(e) = kill_process_tree(leftover.pid, Signal::Kill) {
+                    warn!("task: {} failed to kill leftover: {}", leftover.task, e);
+                }
+            }
+            true
+        });
+
+        let me = getpid();
+        let zombies: Vec<(Pid, Instant, bool)> = get_children(me)
+            .into_iter()
+            .filter(|pid| get_state(*pid) == Some('Z'))
+            .map(
+                |pid| match self.zombies.iter().find(|(zombie, _, _)| *zombie == pid) {
+                    Some(seen) => *seen,
+                    None => (pid, Instant::now(), false),
+                },
+            )
+            .collect();
+        self.zombies = zombies;
+        // a zombie that stays around isn't waiting for a reap in progress
+        for (pid, since, reported) in &mut self.zombies {
+            if !*reported && since.elapsed() >= ZOMBIE_GRACE && !self.orphans.contains(pid) {
+                *reported = true;
+                warn!("zombie child {} is not being reaped", pid.as_raw_nonzero());
+            }
+        }
+    }
+
+    /// Whether some task is still running, suspended or waiting to be started.
+    fn has_work(&self) -> bool {
+        if self.watch.is_some() && !self.draining && !self.shutdown {
+            return true;
+        }
+        let queued = if self.draining {
+            0
+        } else {
+            self.todo_tasks.len()
+        };
+        queued + self.runing_tasks.len() + self.suspended_tasks.len() > 0
+    }
+
+    /// Wait for the next tick or resource check, or less when a child exits
+    /// so it is reaped and replaced right away.
+    fn nap(&mut self) {
+        // the last task finished, nothing left to wait for
+        if !self.has_work() {
+            return;
+        }
+        let stopping = self.runing_tasks.iter().any(|task| task.is_stopping());
+        let mut timeout = if self.shutdown || stopping {
+            // as often as a stop polls its task
+            Duration::from_millis(100)
+        } else {
+            let tick = Duration::from_secs(self.sleep_duration as u64);
+            match self.last_probe {
+                Some(last) => tick.min(self.probe_interval().saturating_sub(last.elapsed())),
+                None => Duration::ZERO,
+            }
+        };
+        if let Some(interval) = self.watchdog {
+            timeout = timeout.min((interval / 2).saturating_sub(self.last_ping.elapsed()));
+        }
+        // wake up when a timed out task's grace is over
+        let waiting = self.runing_tasks.iter().filter(|task| !task.is_stopping());
+        for at in waiting.filter_map(|task| task.timed_out_at) {
+            timeout = timeout.min(self.timeout_grace.saturating_sub(at.elapsed()));
+        }
+        self.draw_progress();
+        let watched: Vec<_> = self
+            .watch
+            .iter()
+            .map(|watch| watch.as_raw_fd())
+            .chain(self.control.iter().map(|control| control.as_raw_fd()))
+            .chain(self.inbox.iter().map(|inbox| inbox.as_raw_fd()))
+            .chain(self.metrics.iter().map(|metrics| metrics.as_raw_fd()))
+            .chain(self.api.iter().map(|(api, _)| api.as_raw_fd()))
+            .collect();
+        #[cfg(feature = "dashboard")]
+        let watched: Vec<_> = watched
+            .into_iter()
+            .chain(self.dashboard.iter().map(|dashboard| dashboard.as_raw_fd()))
+            .collect();
+        for signal in signals::wait(timeout, &watched) {
+            match signal {
+                libc::SIGINT => self.interrupt("SIGINT"),
+                // `systemctl stop`
+                libc::SIGTERM if self.systemd => self.drain(),
+                libc::SIGTERM => self.interrupt("SIGTERM"),
+                libc::SIGUSR2 => self.drain(),
+                libc::SIGHUP => self.reload(),
+                libc::SIGUSR1 => self.dump_status(),
+                libc::SIGTSTP => self.pause(),
+                libc::SIGCONT => self.resume(),
+                _ => {}
+            }
+        }
+        self.check_watch();
+        self.ping_watchdog();
+        let commands = match &self.inbox {
+            Some(inbox) => inbox.commands(),
+            None => Vec::new(),
+        };
+        for command in commands {
+            self.run_command(command);
+        }
+        let requests = match &self.control {
+            Some(control) => control.requests(),
+            None => Vec::new(),
+        };
+        for request in requests {
+            self.handle_request(request);
+        }
+        let requests = match &self.metrics {
+            Some(metrics) => metrics.requests(),
+            None => Vec::new(),
+        };
+        for request in requests {
+            self.serve_metrics(request);
+        }
+        let requests = match &self.api {
+            Some((api, _)) => api.requests(),
+            None => Vec::new(),
+        };
+        for request in requests {
+            self.serve_api(request);
+        }
+        #[cfg(feature = "dashboard")]
+        {
+            let requests = match &self.dashboard {
+                Some(dashboard) => dashboard.requests(),
+                None => Vec::new(),
+            };
+            for request in requests {
+                self.serve_dashboard(request);
+            }
+        }
+    }
+
+    /// The page, the queue state as JSON, cancelling and requeueing tasks,
+    /// and log tails over a WebSocket.
+    #[cfg(feature = "dashboard")]
+    fn serve_dashboard(&mut self, request: HttpRequest) {
+        let task = request.query_param("task").unwrap_or_default();
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => {
+                request.respond(200, "text/html; charset=utf-8", dashboard::PAGE.as_bytes())
+            }
+            ("GET", "/api/state") => {
+                let state = self.dashboard_state().to_string();
+                request.respond(200, "application/json", state.as_bytes());
+            }
+            ("POST", "/api/cancel" | "/api/requeue") if !dashboard::from_page(&request, true) => {
+                request.respond(403, "text/plain", b"not from the dashboard\n")
+            }
+            ("GET", "/api/log") if !dashboard::from_page(&request, false) => {
+                request.respond(403, "text/plain", b"not from the dashboard\n")
+            }
+            ("POST", "/api/cancel" | "/api/requeue") => {
+                let reply = if request.path == "/api/cancel" {
+                    self.cancel(&task)
+                } else {
+                    self.requeue(&task)
+                };
+                let status = if reply.starts_with("error:") {
+                    409
+                } else {
+                    200
+                };
+                request.respond(status, "text/plain", format!("{}\n", reply).as_bytes());
+            }
+            ("GET", "/api/log") => match self.log_of(&task) {
+                Some(log) => dashboard::tail_log(request, log),
+                None => request.respond(404, "text/plain", b"no log for that task\n"),
+            },
+            _ => request.respond(404, "text/plain", b"not found\n"),
+        }
+    }
+
+    /// The log of the running, suspended or ended task `name`.
+    fn log_of(&self, name: &str) -> Option<PathBuf> {
+        let active = self
+            .runing_tasks
+            .iter()
+            .chain(&self.suspended_tasks)
+            .find(|task| task.name == name)
+            .and_then(|task| task.logs.first().cloned());
+        active.or_else(|| {
+            self.ended
+                .iter()
+                .rev()
+                .find(|ended| ended.name == name)
+                .and_then(|ended| ended.log.clone())
+        })
+    }
+
+    #[cfg(feature = "dashboard")]
+    fn dashboard_state(&self) -> serde_json::Value {
+        let state = if self.shutdown {
+            "shutting down"
+        } else if self.draining {
+            "draining"
+        } else if self.paused {
+            "paused"
+        } else {
+            ""
+        };
+        json!({
+            "opinion": self.opinion.map(|opinion| format!("{:?}", opinion)).unwrap_or("none yet".to_string()),
+            "state": state,
+            "session": self.session_json(),
+            "outcomes": self.outcomes,
+            "tasks": self.tasks_json(),
+        })
+    }
+
+    /// The counts of the tasks and the running and suspended ones, for
+    /// `cirno-rs ps`.
+    fn ps(&self) -> serde_json::Value {
+        let active = self.runing_tasks.len() + self.suspended_tasks.len();
+        json!({
+            "opinion": self.opinion.map(|opinion| format!("{:?}", opinion)),
+            "running": self.runing_tasks.len(),
+            "suspended": self.suspended_tasks.len(),
+            "pending": self.todo_tasks.len(),
+            "session": self.session_json(),
+            "outcomes": self.outcomes,
+            "tasks": self.tasks_json()[..active],
+        })
+    }
+
+    /// The running, suspended, pending and recently ended tasks.
+    fn tasks_json(&self) -> Vec<serde_json::Value> {
+        let mut tasks = Vec::new();
+        let active = self
+            .runing_tasks
+            .iter()
+            .map(|task| (task, "running"))
+            .chain(self.suspended_tasks.iter().map(|task| (task, "suspended")));
+        for (task, task_state) in active {
+            tasks.push(json!({
+                "name": task.name,
+                "state": task_state,
+                "command": task.command(),
+                "elapsed": task.start_time.elapsed().unwrap_or_default().as_secs_f64(),
+                "pid": task.child.as_ref().map(|child| child.id()),
+                "rss_kib": task.rss,
+            }));
+        }
+        // the next to be launched first
+        for task in self.todo_tasks.iter().rev() {
+            tasks.push(json!({ "name": task.name, "state": "pending", "command": task.command() }));
+        }
+        for ended in self.ended.iter().rev() {
+            tasks.push(json!({
+                "name": ended.name,
+                "state": ended.outcome,
+                "command": ended.command,
+                "elapsed": ended.run_time,
+            }));
+        }
+        tasks
+    }
+
+    /// `GET /tasks`, `POST /tasks` with a task line or `{"line": ...}`,
+    /// `DELETE /tasks/NAME` and `GET /tasks/NAME/log`, for clients sending
+    /// the token.
+    fn serve_api(&mut self, request: HttpRequest) {
+        let token = self
+            .api
+            .as_ref()
+            .map(|(_, token)| token.as_str())
+            .unwrap_or_default();
+        let authorized = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+        // an empty token would let anyone in
+        let authorized = authorized && !token.is_empty();
+        if !authorized {
+            return api_respond(request, 401, json!({ "error": "missing or wrong token" }));
+        }
+        let path: Vec<String> = request
+            .path
+            .trim_matches('/')
+            .split('/')
+            .map(percent_decode)
+            .collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        match (request.method.as_str(), path.as_slice()) {
+            ("GET", ["tasks"]) => {
+                let tasks = json!(self.tasks_json());
+                api_respond(request, 200, tasks);
+            }
+            ("POST", ["tasks"]) => {
+                let body = String::from_utf8_lossy(&request.body).into_owned();
+                let lines = match serde_json::from_str::<serde_json::Value>(&body) {
+                    Ok(serde_json::Value::Object(object)) => match object.get("line") {
+                        Some(serde_json::Value::String(line)) => vec![line.clone()],
+                        _ => {
+                            return api_respond(
+                                request,
+                                400,
+                                json!({ "error": "expected a \"line\" string" }),
+                            )
+                        }
+                    },
+                    _ => task_lines(&body).into_iter().map(str::to_string).collect(),
+                };
+                let mut tasks = Vec::new();
+                for line in &lines {
+                    match parse_task_line(line) {
+                        Ok(task) => tasks.push(task),
+                        Err(e) => {
+                            return api_respond(request, 400, json!({ "error": e.to_string() }))
+                        }
+                    }
+                }
+                if tasks.is_empty() {
+                    return api_respond(request, 400, json!({ "error": "no task line" }));
+                }
+                let (mut submitted, mut skipped) = (Vec::new(), Vec::new());
+                for task in tasks {
+                    let name = task.name.clone();
+                    let queued = self.todo_tasks.len();
+                    self.submit(task);
+                    if self.todo_tasks.len() > queued {
+                        submitted.push(name);
+                    } else {
+                        skipped.push(name);
+                    }
+                }
+                self.last_probe = None;
+                api_respond(
+                    request,
+                    201,
+                    json!({ "submitted": submitted, "skipped": skipped }),
+                );
+            }
+            ("DELETE", ["tasks", name]) => {
+                let reply = self.cancel(name);
+                match reply.strip_prefix("error: ") {
+                    Some(e) => api_respond(request, 404, json!({ "error": e })),
+                    None => api_respond(
+                        request,
+                        200,
+                        json!({ "result": reply.trim_start_matches("ok: ") }),
+                    ),
+                }
+            }
+            ("GET", ["tasks", name, "log"]) => match self.log_of(name).map(fs::File::open) {
+                Some(Ok(log)) => request.respond_file(200, "text/plain; charset=utf-8", log),
+                Some(Err(e)) => api_respond(request, 404, json!({ "error": e.to_string() })),
+                None => api_respond(
+                    request,
+                    404,
+                    json!({ "error": format!("no log for {}", name) }),
+                ),
+            },
+            (_, ["tasks", ..]) => {
+                api_respond(request, 405, json!({ "error": "method not allowed" }))
+            }
+            _ => api_respond(request, 404, json!({ "error": "not found" })),
+        }
+    }
+
+    fn serve_metrics(&self, request: HttpRequest) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                let metrics = self.metrics();
+                request.respond(200, "text/plain; version=0.0.4", metrics.as_bytes());
+            }
+            (_, "/metrics") => request.respond(405, "text/plain", b"method not allowed\n"),
+            _ => request.respond(404, "text/plain", b"not found\n"),
+        }
+    }
+
+    /// The Prometheus text format of the task counts, the last resource
+    /// check and how long the tasks ran.
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+        metric(
+            "cirno_tasks",
+            "gauge",
+            "Tasks by state.",
+            vec![
+                (
+                    "{state=\"running\"}".to_string(),
+                    self.runing_tasks.len() as f64,
+                ),
+                (
+                    "{state=\"suspended\"}".to_string(),
+                    self.suspended_tasks.len() as f64,
+                ),
+                (
+                    "{state=\"pending\"}".to_string(),
+                    self.todo_tasks.len() as f64,
+                ),
+            ],
+        );
+        let mut outcomes: Vec<_> = self.outcomes.iter().collect();
+        outcomes.sort();
+        metric(
+            "cirno_runs_total",
+            "counter",
+            "Runs that ended, by outcome.",
+            outcomes
+                .into_iter()
+                .map(|(outcome, count)| (format!("{{outcome=\"{}\"}}", outcome), *count as f64))
+                .collect(),
+        );
+        metric(
+            "cirno_runs_failed_total",
+            "counter",
+            "Runs that exited unsuccessfully, failed to start or were OOM killed.",
+            vec![(String::new(), self.failed as f64)],
+        );
+        let measurements = &self.measurements;
+        metric(
+            "cirno_load",
+            "gauge",
+            "Load per core at the last check.",
+            vec![(String::new(), measurements.load)],
+        );
+        metric(
+            "cirno_memory_available_bytes",
+            "gauge",
+            "Available memory at the last check.",
+            vec![(String::new(), (measurements.free_kib * 1024) as f64)],
+        );
+        metric(
+            "cirno_reserved_cores",
+            "gauge",
+            "Cores handed out to running tasks.",
+            vec![(String::new(), measurements.reserved_cores as f64)],
+        );
+        if let Some(io_utilization) = measurements.io_utilization {
+            metric(
+                "cirno_io_utilization_percent",
+                "gauge",
+                "Utilization of the busiest disk at the last check.",
+                vec![(String::new(), io_utilization)],
+            );
+        }
+        if let Some(temp) = measurements.temp {
+            metric(
+                "cirno_temperature_celsius",
+                "gauge",
+                "Temperature of the hottest thermal zone at the last check.",
+                vec![(String::new(), temp)],
+            );
+        }
+        metric(
+            "cirno_opinion",
+            "gauge",
+            "1 for the opinion last acted on.",
+            [
+                CirnoOpinion::Health,
+                CirnoOpinion::Normal,
+                CirnoOpinion::Bad,
+            ]
+            .into_iter()
+            .map(|opinion| {
+                let current = self.opinion == Some(opinion);
+                (
+                    format!("{{opinion=\"{:?}\"}}", opinion),
+                    current as u8 as f64,
+                )
+            })
+            .collect(),
+        );
+        metric(
+            "cirno_task_running_seconds",
+            "gauge",
+            "How long the running and suspended tasks have been running.",
+            self.runing_tasks
+                .iter()
+                .chain(&self.suspended_tasks)
+                .map(|task| {
+                    let elapsed = task.start_time.elapsed().unwrap_or_default().as_secs_f64();
+                    (format!("{{task=\"{}\"}}", label_value(&task.name)), elapsed)
+                })
+                .collect(),
+        );
+        let mut durations: Vec<_> = self.durations.iter().collect();
+        durations.sort_by(|a, b| a.0.cmp(b.0));
+        metric(
+            "cirno_task_duration_seconds",
+            "gauge",
+            "How long the last run of each task took.",
+            durations
+                .into_iter()
+                .map(|(name, duration)| (format!("{{task=\"{}\"}}", label_value(name)), *duration))
+                .collect(),
+        );
+        out
+    }
+
+    /// Carry out a command from the control socket and answer it.
+    fn handle_request(&mut self, request: Request) {
+        let (command, args) = request
+            .line
+            .split_once(char::is_whitespace)
+            .unwrap_or((&request.line, ""));
+        let args = args.trim();
+        let reply = match command {
+            "status" => self.status(),
+            "submit" => match parse_task_line(args) {
+                Ok(task) => {
+                    let name = task.name.clone();
+                    let queued = self.todo_tasks.len();
+                    debug!("generate task from: {}", args);
+                    self.submit(task);
+                    self.last_probe = None;
+                    if self.todo_tasks.len() > queued {
+                        format!("ok: submitted {}", name)
+                    } else {
+                        format!("ok: skipped {}, already done", name)
+                    }
+                }
+                Err(e) => format!("error: {}", e),
+            },
+            "cancel" if !args.is_empty() => self.cancel(args),
+            "requeue" if !args.is_empty() => self.requeue(args),
+            "set" => match args.split_once(char::is_whitespace) {
+                Some((name, value)) => match self.set_setting(name, value.trim()) {
+                    Ok(()) => format!("ok: {} = {}", name, value.trim()),
+                    Err(e) => format!("error: {}", e),
+                },
+                None => "error: expected set <name> <value>".to_string(),
+            },
+            "pause" => {
+                self.pause();
+                "ok".to_string()
+            }
+            "resume" => {
+                self.resume();
+                "ok".to_string()
+            }
+            "drain" => {
+                self.drain();
+                "ok".to_string()
+            }
+            "reload" => {
+                self.reload();
+                "ok".to_string()
+            }
+            "explain" if self.explanation.is_empty() => {
+                "launching, nothing holds tasks back".to_string()
+            }
+            "explain" => format!("not launching: {}", self.explanation.join("; ")),
+            "ps" => self.ps().to_string(),
+            _ => format!("error: unknown command: {}", command),
+        };
+        request.reply(&reply);
+    }
+
+    /// Print what cirno is doing and write it to `status.txt` in the run
+    /// directory.
+    fn dump_status(&self) {
+        let status = self.status();
+        print!("{}", status);
+        let path = self.run_dir.join("status.txt");
+        let written = fs::write(&path, &status).and_then(|_| self.log_access.apply(&path));
+        if let Err(e) = written {
+            warn!("failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    fn status(&self) -> String {
+        let mut status = format!("status at {}\n", format_utc(unix_now(), ' ', ':'));
+        status.push_str(&self.session_lines());
+        let opinion = match self.opinion {
+            Some(opinion) => format!("{:?}", opinion),
+            None => "none yet".to_string(),
+        };
+        let state = if self.shutdown {
+            ", shutting down"
+        } else if self.draining {
+            ", draining"
+        } else if self.paused {
+            ", paused"
+        } else {
+            ""
+        };
+        status.push_str(&format!("opinion: {}{}\n", opinion, state));
+        status.push_str(&format!(
+            "running: {}, suspended: {}, pending: {}\n",
+            self.runing_tasks.len(),
+            self.suspended_tasks.len(),
+            self.todo_tasks.len()
+        ));
+        let mut outcomes: Vec<_> = self.outcomes.iter().collect();
+        outcomes.sort();
+        let outcomes: Vec<String> = outcomes
+            .into_iter()
+            .map(|(outcome, count)| format!("{}: {}", outcome, count))
+            .collect();
+        if !outcomes.is_empty() {
+            status.push_str(&format!("{}\n", outcomes.join(", ")));
+        }
+        let tasks = self
+            .runing_tasks
+            .iter()
+            .map(|task| (task, "running"))
+            .chain(self.suspended_tasks.iter().map(|task| (task, "suspended")));
+        for (task, state) in tasks {
+            let pid = match &task.child {
+                Some(child) => child.id().to_string(),
+                None => "-".to_string(),
+            };
+            let elapsed = task.start_time.elapsed().unwrap_or_default().as_secs_f64();
+            status.push_str(&format!(
+                "task: {} pid {} {} for {:.0}s (memory: {} MB, peak: {} MB)\n",
+                task.name,
+                pid,
+                state,
+                elapsed,
+                task.rss / 1024,
+                task.peak_rss / 1024
+            ));
+        }
+        status
+    }
+
+    /// Drop the pending tasks called `name` and stop the running and
+    /// suspended ones, which are recorded as cancelled.
+    fn cancel(&mut self, name: &str) -> String {
+        let (cancelled, kept): (Vec<Task>, Vec<Task>) = std::mem::take(&mut self.todo_tasks)
+            .into_iter()
+            .partition(|task| task.name == name);
+        self.todo_tasks = kept;
+        let pending = cancelled.len();
+        for task in &cancelled {
+            self.remember_ended(task, "cancelled", 0.0, false);
+        }
+        if pending > 0 {
+            *self.outcomes.entry("cancelled".to_string()).or_default() += pending;
+        }
+
+        let mut stopped = 0;
+        for mut task in std::mem::take(&mut self.suspended_tasks) {
+            if task.name != name {
+                self.suspended_tasks.push(task);
+                continue;
+            }
+            // so it can handle the stop signal
+            if let Err(e) = task.resume() {
+                warn!("task: {} failed to resume: {}", task.name, e);
+            }
+            self.runing_tasks.push(task);
+        }
+        for mut task in std::mem::take(&mut self.runing_tasks) {
+            if task.name != name {
+                self.runing_tasks.push(task);
+                continue;
+            }
+            info!("task: {} cancelled, stopping", task.name);
+            self.record(&mut task, "cancelled", None);
+            self.track_leftovers(&task);
+            self.backend
+                .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+            stopped += 1;
+        }
+
+        if pending + stopped == 0 {
+            return format!("error: no task named {}", name);
+        }
+        if pending > 0 {
+            info!(
+                "task: {} cancelled before starting ({} queued)",
+                name, pending
+            );
+        }
+        format!("ok: cancelled {} pending and {} running", pending, stopped)
+    }
+
+    /// Carry out a command sent through a `SchedulerHandle`.
+    fn run_command(&mut self, command: Command) {
+        match command {
+            Command::Submit(task) => {
+                self.submit(*task);
+                self.last_probe = None;
+            }
+            Command::Cancel(name) => {
+                let reply = self.cancel(&name);
+                if reply.starts_with("error") {
+                    warn!("{}", reply);
+                }
+            }
+            Command::Drain => self.drain(),
+        }
+    }
+
+    /// Submit the line of the ended task `name` again.
+    fn requeue(&mut self, name: &str) -> String {
+        let line = match self.ended.iter().rev().find(|ended| ended.name == name) {
+            Some(ended) if !ended.line.is_empty() => ended.line.clone(),
+            _ => return format!("error: no ended task named {}", name),
+        };
+        match parse_task_line(&line) {
+            Ok(task) => {
+                let queued = self.todo_tasks.len();
+                info!("task: {} requeued", name);
+                self.submit(task);
+                self.last_probe = None;
+                if self.todo_tasks.len() > queued {
+                    format!("ok: requeued {}", name)
+                } else {
+                    format!("ok: skipped {}, already done", name)
+                }
+            }
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    /// Submit the tasks of the files that appeared in the watched directory.
+    fn check_watch(&mut self) {
+        let files = match &self.watch {
+            Some(watch) if !self.shutdown && !self.draining => watch.new_files(),
+            _ => return,
+        };
+        for file in files {
+            let contents = match fs::read_to_string(&file) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warn!("failed to read {}: {}", file.display(), e);
+                    continue;
+                }
+            };
+            info!("new task list: {}", file.display());
+            for line in task_lines(&contents) {
+                match parse_task_line(line) {
+                    Ok(task) => {
+                        debug!("generate task from: {line}");
+                        self.submit(task);
+                        // look at once whether it can start
+                        self.last_probe = None;
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        }
+    }
+
+    /// Stop launching and take every running task through its stop
+    /// sequence, or SIGKILL them all if that is already underway.
+    fn interrupt(&mut self, signal: &str) {
+        if self.shutdown {
+            info!("received {} again, killing all tasks", signal);
+            for task in &mut self.runing_tasks {
+                if let Err(e) = self.backend.kill(task) {
+                    warn!("task: {} failed to kill: {}", task.name, e);
+                }
+            }
+            return;
+        }
+        self.shutdown = true;
+        notify_stopping();
+        info!(
+            "received {}, stopping {} tasks, another {} kills them",
+            signal,
+            self.runing_tasks.len() + self.suspended_tasks.len(),
+            signal
+        );
+        if !self.todo_tasks.is_empty() {
+            info!("{} tasks were not started", self.todo_tasks.len());
+            self.todo_tasks.clear();
+        }
+        // stopped processes can't handle the stop signal
+        for mut task in std::mem::take(&mut self.suspended_tasks) {
+            if let Err(e) = task.resume() {
+                warn!("task: {} failed to resume: {}", task.name, e);
+            }
+            self.runing_tasks.push(task);
+        }
+    }
+
+    /// Tell systemd's watchdog cirno is alive, at twice the rate it asks for.
+    fn ping_watchdog(&mut self) {
+        let interval = match self.watchdog {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.last_ping.elapsed() < interval / 2 {
+            return;
+        }
+        self.last_ping = Instant::now();
+        let state = format!(
+            "WATCHDOG=1\nSTATUS=running: {}, suspended: {}, pending: {}",
+            self.runing_tasks.len(),
+            self.suspended_tasks.len(),
+            self.todo_tasks.len()
+        );
+        if let Err(e) = systemd::notify(&state) {
+            warn!("failed to ping the systemd watchdog: {}", e);
+        }
+    }
+
+    /// Stop launching new tasks and exit once the running and suspended ones
+    /// are done.
+    fn drain(&mut self) {
+        if self.draining || self.shutdown {
+            return;
+        }
+        self.draining = true;
+        notify_stopping();
+        info!(
+            "draining: waiting for {} tasks, {} queued tasks will not be started",
+            self.runing_tasks.len() + self.suspended_tasks.len(),
+            self.todo_tasks.len()
+        );
+    }
+
+    /// Change one setting of the running scheduler, named like its flag.
+    fn set_setting(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "max_workers" => {
+                self.max_workers = parse_setting(name, value)?;
+                self.tuned_workers = self.tuned_workers.min(self.max_workers);
+            }
+            "force_task" => self.set_force_task(parse_setting(name, value)?),
+            "sleep_duration" => self.set_sleep_duration(parse_setting(name, value)?),
+            "probe_interval" => self.set_probe_interval(parse_interval(value)?),
+            "reserved_mem" => self.set_reserved_mem(parse_setting(name, value)?),
+            "per_task_mem" => self.set_per_task_mem(parse_setting(name, value)?),
+            "timeout" => self.set_timeout(parse_setting(name, value)?),
+            "load_max" => self.set_load_max(parse_setting(name, value)?),
+            "load_min" => self.set_load_min(parse_setting(name, value)?),
+            "min_dwell" => self.set_min_dwell(parse_setting(name, value)?),
+            "gpu_util_max" => self.set_gpu_util_max(parse_setting(name, value)?),
+            "oom_retries" => self.set_oom_retries(parse_setting(name, value)?),
+            "kill_leftovers" => self.set_kill_leftovers(parse_setting(name, value)?),
+            _ => return Err(format!("unknown setting: {}", name)),
+        }
+        info!("set {} to {}", name, value);
+        // the new limits may already allow or forbid something
+        self.last_probe = None;
+        Ok(())
+    }
+
+    /// Leave the running tasks alone: nothing is launched, preempted or
+    /// resumed until `resume`. Children are still reaped and timed out.
+    fn pause(&mut self) {
+        if !self.paused {
+            self.paused = true;
+            info!("paused, {} tasks keep running", self.runing_tasks.len());
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.last_probe = None;
+            info!("resumed");
+        }
+    }
+
+    /// Re-read the task list and submit the lines added since it was last
+    /// read. A line that is there twice more often than before is two tasks.
+    fn reload(&mut self) {
+        let path = match &self.task_list {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        if self.shutdown || self.draining {
+            info!("not reloading task list while stopping");
+            return;
+        }
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("failed to reload task list {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let lines = count_lines(&contents);
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        let mut added = 0;
+        for line in task_lines(&contents) {
+            let count = seen.entry(line).or_default();
+            *count += 1;
+            if *count <= self.task_lines.get(line).copied().unwrap_or(0) {
+                continue;
+            }
+            match parse_task_line(line) {
+                Ok(task) => {
+                    debug!("generate task from: {line}");
+                    self.submit(task);
+                    self.last_probe = None;
+                    added += 1;
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+        self.task_lines = lines;
+        info!("reloaded task list, {} new tasks", added);
+    }
+
+    /// Advance the stop sequence of every running task on shutdown.
+    fn shutdown_step(&mut self) {
+        // a task that failed to spawn has nothing to wait for
+        let backend = &self.backend;
+        self.runing_tasks.retain(|task| backend.is_running(task));
+        for task in &mut self.runing_tasks {
+            if let Err(e) = self.backend.stop_step(task) {
+                warn!("task: {} failed to stop: {}", task.name, e);
+            }
+        }
+    }
+
+    /// The host of `--hosts` with the fewest tasks on it, the first of them
+    /// on a tie.
+    fn pick_host(&self) -> Option<String> {
+        let running = |host: &String| {
+            self.runing_tasks
+                .iter()
+                .chain(&self.suspended_tasks)
+                .filter(|task| task.remote.as_ref() == Some(host))
+                .count()
+        };
+        self.hosts.iter().min_by_key(|host| running(host)).cloned()
+    }
+
+    fn probe_interval(&self) -> Duration {
+        self.probe_interval
+            .unwrap_or(Duration::from_secs(self.sleep_duration as u64))
+    }
+
+    fn probe_due(&self) -> bool {
+        match self.last_probe {
+            Some(last) => last.elapsed() >= self.probe_interval(),
+            None => true,
+        }
+    }
+
+    /// The opinion last acted on, none before the first check.
+    pub fn opinion(&self) -> Option<CirnoOpinion> {
+        self.opinion
+    }
+
+    /// Run until every task left, or until shut down by a signal or the
+    /// control socket.
+    pub fn do_it(&mut self) {
+        let text = format!(
+            "cirno started with {} tasks on {}",
+            self.todo_tasks.len(),
+            self.host.clone()
+        );
+        self.post_webhook(
+            "run_started",
+            text,
+            json!({ "tasks": self.todo_tasks.len() }),
+        );
+        while self.has_work() {
+            if self.subreaper {
+                self.adopt_orphans();
+            }
+            self.sweep();
+            self.compress_ready();
+            let active = self.runing_tasks.len() + self.suspended_tasks.len();
+            // check finished or timeout task
+            let mut next_runing_tasks = Vec::new();
+            for mut task in std::mem::take(&mut self.runing_tasks) {
+                task.sample_memory();
+                let timed_out = task.timed_out_at.is_some();
+                let timeout = task
+                    .timeout
+                    .map_or(self.timeout, |timeout| timeout.as_secs() as usize);
+                let result = self.backend.try_wait(
+                    &mut task,
+                    timeout,
+                    self.timeout_signal,
+                    self.timeout_grace,
+                );
+                if !timed_out && task.timed_out_at.is_some() {
+                    let fields = json!({ "task": task.name, "timeout": timeout });
+                    self.task_event(&mut task, "timed_out", fields.clone());
+                    let text = format!(
+                        "task {} timed out after {}",
+                        task.name,
+                        format_elapsed(timeout as u64)
+                    );
+                    self.post_webhook("task_timed_out", text, fields);
+                }
+                match result {
+                    Ok(Some(status)) => {
+                        self.finish(task, status);
+                    }
+                    Ok(None) => {
+                        next_runing_tasks.push(task);
+                    }
+                    Err(e) => {
+                        error!("task: {} failed with error: {}", task.name, e);
+                        self.track_leftovers(&task);
+                        self.record(&mut task, "error", None);
+                        // what is left of the attempt is stopped on the pool
+                        self.backend
+                            .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+                    }
+                }
+            }
+            self.runing_tasks = next_runing_tasks;
+
+            // suspended tasks can still be killed from outside
+            let mut next_suspended_tasks = Vec::new();
+            for mut task in std::mem::take(&mut self.suspended_tasks) {
+                match task.reap() {
+                    Ok(Some(status)) => self.finish(task, status),
+                    Ok(None) => next_suspended_tasks.push(task),
+                    Err(e) => {
+                        error!("task: {} failed with error: {}", task.name, e);
+                        self.track_leftovers(&task);
+                        self.record(&mut task, "error", None);
+                        // what is left of the attempt is stopped on the pool
+                        self.backend
+                            .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+                    }
+                }
+            }
+            self.suspended_tasks = next_suspended_tasks;
+            self.rotate_logs();
+
+            if self.shutdown {
+                self.shutdown_step();
+                self.nap();
+                continue;
+            }
+            if self.paused {
+                self.nap();
+                continue;
+            }
+
+            // a finished task frees resources, look again right away
+            let finished = self.runing_tasks.len() + self.suspended_tasks.len() < active;
+            if !finished && !self.probe_due() {
+                self.nap();
+                continue;
+            }
+            self.last_probe = Some(Instant::now());
+
+            // check cirno's opinion
+            let opinion = self.cirno_check();
+            if self.opinion != Some(opinion) {
+                let measurements = self.measurements.clone();
+                self.event(
+                    "opinion",
+                    json!({
+                        "opinion": format!("{:?}", opinion),
+                        "previous": self.opinion.map(|previous| format!("{:?}", previous)),
+                        "load": measurements.load,
+                        "free_kib": measurements.free_kib,
+                        "reserved_cores": measurements.reserved_cores,
+                        "causes": measurements.causes,
+                    }),
+                );
+                for observer in &mut self.observers {
+                    observer.on_opinion_change(self.opinion, opinion);
+                }
+            }
+            self.opinion = Some(opinion);
+            match opinion {
+                CirnoOpinion::Health => {
+                    if !self.suspended_tasks.is_empty() {
+                        // bring back what was suspended before starting anything new
+                        let mut task = self.suspended_tasks.remove(0);
+                        match task.resume() {
+                            Ok(()) => info!("task: {} resumed", task.name),
+                            Err(e) => warn!("task: {} failed to resume: {}", task.name, e),
+                        }
+                        let fields = json!({ "task": task.name });
+                        self.task_event(&mut task, "resumed", fields);
+                        self.explanation.clear();
+                        self.runing_tasks.push(task);
+                    } else if let Some((mut task, gpus)) = self.next_task() {
+                        // try to add new task
+                        if self.backend.is_running(&task) {
+                            // left over from an attempt nothing stopped
+                            self.backend
+                                .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+                        }
+                        if let Some(host) = task.host.clone().or_else(|| self.pick_host()) {
+                            task.send_to(&host);
+                        }
+                        task.assign_gpus(gpus);
+                        if self.stream {
+                            task.stream(stream::prefix(&task.name));
+                        }
+                        let started = match self.open_logs(&mut task) {
+                            Ok(logs) => {
+                                task.logs = logs;
+                                self.open_span(&mut task);
+                                self.backend.spawn(&mut task)
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = started {
+                            // nothing to wait for, e.g. the program doesn't exist
+                            warn!("task: {} {}", task.name, e);
+                            self.record(&mut task, "error", None);
+                            self.backend
+                                .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+                            self.nap();
+                            continue;
+                        }
+                        info!("task: {} started", task);
+                        let fields = json!({
+                                "task": task.name,
+                                "attempt": task.attempts,
+                                "pid": task.child.as_ref().map(|child| child.id()),
+                                "host": task.remote,
+                                "command": task.command(),
+                                "cpus": task.cpus,
+                                "gpus": task.assigned_gpus,
+                        });
+                        self.task_event(&mut task, "started", fields);
+                        for observer in &mut self.observers {
+                            observer.on_task_start(&task);
+                        }
+                        self.gpu_allocations
+                            .allocate(&task.assigned_gpus, &task.name);
+                        if !task.assigned_gpus.is_empty() {
+                            debug!(
+                                "task: {} assigned gpus: {:?}",
+                                task.name, task.assigned_gpus
+                            );
+                        }
+                        self.explanation.clear();
+                        self.runing_tasks.push(task);
+                    } else {
+                        self.explain_idle(opinion);
+                    }
+                    self.nap();
+                }
+                CirnoOpinion::Normal => {
+                    self.explain_idle(opinion);
+                    self.nap();
+                }
+                CirnoOpinion::Bad => {
+                    self.explain_idle(opinion);
+                    // try to stop one task and sleep
+                    if self.runing_tasks.len() > self.force_task {
+                        let victim = self.pick_victim();
+                        let mut task = self.runing_tasks.remove(victim);
+                        match self.preempt_mode {
+                            PreemptMode::Kill => {
+                                info!("task: {} stopping", task.name);
+                                self.record(&mut task, "preempted", None);
+                                self.track_leftovers(&task);
+                                self.backend
+                                    .stop(&mut task, &mut self.pool, &self.gpu_allocations);
+                                self.todo_tasks.push(task);
+                            }
+                            PreemptMode::Suspend => {
+                                match task.suspend() {
+                                    Ok(()) => info!("task: {} suspended", task.name),
+                                    Err(e) => warn!("task: {} failed to suspend: {}", task.name, e),
+                                }
+                                for observer in &mut self.observers {
+                                    observer.on_task_preempted(&task, PreemptMode::Suspend);
+                                }
+                                let fields = json!({ "task": task.name });
+                                self.task_event(&mut task, "suspended", fields);
+                                self.suspended_tasks.push(task);
+                            }
+                        }
+                    }
+                    self.nap();
+                }
+            }
+        }
+
+        if self.shutdown {
+            info!("all tasks stopped, exiting");
+        } else if self.draining {
+            info!("drained, {} tasks were not started", self.todo_tasks.len());
+        }
+        // the last output may still be on its way to the logs
+        let deadline = Instant::now() + Duration::from_secs(1);
+        self.compress_ready();
+        while !self.uncompressed.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+            self.compress_ready();
+        }
+        for uncompressed in &self.uncompressed {
+            warn!(
+                "task: {} left its logs uncompressed, something still writes to them",
+                uncompressed.task
+            );
+        }
+        if let (Some(history), Some(session)) = (&self.history, self.session_id) {
+            if let Err(e) = history.end_session(session, unix_now()) {
+                warn!("failed to close history session: {}", e);
+            }
+        }
+        if self.progress {
+            self.draw_progress();
+            progress::finish();
+        }
+        let end = if self.shutdown {
+            "interrupted"
+        } else if self.draining {
+            "drained"
+        } else {
+            "finished"
+        };
+        if !self.notify.is_empty() {
+            let body = self.summary();
+            self.notify(&format!("cirno: run {}", end), &body);
+        }
+        let text = format!("cirno run {}: {}", end, self.summary());
+        let fields = json!({
+            "end": end,
+            "done": self.done,
+            "failed": self.failed_tasks,
+            "outcomes": self.outcomes,
+            "wall_time": self.run_start.elapsed().as_secs_f64(),
+            "session": self.session_json(),
+        });
+        self.post_webhook("run_finished", text, fields);
+        if let Some(mailer) = self.mailer.clone() {
+            let host = self.host.clone();
+            let subject = format!("cirno run {} on {}: {}", end, host, self.summary());
+            let body = self.report(end);
+            self.pool.execute(move || {
+                if let Err(e) = mailer.send(&subject, &body) {
+                    warn!("failed to mail the summary of the run: {}", e);
+                }
+            });
+        }
+        let path = self.run_dir.join("trace.json");
+        let written = self
+            .timeline
+            .write(&path)
+            .and_then(|_| self.log_access.apply(&path));
+        if let Err(e) = written {
+            warn!("failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    /// Cirno's opinion, held back from flipping until the previous one has
+    /// lasted `min_dwell` seconds. Running out of memory is never held back.
+    fn cirno_check(&mut self) -> CirnoOpinion {
+        let opinion = self.raw_opinion();
+        if self.min_dwell == 0 {
+            return opinion;
+        }
+
+        let now = Instant::now();
+        match self.last_opinion {
+            Some((last, _)) if last == opinion => opinion,
+            Some((_, since))
+                if now.duration_since(since) < Duration::from_secs(self.min_dwell as u64)
+                    && !(opinion == CirnoOpinion::Bad && self.low_memory) =>
+            {
+                CirnoOpinion::Normal
+            }
+            _ => {
+                self.last_opinion = Some((opinion, now));
+                opinion
+            }
+        }
+    }
+
+    /// Per-cpu load, the 5-minute average unless smoothing is enabled, then an
+    /// exponentially weighted moving average of the 1-minute load.
+    fn sample_load(&mut self, snapshot: &ResourceSnapshot) -> f64 {
+        let alpha = match self.load_alpha {
+            Some(alpha) => alpha,
+            None => return snapshot.load_five,
+        };
+        let one = snapshot.load_one;
+        let smoothed = match self.smoothed_load {
+            Some(prev) => alpha * one + (1.0 - alpha) * prev,
+            None => one,
+        };
+        self.smoothed_load = Some(smoothed);
+        smoothed
+    }
+
+    /// Check the configured PSI thresholds against the 10 second `some`
+    /// averages. Returns whether any is exceeded, and whether all stay below
+    /// half of their threshold, which is required to launch new tasks.
+    fn check_pressure(&self, snapshot: &ResourceSnapshot) -> (bool, bool) {
+        let mut bad = false;
+        let mut calm = true;
+        if !self.psi_supported {
+            return (bad, calm);
+        }
+        let thresholds = [
+            (snapshot.cpu_pressure, self.psi_cpu_max),
+            (snapshot.memory_pressure, self.psi_memory_max),
+            (snapshot.io_pressure, self.psi_io_max),
+        ];
+        for (pressure, max) in thresholds {
+            let max = match max {
+                Some(max) => max,
+                None => continue,
+            };
+            if let Some(pressure) = pressure {
+                bad |= pressure.some_avg10 > max;
+                calm &= pressure.some_avg10 <= max / 2.0;
+            }
+        }
+        (bad, calm)
+    }
+
+    /// Whether swap usage (GB) or the swap-in rate (MB/s since the last check)
+    /// is above its threshold. A thrashing machine may still report free memory.
+    fn check_swap(&mut self, snapshot: &ResourceSnapshot) -> bool {
+        let mut bad = false;
+        if let Some(max) = self.max_swap_used {
+            let used = snapshot.used_swap as f64 / (1024.0 * 1024.0 * 1024.0);
+            bad |= used > max as f64;
+        }
+        if let Some(max) = self.max_swapin_rate {
+            let now = Instant::now();
+            if let Some(pages) = snapshot.swapped_in {
+                if let Some((last_pages, last_time)) = self.last_swapin {
+                    let secs = now.duration_since(last_time).as_secs_f64();
+                    let bytes = pages.saturating_sub(last_pages) * vmstat::page_size();
+                    if secs > 0.0 {
+                        bad |= bytes as f64 / secs / (1024.0 * 1024.0) > max;
+                    }
+                }
+                self.last_swapin = Some((pages, now));
+            }
+        }
+        bad
+    }
+
+    /// Disk utilization of the busiest device since the last check against
+    /// `io_max`. Like the PSI thresholds, launching needs it below half.
+    fn check_io(&mut self, snapshot: &ResourceSnapshot) -> (bool, bool) {
+        let max = match self.io_max {
+            Some(max) => max,
+            None => return (false, true),
+        };
+        let now = Instant::now();
+        let ticks = snapshot.io_ticks.clone();
+        let utilization = match &self.last_io {
+            Some((before, last_time)) => max_utilization(
+                before,
+                &ticks,
+                now.duration_since(*last_time).as_secs_f64() * 1000.0,
+                &self.io_devices,
+            ),
+            None => 0.0,
+        };
+        self.last_io = Some((ticks, now));
+        self.measurements.io_utilization = Some(utilization);
+        (utilization > max, utilization <= max / 2.0)
+    }
+
+    /// Whether the hottest thermal zone is above `temp_max`, and whether it is
+    /// cool enough (5°C below) and not throttling, so new tasks may start.
+    fn check_thermal(&mut self, snapshot: &ResourceSnapshot) -> (bool, bool) {
+        let max = match self.temp_max {
+            Some(max) => max,
+            None => return (false, true),
+        };
+        let temp = snapshot.temperature;
+        self.measurements.temp = temp;
+        let throttles = snapshot.throttles;
+        let throttling =
+            matches!((self.last_throttles, throttles), (Some(last), Some(now)) if now > last);
+        self.last_throttles = throttles;
+        if throttling {
+            warn!("cpu is thermally throttling");
+        }
+        match temp {
+            Some(temp) => (temp > max, temp <= max - 5.0 && !throttling),
+            None => (false, !throttling),
+        }
+    }
+
+    fn raw_opinion(&mut self) -> CirnoOpinion {
+        let reserved_cores = self.reserved_cores();
+        self.measurements = Measurements {
+            reserved_cores,
+            ..Measurements::default()
+        };
+
+        if reserved_cores > self.max_workers {
+            self.measurements.causes.push("workers");
+            self.measurements.bad = true;
+            return CirnoOpinion::Bad;
+        }
+
+        let snapshot = self.probe.sample();
+
+        let load = self.sample_load(&snapshot);
+        let free_mem = (snapshot.available_memory / (1024 * 1024 * 1024)) as usize;
+        let free_kib = snapshot.available_memory / 1024;
+
+        // cpu pressure replaces the load average when it is available
+        let use_load = self.psi_cpu_max.is_none() || !self.psi_supported;
+        let load_bad = use_load && load > self.load_max;
+        let load_calm = !use_load || load <= self.load_min;
+        let (psi_bad, psi_calm) = self.check_pressure(&snapshot);
+        let swap_bad = self.check_swap(&snapshot);
+        let (io_bad, io_calm) = self.check_io(&snapshot);
+        let (hot, cool) = self.check_thermal(&snapshot);
+
+        self.low_memory = free_mem < self.reserved_mem || swap_bad;
+        let bad = [
+            ("memory", free_mem < self.reserved_mem),
+            ("load", load_bad),
+            ("pressure", psi_bad),
+            ("swap", swap_bad),
+            ("io", io_bad),
+            ("thermal", hot),
+        ];
+        let calm = [
+            (
+                "memory",
+                free_kib >= ((self.reserved_mem as u64) << 20) + self.next_task_mem(),
+            ),
+            ("load", load_calm),
+            ("pressure", psi_calm),
+            ("io", io_calm),
+            ("thermal", cool),
+        ];
+        let bad: Vec<&'static str> = bad
+            .iter()
+            .filter(|(_, bad)| *bad)
+            .map(|(check, _)| *check)
+            .collect();
+        let not_calm: Vec<&'static str> = calm
+            .iter()
+            .filter(|(_, calm)| !*calm)
+            .map(|(check, _)| *check)
+            .collect();
+        self.conditions = Conditions {
+            bad,
+            not_calm,
+            reserved_cores,
+            worker_cap: self.worker_cap(),
+            free_kib,
+            reserved_kib: (self.reserved_mem as u64) << 20,
+            per_task_kib: (self.per_task_mem as u64) << 20,
+            low_memory: self.low_memory,
+        };
+        let resources = self.policy.opinion(&self.conditions);
+        self.measurements.load = load;
+        self.measurements.free_kib = free_kib;
+        self.measurements.bad = resources == CirnoOpinion::Bad;
+        self.measurements.causes = match resources {
+            CirnoOpinion::Bad if !self.conditions.bad.is_empty() => self.conditions.bad.clone(),
+            CirnoOpinion::Bad => vec!["policy"],
+            CirnoOpinion::Normal if !self.conditions.not_calm.is_empty() => {
+                self.conditions.not_calm.clone()
+            }
+            CirnoOpinion::Normal => vec!["policy"],
+            CirnoOpinion::Health => Vec::new(),
+        };
+
+        if self.auto_tune {
+            self.tune(resources, reserved_cores);
+        }
+
+        if resources == CirnoOpinion::Bad {
+            return CirnoOpinion::Bad;
+        }
+
+        if reserved_cores >= self.worker_cap() {
+            self.measurements.causes.push("workers");
+            return CirnoOpinion::Normal;
+        }
+
+        if resources == CirnoOpinion::Health && !self.check_disk() {
+            self.measurements.causes.push("disk");
+            return CirnoOpinion::Normal;
+        }
+
+        resources
+    }
+
+    /// Whether every watched filesystem has `min_disk_free` GB left, warning
+    /// once each time one drops below it.
+    fn check_disk(&mut self) -> bool {
+        let min = match self.min_disk_free {
+            Some(min) => (min as u64) << 30,
+            None => return true,
+        };
+        let mut low = Vec::new();
+        for path in std::iter::once(&self.run_dir).chain(&self.disk_paths) {
+            match free_space(path) {
+                Ok(free) if free < min => {
+                    low.push(format!("{} ({} MB free)", path.display(), free >> 20))
+                }
+                Ok(_) => {}
+                Err(e) => warn!("failed to check free space of {}: {}", path.display(), e),
+            }
+        }
+        if !low.is_empty() && !self.disk_low {
+            warn!(
+                "low disk space on {}, not launching new tasks",
+                low.join(", ")
+            );
+        }
+        self.disk_low = !low.is_empty();
+        !self.disk_low
+    }
+
+    /// Index of the running task to stop when conditions are Bad, picked by
+    /// the preempt policy if one is set, else by the scheduling policy.
+    fn pick_victim(&mut self) -> usize {
+        let tasks = self.runing_tasks.iter().enumerate();
+        let picked = match self.preempt_policy {
+            Some(PreemptPolicy::Newest) => tasks.max_by_key(|(_, task)| task.start_time),
+            Some(PreemptPolicy::Oldest) => tasks.min_by_key(|(_, task)| task.start_time),
+            // among equals prefer the newest
+            Some(PreemptPolicy::LowestPriority) => {
+                tasks.rev().min_by_key(|(_, task)| task.priority)
+            }
+            Some(PreemptPolicy::BiggestMem) => tasks.max_by_key(|(_, task)| task.rss),
+            None => None,
+        };
+        if let Some((i, _)) = picked {
+            return i;
+        }
+        let victim = self.policy.victim(&self.runing_tasks, &self.conditions);
+        victim.min(self.runing_tasks.len() - 1)
+    }
+
+    /// Memory in KiB the next task to launch is expected to need: what it
+    /// reserved after an OOM kill, its recent peak from history with
+    /// `adaptive_mem`, otherwise `per_task_mem`.
+    fn next_task_mem(&self) -> u64 {
+        if let Some(reserve) = self.todo_tasks.last().and_then(|task| task.mem_reserve) {
+            return reserve;
+        }
+        let fallback = (self.per_task_mem as u64) << 20;
+        if !self.adaptive_mem {
+            return fallback;
+        }
+        let (task, history) = match (self.todo_tasks.last(), &self.history) {
+            (Some(task), Some(history)) => (task, history),
+            _ => return fallback,
+        };
+        match history.peak_rss_estimate(&task.name) {
+            Ok(Some(estimate)) => estimate,
+            _ => fallback,
+        }
+    }
+
+    /// Cores declared (`@cpus=N`, one by default) by the running tasks. A task
+    /// asking for more than `max_workers` only runs on its own and counts as
+    /// taking them all, what it asks beyond that isn't too many workers.
+    fn reserved_cores(&self) -> usize {
+        self.runing_tasks
+            .iter()
+            .map(|task| task.cpus.min(self.max_workers))
+            .sum()
+    }
+
+    /// The number of cores cirno is willing to hand out at the moment.
+    fn worker_cap(&self) -> usize {
+        if self.auto_tune {
+            self.tuned_workers
+        } else {
+            self.max_workers
+        }
+    }
+
+    /// Move the tuned worker cap one step once the machine has been Bad, or
+    /// Healthy while held back by the cap, for `TUNE_WINDOW` checks in a row.
+    fn tune(&mut self, resources: CirnoOpinion, reserved_cores: usize) {
+        let capped = reserved_cores >= self.tuned_workers;
+        match resources {
+            CirnoOpinion::Health if capped => {
+                self.tune_streak = self.tune_streak.max(0) + 1;
+            }
+            // a free slot says nothing about the cap, keep counting
+            CirnoOpinion::Health => {}
+            CirnoOpinion::Bad => {
+                self.tune_streak = self.tune_streak.min(0) - 1;
+            }
+            CirnoOpinion::Normal => self.tune_streak = 0,
+        }
+
+        let floor = self.force_task.max(1);
+        if self.tune_streak >= TUNE_WINDOW && self.tuned_workers < self.max_workers {
+            self.tuned_workers += 1;
+            self.tune_streak = 0;
+            info!("auto-tune: raising worker cap to {}", self.tuned_workers);
+        } else if self.tune_streak <= -TUNE_WINDOW && self.tuned_workers > floor {
+            self.tuned_workers -= 1;
+            self.tune_streak = 0;
+            info!("auto-tune: lowering worker cap to {}", self.tuned_workers);
+        }
+    }
+}
+
+fn api_respond(request: HttpRequest, status: u16, body: serde_json::Value) {
+    let mut body = body.to_string();
+    body.push('\n');
+    request.respond(status, "application/json", body.as_bytes());
+}
+
+/// `s` escaped for a Prometheus label value.
+fn label_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Filter;
+    use crate::task::TaskBuilder;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    /// Hands out the snapshots in order, the last one over and over.
+    struct ScriptedProbe {
+        script: VecDeque<ResourceSnapshot>,
+    }
+
+    impl ResourceProbe for ScriptedProbe {
+        fn sample(&mut self) -> ResourceSnapshot {
+            match self.script.len() {
+                0 => ResourceSnapshot::default(),
+                1 => self.script[0].clone(),
+                _ => self.script.pop_front().unwrap(),
+            }
+        }
+    }
+
+    fn snapshot(load: f64, free_gib: u64) -> ResourceSnapshot {
+        ResourceSnapshot {
+            load_one: load,
+            load_five: load,
+            available_memory: free_gib * GIB,
+            ..ResourceSnapshot::default()
+        }
+    }
+
+    fn probed(max_workers: usize, script: Vec<ResourceSnapshot>) -> Scheduler {
+        let mut scheduler = Scheduler::new(max_workers);
+        scheduler.set_probe(Box::new(ScriptedProbe {
+            script: script.into(),
+        }));
+        scheduler
+    }
+
+    /// An empty directory of its own for the run of `test`.
+    fn run_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cirno-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn opinion_follows_load_and_memory() {
+        let mut scheduler = probed(4, vec![snapshot(0.5, 16)]);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Health);
+
+        // between --load-min and --load-max
+        let mut scheduler = probed(4, vec![snapshot(1.5, 16)]);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Normal);
+        assert_eq!(scheduler.measurements.causes, vec!["load"]);
+
+        let mut scheduler = probed(4, vec![snapshot(3.0, 16)]);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Bad);
+        assert_eq!(scheduler.measurements.causes, vec!["load"]);
+    }
+
+    #[test]
+    fn opinion_keeps_room_for_the_next_task() {
+        // above --reserved-mem, but not by the memory of another task
+        let mut scheduler = probed(4, vec![snapshot(0.5, 8)]);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Normal);
+        assert_eq!(scheduler.measurements.causes, vec!["memory"]);
+
+        let mut scheduler = probed(4, vec![snapshot(0.5, 2)]);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Bad);
+        assert_eq!(scheduler.measurements.causes, vec!["memory"]);
+        assert!(scheduler.low_memory);
+    }
+
+    #[test]
+    fn opinion_is_normal_with_every_core_reserved() {
+        let mut scheduler = probed(2, vec![snapshot(0.5, 16)]);
+        scheduler
+            .runing_tasks
+            .push(TaskBuilder::new("a", "true").build());
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Health);
+        scheduler
+            .runing_tasks
+            .push(TaskBuilder::new("b", "true").build());
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Normal);
+        assert_eq!(scheduler.measurements.causes, vec!["workers"]);
+    }
+
+    #[test]
+    fn opinion_holds_until_the_dwell_time_passed() {
+        let mut scheduler = probed(4, vec![snapshot(0.5, 16), snapshot(3.0, 16)]);
+        scheduler.set_min_dwell(60);
+        assert_eq!(scheduler.cirno_check(), CirnoOpinion::Health);
+        assert_eq!(scheduler.cirno_check(), CirnoOpinion::Normal);
+
+        // running out of memory is never held back
+        let mut scheduler = probed(4, vec![snapshot(0.5, 16), snapshot(0.5, 2)]);
+        scheduler.set_min_dwell(60);
+        assert_eq!(scheduler.cirno_check(), CirnoOpinion::Health);
+        assert_eq!(scheduler.cirno_check(), CirnoOpinion::Bad);
+    }
+
+    #[test]
+    fn bad_opinion_preempts_a_task_that_runs_again_later() {
+        let dir = run_dir("preempt");
+        let healthy = snapshot(0.5, 16);
+        let mut scheduler = probed(
+            2,
+            vec![healthy.clone(), healthy.clone(), snapshot(3.0, 16), healthy],
+        );
+        scheduler.set_run_dir(dir.clone(), dir.clone());
+        scheduler.set_sleep_duration(1);
+        scheduler.set_probe_interval(Duration::from_millis(50));
+        for name in ["first", "second"] {
+            scheduler.submit(TaskBuilder::new(name, "sleep").arg("1").build());
+        }
+        scheduler.do_it();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(scheduler.outcomes.get("preempted"), Some(&1));
+        assert_eq!(scheduler.outcomes.get("finished"), Some(&2));
+        assert!(scheduler.todo_tasks.is_empty());
+        assert!(scheduler.runing_tasks.is_empty());
+    }
+
+    #[test]
+    fn opinion_counts_an_oversized_task_as_every_core() {
+        let mut scheduler = probed(2, vec![snapshot(0.5, 16)]);
+        let task = TaskBuilder::new("big", "true")
+            .option("cpus", "8")
+            .unwrap()
+            .build();
+        scheduler.runing_tasks.push(task);
+        assert_eq!(scheduler.raw_opinion(), CirnoOpinion::Normal);
+    }
+
+    #[test]
+    fn a_failed_spawn_leaves_the_previous_attempt_to_the_pool() {
+        let dir = run_dir("respawn");
+        let mut scheduler = probed(1, vec![snapshot(0.5, 16)]);
+        scheduler.set_run_dir(dir.clone(), dir.clone());
+        scheduler.set_sleep_duration(1);
+        // an attempt that ignores SIGTERM, stopping it takes the whole grace
+        let ready = dir.join("ready");
+        let script = format!("trap '' TERM; touch {}; exec sleep 10", ready.display());
+        let mut task = TaskBuilder::new("stubborn", "sh")
+            .args(["-c", script.as_str()])
+            .option("stop_grace", "3")
+            .unwrap()
+            .option("stop_attempts", "1")
+            .unwrap()
+            .build();
+        task.spawn().unwrap();
+        while !ready.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // and the next attempt can't start
+        task.handler = std::process::Command::new(dir.join("missing"));
+        scheduler.submit(task);
+
+        let start = Instant::now();
+        scheduler.do_it();
+        let elapsed = start.elapsed();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+        assert_eq!(scheduler.outcomes.get("error"), Some(&1));
+    }
+
+    #[test]
+    fn the_session_shows_in_the_outputs_and_the_history() {
+        let dir = run_dir("session");
+        let mut scheduler = probed(1, vec![snapshot(0.5, 16)]);
+        scheduler.set_run_dir(dir.clone(), dir.clone());
+        scheduler.set_sleep_duration(1);
+        let metadata = vec![("commit".to_string(), "abc".to_string())];
+        scheduler.set_session(Some("sweep".to_string()), metadata.clone());
+        let history = History::open(&dir.join("history.db")).unwrap();
+        let session = history
+            .begin_session(Some("sweep"), unix_now(), &metadata)
+            .unwrap();
+        scheduler.set_history(history, session);
+        scheduler.submit(TaskBuilder::new("ok", "true").build());
+        scheduler.do_it();
+
+        let lines = "session: sweep\nsession commit: abc\n";
+        assert!(scheduler.summary().ends_with(" (session sweep)"));
+        assert!(scheduler.report("finished").contains(lines));
+        assert!(scheduler.status().contains(lines));
+        let json = json!({ "name": "sweep", "metadata": { "commit": "abc" } });
+        assert_eq!(scheduler.ps()["session"], json);
+        let history = History::open(&dir.join("history.db")).unwrap();
+        let runs = history.query(&Filter::default()).unwrap();
+        let sessions = history.sessions(None).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].session.as_deref(), Some("sweep"));
+        assert!(sessions[0].end_time.is_some());
+    }
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+// ends of the self-pipe the handler writes signal numbers into
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signal as u8;
+        // a full pipe already has a wakeup pending
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// A pipe with both ends non-blocking and closed on exec.
+#[cfg(target_os = "linux")]
+fn pipe() -> io::Result<[libc::c_int; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fds)
+}
+
+/// A pipe with both ends non-blocking and closed on exec, set one by one
+/// without `pipe2`. Signals are installed before any task is spawned.
+#[cfg(not(target_os = "linux"))]
+fn pipe() -> io::Result<[libc::c_int; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for fd in fds {
+        let set = unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == 0
+                && libc::fcntl(
+                    fd,
+                    libc::F_SETFL,
+                    libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+                ) == 0
+        };
+        if !set {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(fds)
+}
+
+/// Catch `signals` and queue them for `wait` instead of their default
+/// action. Children get the default handlers back when they exec.
+pub fn install(signals: &[libc::c_int]) -> io::Result<()> {
+    if READ_FD.load(Ordering::SeqCst) < 0 {
+        let fds = pipe()?;
+        READ_FD.store(fds[0], Ordering::SeqCst);
+        WRITE_FD.store(fds[1], Ordering::SeqCst);
+    }
+    for signal in signals {
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+        if unsafe { libc::sigaction(*signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Sleep up to `timeout`, returning as soon as one of the installed signals
+/// arrives with all the signals queued so far, or as soon as one of `fds` is
+/// readable. Without `install` only `fds` cut the sleep short.
+pub fn wait(timeout: Duration, fds: &[RawFd]) -> Vec<libc::c_int> {
+    let pipe = READ_FD.load(Ordering::SeqCst);
+    let mut pollfds: Vec<libc::pollfd> = fds
+        .iter()
+        .chain(Some(&pipe).filter(|fd| **fd >= 0))
+        .map(|fd| libc::pollfd {
+            fd: *fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    if pollfds.is_empty() {
+        std::thread::sleep(timeout);
+        return Vec::new();
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        let received = if pipe >= 0 { drain(pipe) } else { Vec::new() };
+        if !received.is_empty() {
+            return received;
+        }
+        let ready = pollfds[..fds.len()]
+            .iter()
+            .any(|pollfd| pollfd.revents != 0);
+        let left = deadline.saturating_duration_since(Instant::now());
+        if ready || left.is_zero() {
+            return received;
+        }
+        let ms = left.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        // EINTR just goes around again
+        unsafe {
+            libc::poll(
+                pollfds.as_mut_ptr(),
+                pollfds.len() as libc::nfds_t,
+                ms.max(1),
+            )
+        };
+    }
+}
+
+fn drain(fd: libc::c_int) -> Vec<libc::c_int> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        for signal in &buf[..n as usize] {
+            let signal = *signal as libc::c_int;
+            if !received.contains(&signal) {
+                received.push(signal);
+            }
+        }
+    }
+    received
+}
@@ -0,0 +1,215 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Runs on the remote host with the grace period, the working directory and
+/// the command as arguments. Nothing reaches the remote processes when the
+/// client is signalled, so the wrapper reads from the connection and, once
+/// it closes, stops the task's process tree: SIGTERM, then SIGKILL after
+/// the grace period. The tree is walked with `ps` as the shell of a session
+/// without a terminal has no job control to put the task in a group of its
+/// own.
+const WRAPPER: &str = r#"grace=$1
+dir=$2
+shift 2
+if [ -n "$dir" ]; then
+    cd "$dir" || exit 127
+fi
+tree() {
+    for child in $(ps -A -o pid= -o ppid= | awk -v parent="$1" '$2 == parent { print $1 }'); do
+        tree "$child"
+    done
+    echo "$1"
+}
+exec 3<&0
+"$@" </dev/null 3<&- &
+task=$!
+(
+    cat <&3 >/dev/null
+    pids=$(tree $task)
+    kill -TERM $pids
+    sleep "$grace"
+    kill -KILL $pids
+) >/dev/null 2>&1 &
+watcher=$!
+wait $task
+status=$?
+kill -KILL $(tree $watcher) 2>/dev/null
+exit $status
+"#;
+
+/// `prog` with `args` run on `host` by `ssh` instead, in the working
+/// directory of `command` and with the environment set for it, which
+/// therefore has to exist on the host as well. The output comes back
+/// through the client, and the client's stdin is a pipe left open until it
+/// exits, so killing it, by the stop sequence or otherwise, stops the remote
+/// task `grace` later at the most.
+pub(crate) fn wrap(
+    command: &Command,
+    host: &str,
+    prog: &str,
+    args: &[String],
+    grace: Duration,
+) -> Command {
+    let dir = command
+        .get_current_dir()
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    let dir = std::path::absolute(&dir)
+        .unwrap_or(dir)
+        .to_string_lossy()
+        .into_owned();
+    let mut remote = Vec::new();
+    let envs: Vec<String> = command
+        .get_envs()
+        .filter(|(key, _)| *key != "CUDA_VISIBLE_DEVICES" && *key != "TRACEPARENT")
+        .filter_map(|(key, value)| {
+            Some(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                value?.to_string_lossy()
+            ))
+        })
+        .collect();
+    if !envs.is_empty() {
+        remote.push("env".to_string());
+        remote.extend(envs);
+    }
+    remote.extend([
+        "sh".to_string(),
+        "-c".to_string(),
+        WRAPPER.to_string(),
+        "cirno".to_string(),
+    ]);
+    remote.push(grace.as_secs().max(1).to_string());
+    remote.push(dir);
+    remote.push(prog.to_string());
+    remote.extend(args.iter().cloned());
+    let remote: Vec<String> = remote.iter().map(|word| quote(word)).collect();
+
+    let mut wrapped = Command::new("ssh");
+    wrapped
+        .args(["-T", "-o", "BatchMode=yes", "--", host])
+        .arg(remote.join(" "))
+        .stdin(Stdio::piped());
+    // kept so the task can be sent somewhere else later
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// `word` as one word for the remote shell.
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// The words of the remote command line, which are all quoted.
+    fn remote_words(wrapped: &Command) -> Vec<String> {
+        let args = args(wrapped);
+        assert_eq!(args[..5], ["-T", "-o", "BatchMode=yes", "--", "gpu1"]);
+        assert_eq!(args.len(), 6);
+        let mut words = Vec::new();
+        let mut rest = args[5].as_str();
+        while let Some(start) = rest.strip_prefix('\'') {
+            // a quote is written as '\'' inside a word
+            let mut word = String::new();
+            let mut chars = start;
+            loop {
+                let end = chars.find('\'').unwrap();
+                word.push_str(&chars[..end]);
+                chars = &chars[end + 1..];
+                match chars.strip_prefix(r"\''") {
+                    Some(more) => {
+                        word.push('\'');
+                        chars = more;
+                    }
+                    None => break,
+                }
+            }
+            words.push(word);
+            rest = chars.strip_prefix(' ').unwrap_or(chars);
+        }
+        assert_eq!(rest, "");
+        words
+    }
+
+    #[test]
+    fn quote_makes_one_word_of_anything() {
+        assert_eq!(quote("plain"), "'plain'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("a b $HOME"), "'a b $HOME'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn wrap_runs_the_command_in_the_wrapper_on_the_host() {
+        let mut command = Command::new("python");
+        command
+            .env("SEED", "1")
+            .env("CUDA_VISIBLE_DEVICES", "0")
+            .env("TRACEPARENT", "00-1-2-01")
+            .current_dir("/data/work");
+        let args = ["train.py".to_string(), "it's".to_string()];
+        let wrapped = wrap(
+            &command,
+            "gpu1",
+            "python",
+            &args,
+            Duration::from_millis(1500),
+        );
+        assert_eq!(wrapped.get_program(), "ssh");
+        assert_eq!(
+            remote_words(&wrapped),
+            [
+                "env",
+                "SEED=1",
+                "sh",
+                "-c",
+                WRAPPER,
+                "cirno",
+                "1",
+                "/data/work",
+                "python",
+                "train.py",
+                "it's"
+            ]
+        );
+        // the remote host picks gpus and traces of its own, the client keeps them all
+        let mut envs: Vec<_> = wrapped
+            .get_envs()
+            .map(|(key, _)| key.to_string_lossy().into_owned())
+            .collect();
+        envs.sort();
+        assert_eq!(envs, ["CUDA_VISIBLE_DEVICES", "SEED", "TRACEPARENT"]);
+        assert_eq!(wrapped.get_current_dir(), Some(Path::new("/data/work")));
+    }
+
+    #[test]
+    fn wrap_leaves_out_env_without_variables_to_pass() {
+        let mut command = Command::new("true");
+        command.current_dir("/data/work");
+        let wrapped = wrap(&command, "gpu1", "true", &[], Duration::from_secs(10));
+        let words = remote_words(&wrapped);
+        assert_eq!(words[..2], ["sh", "-c"]);
+        assert_eq!(words[3..], ["cirno", "10", "/data/work", "true"]);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::thread::{self, JoinHandle};
+
+use crate::clock::{format_utc, unix_now};
+
+// red, green, yellow, blue, magenta and cyan
+const COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
+/// The `[name]` put in front of the streamed lines of a task, in a color of
+/// its own when stdout is a terminal.
+pub fn prefix(name: &str) -> String {
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+        return format!("[{}]", name);
+    }
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    name.hash(&mut hasher);
+    let color = COLORS[(hasher.finish() % COLORS.len() as u64) as usize];
+    format!("\x1b[{}m[{}]\x1b[0m", color, name)
+}
+
+/// Copy what `output` writes into `log` and, line by line, to stdout behind
+/// the time and `prefix`, until `output` is closed.
+pub fn tee<R: Read + Send + 'static>(output: R, log: File, prefix: String) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = copy_lines(output, log, &prefix) {
+            tracing::warn!("{} stopped streaming: {}", prefix, e);
+        }
+    })
+}
+
+fn copy_lines<R: Read>(output: R, mut log: File, prefix: &str) -> io::Result<()> {
+    let mut reader = BufReader::new(output);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        log.write_all(&line)?;
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        let time = format_utc(unix_now(), ' ', ':');
+        // one write per line, so lines of different tasks don't interleave
+        let mut out = format!("{} {} ", &time[11..], prefix).into_bytes();
+        out.extend_from_slice(&line);
+        crate::progress::print(&out)?;
+    }
+}
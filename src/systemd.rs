@@ -0,0 +1,41 @@
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Whether systemd waits for notifications, i.e. the service has
+/// `Type=notify` or `WatchdogSec=`.
+pub fn is_notify() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Send `state` such as `READY=1` or `WATCHDOG=1` to systemd, see
+/// `sd_notify(3)`. Does nothing outside of a notify service.
+pub fn notify(state: &str) -> io::Result<()> {
+    let path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    let addr = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        // abstract sockets are Linux only
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::from(io::ErrorKind::Unsupported)),
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// How often systemd expects `WATCHDOG=1`, if it watches this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
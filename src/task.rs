@@ -0,0 +1,1135 @@
+use rustix::process::{Pid, Signal};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+use crate::cgroup::{parse_size, sanitize_name, Cgroup, CgroupLimits};
+use crate::clock::parse_grace;
+use crate::container::{Container, Runtime};
+use crate::error::CirnoError;
+use crate::gpu::GpuAllocations;
+use crate::namespace::Namespaces;
+use crate::otlp::Span;
+use crate::pool::WorkerPool;
+use crate::preexec::{parse_oom_score_adj, IoPriority, PreExec, Rlimit};
+use crate::process::{
+    collect_tree, get_rss, get_state, kill_process_tree, parse_signal, stuck_processes,
+    try_wait_with_usage, ChildUsage, ProcessGroupHandle,
+};
+use crate::sandbox::Sandbox;
+use crate::ssh;
+use crate::stream;
+use crate::timeline::Lane;
+use crate::user::{lookup_group, lookup_user};
+use crate::vmstat;
+
+/// One command run by the scheduler, with its options and, while it runs,
+/// its child and what it used.
+#[derive(Debug)]
+pub struct Task {
+    pub(crate) name: String,
+    /// the task list line it came from, to submit it again
+    pub(crate) line: String,
+    prog: String,
+    args: Vec<String>,
+    pub(crate) handler: Command,
+    pub(crate) child: Option<Child>,
+    /// the processes of `child`, made as it is spawned
+    group: Option<ProcessGroupHandle>,
+    pub(crate) start_time: SystemTime,
+    pub(crate) usage: Option<ChildUsage>,
+    /// RSS of the process tree at the last sample, and the peak since spawn, in KiB
+    pub(crate) rss: u64,
+    pub(crate) peak_rss: u64,
+    /// when the task was SIGSTOPped, and how long it has spent suspended
+    suspended_at: Option<SystemTime>,
+    paused: Duration,
+    /// when the timeout signal was sent
+    pub(crate) timed_out_at: Option<Instant>,
+    /// when the stop signal was last sent on shutdown and how often
+    stopping: Option<(Instant, usize)>,
+    /// higher is more important, set with `@priority=N`
+    pub(crate) priority: i32,
+    /// cores the task uses, counted against `max_workers`
+    pub(crate) cpus: usize,
+    /// number of devices needed and free MiB needed on each of them
+    pub(crate) gpus: usize,
+    pub(crate) gpu_mem: u64,
+    pub(crate) assigned_gpus: Vec<u32>,
+    /// how the task is asked to stop, set with `@stop_signal=`, `@stop_grace=`
+    /// and `@stop_attempts=` on top of the global one
+    pub(crate) stop_policy: StopPolicy,
+    /// applied in the child before exec, installed on the first spawn
+    pub(crate) pre_exec: PreExec,
+    pre_exec_installed: bool,
+    /// the image to run the task in with `@image=`, and the runtime running
+    /// it from `@runtime=`
+    pub(crate) image: Option<String>,
+    pub(crate) runtime: Option<Runtime>,
+    container: Option<Container>,
+    /// the host to run the task on over ssh with `@host=`, and the one the
+    /// command currently goes to
+    pub(crate) host: Option<String>,
+    pub(crate) remote: Option<String>,
+    /// where each attempt gets its own cgroup, with `limits` applied to it
+    pub(crate) cgroup_parent: Option<PathBuf>,
+    cgroup: Option<Cgroup>,
+    pub(crate) limits: CgroupLimits,
+    pub(crate) attempts: usize,
+    /// system wide OOM kills when the attempt started
+    oom_kills: Option<u64>,
+    /// memory in KiB to keep free before launching, raised after an OOM kill
+    pub(crate) mem_reserve: Option<u64>,
+    pub(crate) oom_retries: usize,
+    /// the tree at the last look, and with `--subreaper` the orphans of it
+    /// that were reparented to cirno and what those used once reaped
+    pub(crate) tree: Vec<Pid>,
+    pub(crate) adopted: Vec<Pid>,
+    pub(crate) adopted_usage: ChildUsage,
+    /// with `--stream`, the prefix of the output copied to the console and
+    /// the logs the copies go to once the child is spawned
+    stream: Option<String>,
+    stdout_log: Option<fs::File>,
+    stderr_log: Option<fs::File>,
+    /// the threads making those copies, done once nothing holds the pipes
+    pub(crate) tees: Vec<JoinHandle<()>>,
+    /// the log files of the current attempt, kept under `--log-max-size`
+    pub(crate) logs: Vec<PathBuf>,
+    /// with `--otlp-endpoint`, the span from the first start until the task
+    /// leaves cirno
+    pub(crate) span: Option<Span>,
+    /// the worker slot of `trace.json` the task runs in
+    pub(crate) lane: Option<Lane>,
+    /// set with `TaskBuilder`, over the timeout and log paths of the scheduler
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) stdout_file: Option<PathBuf>,
+    pub(crate) stderr_file: Option<PathBuf>,
+}
+
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.prog == other.prog && self.args == other.args
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut args = String::new();
+        for arg in &self.args {
+            args.push_str(arg);
+            args.push(' ');
+        }
+        write!(f, "{} {} {}", self.name, self.prog, args)
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        let child = self.child.take();
+        // kill it, and reap it in the background as dropping must not wait
+        if let Some(child) = child {
+            if let Err(e) = kill_tree(Pid::from_child(&child), self.cgroup.as_ref(), Signal::Kill) {
+                warn!("task: {} failed to kill: {}", self.name, e);
+            }
+            let name = self.name.clone();
+            let container = self.container.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = reap_or_track(&name, child) {
+                    warn!("task: {} failed to drop: {}", name, e);
+                }
+                if let Some(container) = container {
+                    container.clear();
+                }
+            });
+        }
+        self.signal_adopted(Signal::Kill);
+        self.remove_cgroup();
+    }
+}
+
+/// Builds a `Task` argument by argument, for programs that have no task list
+/// line to parse.
+pub struct TaskBuilder {
+    task: Task,
+}
+
+impl TaskBuilder {
+    /// A task called `name` running the program `prog`, without arguments.
+    pub fn new(name: &str, prog: &str) -> TaskBuilder {
+        let task = Task {
+            name: name.to_string(),
+            line: String::new(),
+            prog: prog.to_string(),
+            args: Vec::new(),
+            handler: Command::new(prog),
+            child: None,
+            group: None,
+            start_time: SystemTime::now(),
+            usage: None,
+            rss: 0,
+            peak_rss: 0,
+            suspended_at: None,
+            paused: Duration::from_secs(0),
+            timed_out_at: None,
+            stopping: None,
+            priority: 0,
+            cpus: 1,
+            gpus: 0,
+            gpu_mem: 0,
+            assigned_gpus: Vec::new(),
+            stop_policy: StopPolicy::default(),
+            pre_exec: PreExec::default(),
+            pre_exec_installed: false,
+            image: None,
+            runtime: None,
+            container: None,
+            host: None,
+            remote: None,
+            cgroup_parent: None,
+            cgroup: None,
+            limits: CgroupLimits::default(),
+            attempts: 0,
+            oom_kills: None,
+            mem_reserve: None,
+            oom_retries: 0,
+            tree: Vec::new(),
+            adopted: Vec::new(),
+            adopted_usage: ChildUsage::default(),
+            stream: None,
+            tees: Vec::new(),
+            stdout_log: None,
+            stderr_log: None,
+            logs: Vec::new(),
+            timeout: None,
+            stdout_file: None,
+            stderr_file: None,
+            span: None,
+            lane: None,
+        };
+        TaskBuilder { task }
+    }
+
+    /// Append one argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> TaskBuilder {
+        let arg = arg.into();
+        self.task.handler.arg(&arg);
+        self.task.args.push(arg);
+        self
+    }
+
+    /// Append every argument of `args`.
+    pub fn args<I, S>(self, args: I) -> TaskBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        args.into_iter().fold(self, |builder, arg| builder.arg(arg))
+    }
+
+    /// Set the environment variable `key` to `value` for the task.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> TaskBuilder {
+        self.task.handler.env(key, value);
+        self
+    }
+
+    /// Run the task in `dir` instead of the working directory of cirno.
+    pub fn cwd(mut self, dir: impl AsRef<Path>) -> TaskBuilder {
+        self.task.handler.current_dir(dir);
+        self
+    }
+
+    /// How long the task may run before it gets the timeout signal, instead
+    /// of the timeout of the scheduler.
+    pub fn timeout(mut self, timeout: Duration) -> TaskBuilder {
+        self.task.timeout = Some(timeout);
+        self
+    }
+
+    /// Write stdout to `path` instead of `<name>.txtlog` in the run directory.
+    pub fn stdout_file(mut self, path: impl Into<PathBuf>) -> TaskBuilder {
+        self.task.stdout_file = Some(path.into());
+        self
+    }
+
+    /// Write stderr to `path` instead of `<name>.err` in the run directory,
+    /// when stderr goes to a file of its own.
+    pub fn stderr_file(mut self, path: impl Into<PathBuf>) -> TaskBuilder {
+        self.task.stderr_file = Some(path.into());
+        self
+    }
+
+    /// Apply one `@key=value` option, as in a task list.
+    pub fn option(mut self, key: &str, value: &str) -> Result<TaskBuilder, String> {
+        self.task.set_option(key, value)?;
+        Ok(self)
+    }
+
+    /// The task, submitted again as its command line when requeued.
+    pub fn build(mut self) -> Task {
+        if self.task.line.is_empty() {
+            self.task.line = self.task.command();
+        }
+        self.task
+    }
+}
+
+impl Task {
+    /// Start a new attempt. What is left of the last one has to be stopped
+    /// with `stop_in_background` first, this never waits for it.
+    pub(crate) fn spawn(&mut self) -> Result<(), CirnoError> {
+        if self.child.is_some() {
+            return Err(CirnoError::Stop(std::io::Error::other(
+                "it is still running",
+            )));
+        }
+
+        if !self.pre_exec_installed {
+            self.pre_exec
+                .clone()
+                .install(&mut self.handler)
+                .map_err(CirnoError::Spawn)?;
+            self.pre_exec_installed = true;
+        }
+
+        if let (Some(container), true) = (&self.container, self.attempts > 0) {
+            container.clear();
+        }
+        self.attempts += 1;
+        self.remove_cgroup();
+        // the child writes itself into cgroup.procs, so the file has to stay
+        // open until spawn returns
+        let procs =
+            self.cgroup_parent
+                .clone()
+                .and_then(|parent| match self.create_cgroup(&parent) {
+                    Ok(procs) => Some(procs),
+                    Err(e) => {
+                        warn!("task: {} failed to set up cgroup: {}", self.name, e);
+                        self.remove_cgroup();
+                        None
+                    }
+                });
+        self.pre_exec
+            .set_cgroup_procs(procs.as_ref().map(|procs| procs.as_raw_fd()));
+        let (mut p, spawned) = match self.handler.spawn() {
+            Ok(p) => (Some(p), Ok(())),
+            Err(e) => (None, Err(CirnoError::Spawn(e))),
+        };
+        self.pre_exec.set_cgroup_procs(None);
+        drop(procs);
+        // made right away, a job on Windows only holds what starts after it
+        self.group = None;
+        if let Some(child) = &mut p {
+            match ProcessGroupHandle::new(child) {
+                Ok(group) => self.group = Some(group),
+                Err(e) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(CirnoError::Spawn(e));
+                }
+            }
+        }
+        if let (Some(child), Some(prefix)) = (p.as_mut(), &self.stream) {
+            if let (Some(output), Some(log)) = (child.stdout.take(), self.stdout_log.take()) {
+                self.tees.push(stream::tee(output, log, prefix.clone()));
+            }
+            if let (Some(output), Some(log)) = (child.stderr.take(), self.stderr_log.take()) {
+                self.tees.push(stream::tee(output, log, prefix.clone()));
+            }
+        }
+        self.stdout_log = None;
+        self.stderr_log = None;
+        self.start_time = std::time::SystemTime::now();
+        self.oom_kills = vmstat::read_counter("oom_kill");
+        self.usage = None;
+        self.tree.clear();
+        self.adopted.clear();
+        self.adopted_usage = ChildUsage::default();
+        self.rss = 0;
+        self.peak_rss = 0;
+        self.suspended_at = None;
+        self.paused = Duration::from_secs(0);
+        self.timed_out_at = None;
+        self.stopping = None;
+        self.child = p;
+        spawned
+    }
+
+    /// Whether the attempt that exited with `status` ended in an OOM kill. The
+    /// cgroup knows for sure, otherwise a SIGKILL that came with a new OOM kill
+    /// in `/proc/vmstat` is taken as one.
+    pub(crate) fn was_oom_killed(&self, status: std::process::ExitStatus) -> bool {
+        if let Some(kills) = self.cgroup.as_ref().and_then(|cgroup| cgroup.oom_kills()) {
+            return kills > 0;
+        }
+        match (self.oom_kills, vmstat::read_counter("oom_kill")) {
+            (Some(before), Some(after)) => status.signal() == Some(9) && after > before,
+            _ => false,
+        }
+    }
+
+    /// Create the cgroup of this attempt and open its `cgroup.procs`.
+    fn create_cgroup(&mut self, parent: &Path) -> std::io::Result<fs::File> {
+        let name = format!("{}.{}", sanitize_name(&self.name), self.attempts);
+        let cgroup = Cgroup::create(parent, &name)?;
+        self.cgroup = Some(cgroup.clone());
+        cgroup.apply(&self.limits)?;
+        cgroup.open_procs()
+    }
+
+    fn remove_cgroup(&mut self) {
+        if let Some(cgroup) = self.cgroup.take() {
+            remove_cgroup(&self.name, &cgroup);
+        }
+    }
+
+    /// Signal the task's whole tree, so workers it started can't outlive a
+    /// timeout.
+    fn signal(&self, pid: Pid, sig: Signal) -> std::io::Result<()> {
+        kill_tree(pid, self.cgroup.as_ref(), sig)?;
+        self.signal_adopted(sig);
+        Ok(())
+    }
+
+    /// One step of the stop sequence that doesn't wait: send the stop signal
+    /// again once its grace period is over, SIGKILL after the last attempt.
+    pub(crate) fn stop_step(&mut self) -> std::io::Result<()> {
+        let group = match (&self.child, &self.group) {
+            (Some(_), Some(group)) => group,
+            _ => return Ok(()),
+        };
+        let sent = match self.stopping {
+            Some((at, sent)) if sent > self.stop_policy.attempts() => {
+                if at.elapsed() >= KILL_WAIT {
+                    if let Some(child) = self.child.take() {
+                        track(&self.name, child);
+                    }
+                    self.group = None;
+                    return Err(std::io::Error::other("did not exit after SIGKILL"));
+                }
+                return Ok(());
+            }
+            Some((at, _)) if at.elapsed() < self.stop_policy.grace() => return Ok(()),
+            Some((_, sent)) => sent,
+            None => 0,
+        };
+        if sent < self.stop_policy.attempts() {
+            group.signal(self.stop_policy.signal())?;
+            self.signal_adopted(self.stop_policy.signal());
+        } else {
+            if sent == self.stop_policy.attempts() {
+                warn!("task: {} did not stop, killing it", self.name);
+            }
+            self.kill()?;
+        }
+        self.stopping = Some((Instant::now(), sent + 1));
+        Ok(())
+    }
+
+    /// Whether the stop sequence of `stop_step` is under way, which wants to
+    /// be stepped more often than resources are checked.
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.is_some()
+    }
+
+    /// SIGKILL the task's tree and adopted processes right away.
+    pub(crate) fn kill(&mut self) -> std::io::Result<()> {
+        if let Some(child) = &self.child {
+            kill_tree(Pid::from_child(child), self.cgroup.as_ref(), Signal::Kill)?;
+            if let Some(container) = &self.container {
+                container.remove();
+            }
+        }
+        self.signal_adopted(Signal::Kill);
+        Ok(())
+    }
+
+    /// Signal the trees of the orphans adopted from this task.
+    pub(crate) fn signal_adopted(&self, sig: Signal) {
+        for pid in &self.adopted {
+            // they exit on their own as well, errors here don't matter
+            let _ = kill_process_tree(*pid, sig);
+        }
+    }
+
+    /// Hand the stop sequence over to the pool so the caller can move on at
+    /// once. The task's gpus and cgroup are released when the stop is done.
+    pub(crate) fn stop_in_background(&mut self, pool: &mut WorkerPool, gpus: &GpuAllocations) {
+        let devices = std::mem::take(&mut self.assigned_gpus);
+        let gpus = gpus.clone();
+        let cgroup = self.cgroup.take();
+        let container = self.container.clone();
+        let adopted = std::mem::take(&mut self.adopted);
+        let policy = self.stop_policy;
+        match (self.child.take(), self.group.take()) {
+            (Some(child), Some(group)) => {
+                let name = self.name.clone();
+                pool.execute(move || {
+                    let stopped = stop_child(
+                        &name,
+                        child,
+                        &group,
+                        cgroup.as_ref(),
+                        container.as_ref(),
+                        policy,
+                    );
+                    match stopped {
+                        Ok(Some(status)) => info!("task: {} stopped with status: {}", name, status),
+                        Ok(None) => info!("task: {} stopped", name),
+                        Err(e) => warn!("task: {} failed to stop: {}", name, e),
+                    }
+                    for pid in adopted {
+                        let _ = kill_process_tree(pid, Signal::Kill);
+                    }
+                    gpus.release(&devices);
+                    if let Some(cgroup) = cgroup {
+                        remove_cgroup(&name, &cgroup);
+                    }
+                });
+            }
+            _ => {
+                gpus.release(&devices);
+                if let Some(cgroup) = cgroup {
+                    remove_cgroup(&self.name, &cgroup);
+                }
+            }
+        }
+    }
+
+    /// Reap the child if it has exited, keeping its resource usage.
+    pub(crate) fn reap(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        let pid = match &self.child {
+            Some(child) => Pid::from_child(child),
+            None => return Ok(None),
+        };
+        match try_wait_with_usage(pid)? {
+            Some((status, usage)) => {
+                self.child = None;
+                self.group = None;
+                self.usage = Some(usage);
+                Ok(Some(status))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reap the child, signalling it once it ran longer than `timeout` and
+    /// stopping it if it is still there `grace` later. Nothing here waits,
+    /// the stop goes one `stop_step` per call.
+    pub(crate) fn try_wait(
+        &mut self,
+        timeout: usize,
+        signal: Signal,
+        grace: Duration,
+    ) -> std::io::Result<Option<std::process::ExitStatus>> {
+        let pid = match &self.child {
+            Some(child) => Pid::from_child(child),
+            None => return Ok(None),
+        };
+        if let Some(status) = self.reap()? {
+            return Ok(Some(status));
+        }
+
+        let elapsed = self
+            .start_time
+            .elapsed()
+            .unwrap_or(Duration::from_secs(0))
+            .saturating_sub(self.paused);
+        if elapsed.as_secs() > timeout as u64 && timeout > 0 {
+            match self.timed_out_at {
+                None => {
+                    info!("task: {} timeout", self.name);
+                    self.timed_out_at = Some(Instant::now());
+                    self.signal(pid, signal)?;
+                }
+                Some(at) if at.elapsed() >= grace => {
+                    if !self.is_stopping() {
+                        info!(
+                            "task: {} still running {:.0}s after timeout, stopping it",
+                            self.name,
+                            at.elapsed().as_secs_f64()
+                        );
+                    }
+                    self.stop_step()?;
+                }
+                Some(_) => {}
+            }
+        }
+        // just return
+        Ok(None)
+    }
+
+    /// Apply one `@key=value` option from the task list.
+    pub fn set_option(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "stop_signal" => {
+                self.stop_policy.signal = Some(parse_signal(value)?);
+            }
+            "stop_grace" => {
+                self.stop_policy.grace = Some(parse_grace(value)?);
+            }
+            "stop_attempts" => {
+                self.stop_policy.attempts = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid stop_attempts {}: {}", value, e))?,
+                );
+            }
+            "priority" => {
+                self.priority = value
+                    .parse()
+                    .map_err(|e| format!("invalid priority {}: {}", value, e))?;
+            }
+            "cpus" => {
+                self.cpus = value
+                    .parse()
+                    .map_err(|e| format!("invalid cpus {}: {}", value, e))?;
+            }
+            "gpus" => {
+                self.gpus = value
+                    .parse()
+                    .map_err(|e| format!("invalid gpus {}: {}", value, e))?;
+            }
+            "gpu_mem" => {
+                self.gpu_mem = value
+                    .parse()
+                    .map_err(|e| format!("invalid gpu_mem {}: {}", value, e))?;
+            }
+            "nice" => {
+                self.pre_exec.nice = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid nice {}: {}", value, e))?,
+                );
+            }
+            "ionice" => {
+                self.pre_exec.ionice = Some(IoPriority::parse(value)?);
+            }
+            "oom_score_adj" => {
+                self.pre_exec.oom_score_adj = Some(parse_oom_score_adj(value)?);
+            }
+            "user" => {
+                self.pre_exec.user =
+                    Some(lookup_user(value).ok_or(format!("unknown user: {}", value))?);
+            }
+            "sandbox_read" => {
+                self.pre_exec.sandbox.read = Sandbox::parse_paths(value);
+            }
+            "sandbox_write" => {
+                self.pre_exec.sandbox.write = Sandbox::parse_paths(value);
+            }
+            "sandbox_network" => {
+                self.pre_exec.sandbox.network = value
+                    .parse()
+                    .map_err(|e| format!("invalid sandbox_network {}: {}", value, e))?;
+            }
+            "image" => {
+                self.image = Some(value.to_string());
+            }
+            "host" => {
+                self.host = Some(value.to_string());
+            }
+            "runtime" => {
+                self.runtime = Some(Runtime::parse(value)?);
+            }
+            "unshare" => {
+                self.pre_exec.unshare = Some(Namespaces::parse(value)?);
+            }
+            "group" => {
+                self.pre_exec.group =
+                    Some(lookup_group(value).ok_or(format!("unknown group: {}", value))?);
+            }
+            _ if key.starts_with("rlimit_") => {
+                self.pre_exec
+                    .set_rlimit(Rlimit::parse(&key["rlimit_".len()..], value)?);
+            }
+            "mem_max" => {
+                self.limits.memory_max = Some(parse_size(value)?);
+            }
+            "mem_high" => {
+                self.limits.memory_high = Some(parse_size(value)?);
+            }
+            "cpu_max" => {
+                self.limits.cpu_max = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("invalid cpu_max {}: {}", value, e))?,
+                );
+            }
+            _ => return Err(format!("unknown task option: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Run the task in a container of its `@image=` from now on, with
+    /// `run_dir` mounted, see `Container::wrap`.
+    pub(crate) fn containerize(&mut self, run_dir: &Path) {
+        if let (Some(image), None) = (&self.image, &self.container) {
+            let container = Container::new(self.runtime.unwrap_or(Runtime::Docker), &self.name);
+            self.handler = container.wrap(&self.handler, image, run_dir, self.gpus > 0);
+            self.container = Some(container);
+        }
+    }
+
+    /// Run the task on `host` over ssh from now on, see `ssh::wrap`.
+    pub(crate) fn send_to(&mut self, host: &str) {
+        if self.remote.as_deref() == Some(host) {
+            return;
+        }
+        let grace = self.stop_policy.grace();
+        self.handler = ssh::wrap(&self.handler, host, &self.prog, &self.args, grace);
+        // the pre-exec setup went with the old command
+        self.pre_exec_installed = false;
+        self.remote = Some(host.to_string());
+    }
+
+    /// Restrict the task to the given devices through `CUDA_VISIBLE_DEVICES`.
+    pub(crate) fn assign_gpus(&mut self, devices: Vec<u32>) {
+        if devices.is_empty() {
+            self.handler.env_remove("CUDA_VISIBLE_DEVICES");
+        } else {
+            let visible: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+            self.handler.env("CUDA_VISIBLE_DEVICES", visible.join(","));
+        }
+        self.assigned_gpus = devices;
+    }
+
+    /// SIGSTOP the whole process tree, keeping it in memory.
+    pub(crate) fn suspend(&mut self) -> std::io::Result<()> {
+        if let Some(child) = &self.child {
+            // the client of the runtime can't pass SIGSTOP on
+            if let Some(container) = &self.container {
+                container.pause()?;
+            }
+            kill_tree(Pid::from_child(child), self.cgroup.as_ref(), Signal::Stop)?;
+            self.signal_adopted(Signal::Stop);
+            self.suspended_at = Some(SystemTime::now());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn resume(&mut self) -> std::io::Result<()> {
+        if let Some(child) = &self.child {
+            kill_tree(Pid::from_child(child), self.cgroup.as_ref(), Signal::Cont)?;
+            self.signal_adopted(Signal::Cont);
+            if let Some(container) = &self.container {
+                container.unpause()?;
+            }
+        }
+        if let Some(at) = self.suspended_at.take() {
+            self.paused += at.elapsed().unwrap_or(Duration::from_secs(0));
+        }
+        Ok(())
+    }
+
+    /// Sample the memory of the task's process tree, or of its cgroup which
+    /// also catches processes that left the tree.
+    pub(crate) fn sample_memory(&mut self) -> u64 {
+        self.refresh_tree();
+        if self.child.is_some() {
+            self.rss = match self
+                .cgroup
+                .as_ref()
+                .and_then(|cgroup| cgroup.memory_current())
+            {
+                Some(current) => current,
+                None => self.tree.iter().filter_map(|pid| get_rss(*pid)).sum(),
+            };
+            self.peak_rss = self.peak_rss.max(self.rss);
+        }
+        self.rss
+    }
+
+    /// Walk the process tree of the child and of the adopted orphans again.
+    /// Once the child exited its children are gone from the tree, so the
+    /// last one is kept for `track_leftovers`.
+    pub(crate) fn refresh_tree(&mut self) {
+        if let Some(child) = &self.child {
+            if get_state(Pid::from_child(child)) == Some('Z') {
+                return;
+            }
+        }
+        let mut tree: Vec<Pid> = self
+            .child
+            .as_ref()
+            .map(|child| collect_tree(Pid::from_child(child)))
+            .unwrap_or_default();
+        for pid in &self.adopted {
+            tree.extend(collect_tree(*pid));
+        }
+        self.tree = tree;
+    }
+
+    /// What the task is called in the logs and by the control commands.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How often the task was started, counting the current attempt.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// The most memory the process tree of the current attempt used, in KiB.
+    /// While waiting to be started again, that of the last attempt.
+    pub fn peak_rss(&self) -> u64 {
+        self.peak_rss
+    }
+
+    /// The memory its process tree uses at the moment, in KiB.
+    pub fn rss(&self) -> u64 {
+        self.rss
+    }
+
+    /// The memory reserved for the task after it was OOM-killed, in KiB.
+    pub fn mem_reserve(&self) -> Option<u64> {
+        self.mem_reserve
+    }
+
+    /// Its `@priority`, higher is more important.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// When the current attempt was started.
+    pub fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    /// A command running the program with the arguments, environment and
+    /// working directory of the task, without its pre-exec setup.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn plain_command(&self) -> Command {
+        let mut command = Command::new(self.handler.get_program());
+        command.args(self.handler.get_args());
+        for (key, value) in self.handler.get_envs() {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+        if let Some(dir) = self.handler.get_current_dir() {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// The program and its arguments, joined by spaces.
+    pub fn command(&self) -> String {
+        let mut command = self.prog.clone();
+        for arg in &self.args {
+            command.push(' ');
+            command.push_str(arg);
+        }
+        command
+    }
+
+    fn stdout(&mut self, pipe: Stdio) -> &mut Self {
+        self.handler.stdout(pipe);
+        self
+    }
+
+    pub(crate) fn stderr(&mut self, pipe: Stdio) -> &mut Self {
+        self.handler.stderr(pipe);
+        self
+    }
+
+    /// Copy the output to the console behind `prefix` as well as to the logs.
+    pub(crate) fn stream(&mut self, prefix: String) -> &mut Self {
+        self.stream = Some(prefix);
+        self
+    }
+
+    /// Write stdout to `filename`, and stderr as well if `merge_stderr`.
+    pub(crate) fn stdout_from_file(
+        &mut self,
+        filename: &Path,
+        merge_stderr: bool,
+        append: bool,
+    ) -> Result<&mut Self, CirnoError> {
+        let file = create_log(filename, append)?;
+        if merge_stderr {
+            // one shared offset, so neither stream overwrites the other
+            let dup = file.try_clone().map_err(|source| CirnoError::Log {
+                path: filename.to_path_buf(),
+                source,
+            })?;
+            self.stderr_to(dup);
+        }
+        self.stdout_to(file);
+        Ok(self)
+    }
+
+    pub(crate) fn stderr_from_file(
+        &mut self,
+        filename: &Path,
+        append: bool,
+    ) -> Result<&mut Self, CirnoError> {
+        let file = create_log(filename, append)?;
+        self.stderr_to(file);
+        Ok(self)
+    }
+
+    fn stdout_to(&mut self, file: fs::File) {
+        if self.stream.is_some() {
+            self.stdout_log = Some(file);
+            self.stdout(Stdio::piped());
+        } else {
+            self.stdout(Stdio::from(file));
+        }
+    }
+
+    fn stderr_to(&mut self, file: fs::File) {
+        if self.stream.is_some() {
+            self.stderr_log = Some(file);
+            self.stderr(Stdio::piped());
+        } else {
+            self.stderr(Stdio::from(file));
+        }
+    }
+}
+
+fn create_log(filename: &Path, append: bool) -> Result<fs::File, CirnoError> {
+    let open = || {
+        if let Some(p) = filename.parent() {
+            fs::create_dir_all(p)?;
+        }
+        // O_APPEND even when truncating, so a log emptied by rotation is
+        // written from its new end
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(filename)?;
+        if !append {
+            file.set_len(0)?;
+        }
+        Ok(file)
+    };
+    open().map_err(|source| CirnoError::Log {
+        path: filename.to_path_buf(),
+        source,
+    })
+}
+
+/// How long a SIGKILLed child gets to go away before it is left to a
+/// background thread.
+const KILL_WAIT: Duration = Duration::from_secs(5);
+
+/// Signal the whole tree of a task, through its cgroup when it has one.
+fn kill_tree(pid: Pid, cgroup: Option<&Cgroup>, sig: Signal) -> std::io::Result<()> {
+    match cgroup {
+        Some(cgroup) if sig == Signal::Kill => cgroup.kill(),
+        Some(cgroup) => cgroup.signal(sig),
+        None => kill_process_tree(pid, sig),
+    }
+}
+
+fn stop_child(
+    name: &str,
+    mut child: Child,
+    group: &ProcessGroupHandle,
+    cgroup: Option<&Cgroup>,
+    container: Option<&Container>,
+    policy: StopPolicy,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let stautus = child.try_wait()?;
+    match stautus {
+        Some(status) => Ok(Some(status)),
+        None => {
+            // ask a few times, waiting a little after each
+            for _ in 0..policy.attempts() {
+                group.signal(policy.signal())?;
+                let deadline = Instant::now() + policy.grace();
+                while Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(100).min(policy.grace()));
+                    if let Some(status) = child.try_wait()? {
+                        return Ok(Some(status));
+                    }
+                }
+            }
+            // kill it
+            kill_tree(Pid::from_child(&child), cgroup, Signal::Kill)?;
+            if let Some(container) = container {
+                container.remove();
+            }
+            // wait for free
+            reap_or_track(name, child)
+        }
+    }
+}
+
+/// Remove the cgroup of an attempt. What is still in it is killed first and
+/// keeps it busy until it is gone, which can take long for processes stuck in
+/// uninterruptible sleep, so then it is removed by a background thread.
+fn remove_cgroup(name: &str, cgroup: &Cgroup) {
+    if cgroup.procs().is_empty() {
+        return remove_empty_cgroup(name, cgroup);
+    }
+    if let Err(e) = cgroup.kill() {
+        warn!(
+            "task: {} failed to kill what is left in its cgroup: {}",
+            name, e
+        );
+    }
+    let name = name.to_string();
+    let cgroup = cgroup.clone();
+    std::thread::spawn(move || {
+        while !cgroup.procs().is_empty() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        remove_empty_cgroup(&name, &cgroup);
+    });
+}
+
+fn remove_empty_cgroup(name: &str, cgroup: &Cgroup) {
+    if let Err(e) = cgroup.remove() {
+        warn!(
+            "task: {} failed to remove cgroup {}: {}",
+            name,
+            cgroup.path().display(),
+            e
+        );
+    }
+}
+
+/// Wait a little for a killed child to go away.
+///
+/// Processes stuck in uninterruptible sleep survive SIGKILL until the kernel
+/// releases them, so instead of blocking forever the child is handed to a
+/// background thread that reaps it whenever it finally exits.
+fn reap_or_track(
+    name: &str,
+    mut child: Child,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let deadline = Instant::now() + KILL_WAIT;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    track(name, child);
+    Ok(None)
+}
+
+/// Reap a child that survived SIGKILL in a background thread, whenever it
+/// finally exits.
+fn track(name: &str, mut child: Child) {
+    let stuck = stuck_processes(Pid::from_child(&child));
+    let pids: Vec<String> = stuck
+        .iter()
+        .map(|p| p.as_raw_nonzero().to_string())
+        .collect();
+    warn!(
+        "task: {} survived SIGKILL, processes in uninterruptible sleep: [{}], tracking it in the background",
+        name,
+        pids.join(", ")
+    );
+    let name = name.to_string();
+    std::thread::spawn(move || match child.wait() {
+        Ok(status) => info!("task: {} finally exited with status: {}", name, status),
+        Err(e) => warn!("task: {} failed to wait: {}", name, e),
+    });
+}
+
+/// How a task is asked to stop before it gets SIGKILLed: `signal` is sent
+/// `attempts` times, each followed by `grace` to exit. Unset fields fall back
+/// to the global policy, then to three SIGTERMs a second apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StopPolicy {
+    pub signal: Option<Signal>,
+    pub grace: Option<Duration>,
+    pub attempts: Option<usize>,
+}
+
+impl StopPolicy {
+    pub(crate) fn or(self, other: StopPolicy) -> StopPolicy {
+        StopPolicy {
+            signal: self.signal.or(other.signal),
+            grace: self.grace.or(other.grace),
+            attempts: self.attempts.or(other.attempts),
+        }
+    }
+
+    pub(crate) fn signal(&self) -> Signal {
+        self.signal.unwrap_or(Signal::Term)
+    }
+
+    pub(crate) fn grace(&self) -> Duration {
+        self.grace.unwrap_or(Duration::from_secs(1))
+    }
+
+    fn attempts(&self) -> usize {
+        self.attempts.unwrap_or(3)
+    }
+}
+
+pub(crate) fn count_lines(contents: &str) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for line in task_lines(contents) {
+        *counts.entry(line.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// The lines of a task list, one task each.
+pub fn task_lines(contents: &str) -> Vec<&str> {
+    let contents = contents.trim();
+    if contents.is_empty() {
+        return Vec::new();
+    }
+    contents.split("\n").collect()
+}
+
+/// Parse one line of a task list, `@key=value` options followed by the
+/// command, into a task named after the last word of the command.
+pub fn parse_task_line(line: &str) -> Result<Task, CirnoError> {
+    // leading `@key=value` tokens are task options, the rest is the command
+    let mut options = Vec::new();
+    let mut command = line.trim_start();
+    while let Some(option) = command.strip_prefix('@') {
+        let (token, rest) = option
+            .split_once(char::is_whitespace)
+            .unwrap_or((option, ""));
+        let (key, value) = token.split_once('=').ok_or_else(|| {
+            CirnoError::TaskLine(format!("Invalid task option `@{}` in: {}", token, line))
+        })?;
+        options.push((key, value));
+        command = rest.trim_start();
+    }
+    let name: &str = command
+        .split_whitespace()
+        .last()
+        .ok_or_else(|| CirnoError::TaskLine(format!("Missing command in: {}", line)))?;
+    let mut words = command.split_whitespace();
+    let prog = words.next().unwrap_or(name);
+    let mut builder = TaskBuilder::new(name, prog).args(words);
+    builder.task.line = line.trim().to_string();
+    for (key, value) in options {
+        builder = builder
+            .option(key, value)
+            .map_err(|e| CirnoError::TaskLine(format!("Invalid task line `{}`: {}", line, e)))?;
+    }
+    Ok(builder.build())
+}
+
+/// The tasks of a task list, or the error of the first line that does not
+/// parse.
+pub fn gen_tasks(contents: &str) -> Result<Vec<Task>, CirnoError> {
+    let mut task_list = Vec::new();
+    for line in task_lines(contents) {
+        let task = parse_task_line(line)?;
+        debug!("generate task from: {line}");
+        task_list.push(task);
+    }
+
+    Ok(task_list)
+}
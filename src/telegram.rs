@@ -0,0 +1,119 @@
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::control;
+
+/// How long a `getUpdates` call waits for a message.
+const POLL_SECONDS: u64 = 30;
+
+/// The control commands the bot passes on, with `/` in front.
+const COMMANDS: [&str; 6] = ["status", "pause", "resume", "drain", "cancel", "explain"];
+
+const HELP: &str = "/status, /explain, /pause, /resume, /drain, /cancel <task>";
+
+/// A Telegram bot taking control commands from the chats in `chats` and
+/// sending them to the control socket at `socket`, replying with what
+/// cirno answered. Messages from other chats are ignored.
+pub fn spawn(token: String, chats: Vec<i64>, socket: PathBuf) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut offset = 0;
+        loop {
+            let updates = match call(
+                &token,
+                "getUpdates",
+                &json!({ "offset": offset, "timeout": POLL_SECONDS }),
+            ) {
+                Ok(updates) => updates,
+                Err(e) => {
+                    tracing::warn!("telegram: getUpdates failed: {}", e);
+                    thread::sleep(Duration::from_secs(10));
+                    continue;
+                }
+            };
+            for update in updates.as_array().into_iter().flatten() {
+                if let Some(id) = update["update_id"].as_i64() {
+                    offset = id + 1;
+                }
+                let message = &update["message"];
+                let (chat, text) = match (message["chat"]["id"].as_i64(), message["text"].as_str())
+                {
+                    (Some(chat), Some(text)) => (chat, text),
+                    _ => continue,
+                };
+                if !chats.contains(&chat) {
+                    tracing::warn!("telegram: ignoring a message from chat {}", chat);
+                    continue;
+                }
+                let reply = answer(text, &socket);
+                if let Err(e) = call(
+                    &token,
+                    "sendMessage",
+                    &json!({ "chat_id": chat, "text": reply }),
+                ) {
+                    tracing::warn!("telegram: sendMessage failed: {}", e);
+                }
+            }
+        }
+    })
+}
+
+/// What to reply to the message `text`.
+fn answer(text: &str, socket: &Path) -> String {
+    let line = text.trim().trim_start_matches('/');
+    let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    // `/status@cirno_bot` in group chats
+    let command = command.split('@').next().unwrap_or(command);
+    if !COMMANDS.contains(&command) {
+        return HELP.to_string();
+    }
+    match control::send(socket, format!("{} {}", command, args.trim()).trim()) {
+        Ok(reply) if reply.trim().is_empty() => "ok".to_string(),
+        Ok(reply) => reply,
+        Err(e) => format!("error: failed to reach cirno: {}", e),
+    }
+}
+
+/// Call the bot API `method` with `params`, returning its `result`. The
+/// token is handed to `curl` on stdin, out of sight of `ps`.
+fn call(token: &str, method: &str, params: &Value) -> io::Result<Value> {
+    let config = format!(
+        "url = \"https://api.telegram.org/bot{}/{}\"\nheader = \"Content-Type: application/json\"\ndata = \"{}\"\n",
+        quote(token),
+        method,
+        quote(&params.to_string())
+    );
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--max-time"])
+        .arg((POLL_SECONDS + 10).to_string())
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        stdin.write_all(config.as_bytes())?;
+    }
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("curl failed: {}", stderr.trim())));
+    }
+    let reply: Value = serde_json::from_slice(&output.stdout).map_err(io::Error::other)?;
+    if reply["ok"] != true {
+        let description = reply["description"].as_str().unwrap_or("not ok");
+        return Err(io::Error::other(format!(
+            "telegram answered: {}",
+            description
+        )));
+    }
+    Ok(reply["result"].clone())
+}
+
+/// `s` escaped for a double-quoted value of a curl config file.
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,39 @@
+/// Highest temperature in °C over all thermal zones in `/sys/class/thermal`.
+pub fn max_temperature() -> Option<f64> {
+    let entries = std::fs::read_dir("/sys/class/thermal").ok()?;
+    let mut max: Option<f64> = None;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if !name.to_string_lossy().starts_with("thermal_zone") {
+            continue;
+        }
+        let temp = std::fs::read_to_string(entry.path().join("temp"))
+            .ok()
+            .and_then(|t| t.trim().parse::<i64>().ok());
+        // millidegrees Celsius
+        if let Some(temp) = temp {
+            let temp = temp as f64 / 1000.0;
+            max = Some(max.map_or(temp, |m| m.max(temp)));
+        }
+    }
+    max
+}
+
+/// Summed thermal throttle events of all cpus, where the platform reports them
+/// (`/sys/devices/system/cpu/cpu*/thermal_throttle`).
+pub fn throttle_count() -> Option<u64> {
+    let entries = std::fs::read_dir("/sys/devices/system/cpu").ok()?;
+    let mut total = None;
+    for entry in entries.flatten() {
+        let dir = entry.path().join("thermal_throttle");
+        for file in ["core_throttle_count", "package_throttle_count"] {
+            if let Some(count) = std::fs::read_to_string(dir.join(file))
+                .ok()
+                .and_then(|c| c.trim().parse::<u64>().ok())
+            {
+                total = Some(total.unwrap_or(0) + count);
+            }
+        }
+    }
+    total
+}
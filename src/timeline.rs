@@ -0,0 +1,114 @@
+use serde_json::{json, Value};
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// When the tasks of a run were running, in the Chrome trace event format
+/// that `chrome://tracing` and Perfetto open. Every worker slot gets a lane
+/// of its own; a task takes the lowest free slot while it runs.
+#[derive(Debug)]
+pub struct Timeline {
+    start: Instant,
+    /// which slots are taken
+    slots: Vec<bool>,
+    events: Vec<Value>,
+}
+
+/// A slot taken by a running task, and since when in microseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Lane {
+    slot: usize,
+    since: u64,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline {
+            start: Instant::now(),
+            slots: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    /// Take the lowest free slot for a task starting or resuming now.
+    pub fn begin(&mut self) -> Lane {
+        let slot = match self.slots.iter().position(|taken| !taken) {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(false);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[slot] = true;
+        Lane {
+            slot,
+            since: self.now(),
+        }
+    }
+
+    /// Free `lane` and add the time the task `name` spent in it, which ended
+    /// now with `outcome`, e.g. `suspended` or `finished`.
+    pub fn end(&mut self, lane: Lane, name: &str, outcome: &str, args: Value) {
+        let now = self.now();
+        self.slots[lane.slot] = false;
+        let mut args = args;
+        if let Value::Object(args) = &mut args {
+            args.insert("outcome".to_string(), Value::from(outcome));
+        }
+        self.events.push(json!({
+            "name": name,
+            "cat": outcome,
+            "ph": "X",
+            "ts": lane.since,
+            "dur": now - lane.since,
+            "pid": 1,
+            "tid": lane.slot,
+            "args": args,
+        }));
+    }
+
+    /// Mark `event` of the task `name` in `lane`, e.g. a timeout.
+    pub fn mark(&mut self, lane: Lane, name: &str, event: &str) {
+        self.events.push(json!({
+            "name": format!("{} {}", name, event),
+            "cat": event,
+            "ph": "i",
+            "s": "t",
+            "ts": self.now(),
+            "pid": 1,
+            "tid": lane.slot,
+        }));
+    }
+
+    /// Write the timeline to `path`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut events = vec![json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": 1,
+            "args": { "name": "cirno" },
+        })];
+        for slot in 0..self.slots.len() {
+            events.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": slot,
+                "args": { "name": format!("slot {}", slot) },
+            }));
+        }
+        events.extend(self.events.iter().cloned());
+        let trace = json!({ "traceEvents": events, "displayTimeUnit": "ms" });
+        std::fs::write(path, trace.to_string())
+    }
+}
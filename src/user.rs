@@ -0,0 +1,153 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Resolve a user name or a numeric uid.
+pub fn lookup_user(user: &str) -> Option<u32> {
+    if let Ok(uid) = user.parse::<u32>() {
+        return Some(uid);
+    }
+    let name = CString::new(user).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    Some(unsafe { (*passwd).pw_uid })
+}
+
+/// Resolve a group name or a numeric gid.
+pub fn lookup_group(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Some(gid);
+    }
+    let name = CString::new(group).ok()?;
+    let grp = unsafe { libc::getgrnam(name.as_ptr()) };
+    if grp.is_null() {
+        return None;
+    }
+    Some(unsafe { (*grp).gr_gid })
+}
+
+/// Primary group of a uid, used when only a user is given.
+pub fn primary_group(uid: u32) -> Option<u32> {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return None;
+    }
+    Some(unsafe { (*passwd).pw_gid })
+}
+
+/// Groups of the user with `uid` from the group database, starting with
+/// `gid`, as `initgroups(3)` would set them.
+pub fn supplementary_groups(uid: u32, gid: u32) -> Vec<u32> {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return vec![gid];
+    }
+    let name = unsafe { (*passwd).pw_name };
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let ret = unsafe {
+            libc::getgrouplist(name, gid as _, groups.as_mut_ptr() as *mut _, &mut count)
+        };
+        if ret >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        // not every libc reports how many there are, so grow until they fit
+        if groups.len() >= 1 << 16 {
+            return vec![gid];
+        }
+        let len = (count as usize).max(groups.len() * 2);
+        groups.resize(len, 0);
+    }
+}
+
+/// Parse `user[:group]` into a uid and gid, defaulting the gid to the user's
+/// primary group.
+pub fn parse_owner(owner: &str) -> Result<(u32, u32), String> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let uid = lookup_user(user).ok_or(format!("unknown user: {}", user))?;
+    let gid = match group {
+        Some(group) => lookup_group(group).ok_or(format!("unknown group: {}", group))?,
+        None => primary_group(uid).unwrap_or(uid),
+    };
+    Ok((uid, gid))
+}
+
+/// Parse an octal file mode such as `640` or `0o750`, at most `7777`.
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    if digits.is_empty() || !digits.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return Err(format!("invalid mode {}: not an octal number", mode));
+    }
+    match u32::from_str_radix(digits, 8) {
+        Ok(bits) if bits <= 0o7777 => Ok(bits),
+        _ => Err(format!("invalid mode {}: above 7777", mode)),
+    }
+}
+
+/// Mode and ownership applied to every file cirno creates for its tasks.
+#[derive(Debug, Clone, Default)]
+pub struct FileAccess {
+    pub mode: Option<u32>,
+    pub owner: Option<(u32, u32)>,
+}
+
+impl FileAccess {
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        if let Some((uid, gid)) = self.owner {
+            std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mode_reads_octal_with_or_without_prefix() {
+        assert_eq!(parse_mode("640"), Ok(0o640));
+        assert_eq!(parse_mode("0o750"), Ok(0o750));
+        assert_eq!(parse_mode("0"), Ok(0));
+        assert_eq!(parse_mode("7777"), Ok(0o7777));
+        assert_eq!(parse_mode("0007777"), Ok(0o7777));
+    }
+
+    #[test]
+    fn parse_mode_rejects_what_isnt_a_mode() {
+        for mode in [
+            "", "0o", "10000", "77777", "8", "+640", "-1", "0o0o7", "rwx", " 640",
+        ] {
+            assert!(parse_mode(mode).is_err(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn parse_owner_takes_ids_and_names() {
+        assert_eq!(parse_owner("0:0"), Ok((0, 0)));
+        assert_eq!(parse_owner("root"), Ok((0, 0)));
+        assert_eq!(parse_owner("root:0"), Ok((0, 0)));
+        assert_eq!(parse_owner("0:54321"), Ok((0, 54321)));
+        // without a passwd entry the group defaults to the uid
+        assert_eq!(parse_owner("54321"), Ok((54321, 54321)));
+    }
+
+    #[test]
+    fn parse_owner_rejects_unknown_names() {
+        assert!(parse_owner("no-such-cirno-user").is_err());
+        assert!(parse_owner("root:no-such-cirno-group").is_err());
+        assert!(parse_owner("").is_err());
+        assert!(parse_owner("0:").is_err());
+    }
+}
@@ -0,0 +1,21 @@
+/// Read one counter from `/proc/vmstat`, e.g. `pswpin`.
+pub fn read_counter(name: &str) -> Option<u64> {
+    let contents = std::fs::read_to_string("/proc/vmstat").ok()?;
+    contents.lines().find_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        if key == name {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+pub fn page_size() -> u64 {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as u64
+    } else {
+        4096
+    }
+}
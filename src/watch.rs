@@ -0,0 +1,94 @@
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::FromRawFd;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Files written or moved into a directory, seen through inotify.
+#[derive(Debug)]
+pub struct DirWatch {
+    fd: OwnedFd,
+    dir: PathBuf,
+}
+
+// wd, mask, cookie and len, followed by len bytes of name
+const EVENT_HEADER: usize = 16;
+
+impl DirWatch {
+    #[cfg(target_os = "linux")]
+    pub fn new(dir: &Path) -> io::Result<DirWatch> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // a file shows up once its writer closed it, or moved in whole
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(DirWatch {
+            fd,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// inotify is Linux only.
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_dir: &Path) -> io::Result<DirWatch> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "watching a directory needs inotify",
+        ))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Files that appeared since the last call, without waiting. Hidden files
+    /// are skipped, so a file can be written as `.name` and renamed when done.
+    pub fn new_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                break;
+            }
+            let mut events = &buf[..n as usize];
+            while events.len() >= EVENT_HEADER {
+                let len = u32::from_ne_bytes(events[12..16].try_into().unwrap()) as usize;
+                let end = (EVENT_HEADER + len).min(events.len());
+                let name = &events[EVENT_HEADER..end];
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                if !name.is_empty() && !name.starts_with(b".") {
+                    let path = self.dir.join(OsStr::from_bytes(name));
+                    if !files.contains(&path) {
+                        files.push(path);
+                    }
+                }
+                events = &events[end..];
+            }
+        }
+        files
+    }
+}
+
+impl AsRawFd for DirWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// An http(s) URL that takes JSON posts, e.g. a Slack, Discord or Matrix
+/// incoming webhook.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+}
+
+impl Webhook {
+    /// Parse an `http://` or `https://` URL.
+    pub fn parse(s: &str) -> Result<Webhook, String> {
+        if !s.starts_with("http://") && !s.starts_with("https://") {
+            return Err(format!("expected an http or https URL, got: {}", s));
+        }
+        Ok(Webhook { url: s.to_string() })
+    }
+
+    /// Post `body` with `curl`, which knows TLS and proxies, waiting at most
+    /// 10 seconds for the answer.
+    pub fn post(&self, body: &str) -> io::Result<()> {
+        let mut curl = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", "10"])
+            .args([
+                "--header",
+                "Content-Type: application/json",
+                "--data-binary",
+                "@-",
+            ])
+            .arg(&self.url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = curl.stdin.take() {
+            stdin.write_all(body.as_bytes())?;
+        }
+        let output = curl.wait_with_output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!("curl failed: {}", stderr.trim())));
+        }
+        Ok(())
+    }
+}